@@ -0,0 +1,476 @@
+use crate::compose::Compose;
+use crate::gc::Host;
+use crate::hub::home;
+use chrono::{DateTime, Local};
+use sha2::{Digest, Sha256};
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, write, File};
+use std::io::{copy, Error, ErrorKind};
+use std::path::{absolute, Path, PathBuf};
+use std::process::Stdio;
+use toml::{Table, Value};
+
+pub const BACKUP_DIR: &str = "~/.docks/backups";
+pub const MANIFEST: &str = "manifest.toml";
+const IMAGE: &str = "alpine:3";
+
+/// The `[backup]` section: where snapshots are stored, whether the stack is stopped while its
+/// volumes are archived, and the image running tar on the host.
+#[derive(Clone, Debug)]
+pub struct Backup {
+    pub dir: PathBuf,
+    pub stop: bool,
+    pub image: String,
+}
+
+/// One volume archived in a snapshot.
+#[derive(Clone, Debug)]
+pub struct Archive {
+    pub volume: String,
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The volumes of a container archived together, described by the manifest of `dir`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub dir: PathBuf,
+    pub container: String,
+    pub host: String,
+    pub created: String,
+    pub stopped: bool,
+    pub archives: Vec<Archive>,
+}
+
+fn sha256(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    copy(&mut file, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+impl Backup {
+    /// Read `[backup]` from a config living in `root`.
+    ///
+    /// # Errors
+    ///
+    /// When `root` cannot be made absolute.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Self, Error> {
+        let root = absolute(root)?;
+        let backup = config.get("backup");
+        let field = |name: &str| backup.and_then(|b| b.get(name)).and_then(Value::as_str);
+        Ok(Self {
+            dir: home(field("dir").unwrap_or(BACKUP_DIR), root.as_path()),
+            stop: backup
+                .and_then(|b| b.get("stop"))
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            image: field("image").unwrap_or(IMAGE).to_string(),
+        })
+    }
+
+    /// Archive the named volumes `compose` declares on `host`, only the ones listed in `only`
+    /// when it is not empty, into a timestamped snapshot of `container`. With `stop` the stack
+    /// is stopped during the archive and started again.
+    ///
+    /// # Errors
+    ///
+    /// When the container has no volume on the host or an archive cannot be written.
+    pub fn snapshot(
+        &self,
+        host: &Host,
+        container: &str,
+        compose: &Compose,
+        only: &[String],
+        stop: bool,
+    ) -> Result<Snapshot, Error> {
+        let existing = host.docker(&["volume", "ls", "-q"])?;
+        let volumes = compose
+            .volumes()
+            .into_iter()
+            .filter(|volume| existing.lines().any(|line| line.eq(volume)))
+            .filter(|volume| only.is_empty() || only.contains(volume))
+            .collect::<Vec<String>>();
+        if volumes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{container} has no volume on {}", host.name),
+            ));
+        }
+        let created = Local::now();
+        let dir = self
+            .dir
+            .join(host.name.as_str())
+            .join(container)
+            .join(created.format("%Y%m%d-%H%M%S").to_string());
+        create_dir_all(&dir)?;
+        let project = host.project(container);
+        if stop {
+            host.docker(&["compose", "--project-directory", project.as_str(), "stop"])?;
+        }
+        let archives = self.archive(host, &volumes, dir.as_path());
+        if stop {
+            host.docker(&["compose", "--project-directory", project.as_str(), "start"])?;
+        }
+        let archives = archives.inspect_err(|_| {
+            let _ = remove_dir_all(&dir);
+        })?;
+        let snapshot = Snapshot {
+            dir,
+            container: container.to_string(),
+            host: host.name.clone(),
+            created: created.to_rfc3339(),
+            stopped: stop,
+            archives,
+        };
+        snapshot.save()?;
+        Ok(snapshot)
+    }
+
+    fn archive(&self, host: &Host, volumes: &[String], dir: &Path) -> Result<Vec<Archive>, Error> {
+        let mut archives: Vec<Archive> = Vec::new();
+        for volume in volumes {
+            let file = format!("{volume}.tar.gz");
+            let path = dir.join(file.as_str());
+            let mount = format!("{volume}:/volume:ro");
+            let status = host
+                .command(&[
+                    "run",
+                    "--rm",
+                    "-v",
+                    mount.as_str(),
+                    self.image.as_str(),
+                    "tar",
+                    "-czf",
+                    "-",
+                    "-C",
+                    "/volume",
+                    ".",
+                ])
+                .stdin(Stdio::null())
+                .stdout(File::create(&path)?)
+                .status()
+                .map_err(|_| Error::new(ErrorKind::NotFound, "docker or ssh not found"))?;
+            if status.success().eq(&false) {
+                let _ = remove_file(&path);
+                return Err(Error::other(format!(
+                    "cannot archive {volume} on {}",
+                    host.name
+                )));
+            }
+            archives.push(Archive {
+                volume: volume.clone(),
+                size: path.metadata()?.len(),
+                sha256: sha256(path.as_path())?,
+                file,
+            });
+        }
+        Ok(archives)
+    }
+
+    /// Every snapshot stored in the backup directory, the oldest first.
+    ///
+    /// # Errors
+    ///
+    /// When a manifest cannot be read.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, Error> {
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        let entries = |dir: &Path| -> Vec<PathBuf> {
+            read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect()
+        };
+        for host in entries(self.dir.as_path()) {
+            for container in entries(host.as_path()) {
+                for dir in entries(container.as_path()) {
+                    if dir.join(MANIFEST).is_file() {
+                        snapshots.push(Snapshot::load(dir.as_path())?);
+                    }
+                }
+            }
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created));
+        Ok(snapshots)
+    }
+
+    /// Delete the snapshots of `container` taken on `host` that `retention` does not keep, each
+    /// `(period, count)` keeping the newest snapshot of the `count` latest days, weeks or
+    /// months that have one. Returns the deleted directories.
+    ///
+    /// # Errors
+    ///
+    /// When a manifest cannot be read or a snapshot cannot be deleted.
+    pub fn retain(
+        &self,
+        host: &str,
+        container: &str,
+        retention: &[(String, usize)],
+    ) -> Result<Vec<PathBuf>, Error> {
+        if retention.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots = self
+            .snapshots()?
+            .into_iter()
+            .filter(|snapshot| snapshot.host.eq(host) && snapshot.container.eq(container))
+            .collect::<Vec<Snapshot>>();
+        snapshots.reverse();
+        let mut kept: Vec<PathBuf> = Vec::new();
+        for (period, count) in retention {
+            let format = match period.as_str() {
+                "daily" => "%Y-%m-%d",
+                "weekly" => "%G-%V",
+                _ => "%Y-%m",
+            };
+            let mut seen: Vec<String> = Vec::new();
+            for snapshot in &snapshots {
+                let Ok(created) = DateTime::parse_from_rfc3339(snapshot.created.as_str()) else {
+                    continue;
+                };
+                let key = created.format(format).to_string();
+                if seen.contains(&key) {
+                    continue;
+                }
+                if seen.len().ge(count) {
+                    break;
+                }
+                seen.push(key);
+                kept.push(snapshot.dir.clone());
+            }
+        }
+        let mut deleted: Vec<PathBuf> = Vec::new();
+        for snapshot in snapshots {
+            if kept.contains(&snapshot.dir).eq(&false) {
+                remove_dir_all(&snapshot.dir)?;
+                deleted.push(snapshot.dir);
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Replace the content of the `target` volume on `host` with `archive`, the volume is
+    /// created when missing and must not be used by a running container.
+    ///
+    /// # Errors
+    ///
+    /// When the archive does not match its manifest, the volume is in use or tar fails.
+    pub fn restore(
+        &self,
+        snapshot: &Snapshot,
+        archive: &Archive,
+        host: &Host,
+        target: &str,
+    ) -> Result<(), Error> {
+        let path = snapshot.dir.join(archive.file.as_str());
+        if sha256(path.as_path())?.ne(&archive.sha256) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not match its manifest", path.display()),
+            ));
+        }
+        let filter = format!("volume={target}");
+        let users = host.docker(&["ps", "-q", "--filter", filter.as_str()])?;
+        if users.is_empty().eq(&false) {
+            return Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!(
+                    "{target} is used by running containers on {}, stop them first",
+                    host.name
+                ),
+            ));
+        }
+        host.docker(&["volume", "create", target])?;
+        let mount = format!("{target}:/volume");
+        let status = host
+            .command(&[
+                "run",
+                "--rm",
+                "-i",
+                "-v",
+                mount.as_str(),
+                self.image.as_str(),
+                "sh",
+                "-c",
+                "find /volume -mindepth 1 -delete && tar -xzf - -C /volume",
+            ])
+            .stdin(File::open(&path)?)
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "docker or ssh not found"))?;
+        if status.success() {
+            return Ok(());
+        }
+        Err(Error::other(format!(
+            "cannot restore {} into {target} on {}",
+            archive.volume, host.name
+        )))
+    }
+}
+
+impl Snapshot {
+    /// Read the manifest of the snapshot stored in `dir`.
+    ///
+    /// # Errors
+    ///
+    /// When the manifest is missing or not valid toml.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(MANIFEST);
+        let manifest = toml::from_str::<Table>(read_to_string(&path)?.as_str())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+        let field = |table: &Table, name: &str| {
+            table
+                .get(name)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let archives = manifest
+            .get("volumes")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_table)
+            .map(|volume| Archive {
+                volume: field(volume, "name"),
+                file: field(volume, "file"),
+                size: volume
+                    .get("size")
+                    .and_then(Value::as_integer)
+                    .and_then(|size| u64::try_from(size).ok())
+                    .unwrap_or_default(),
+                sha256: field(volume, "sha256"),
+            })
+            .collect();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            container: field(&manifest, "container"),
+            host: field(&manifest, "host"),
+            created: field(&manifest, "created"),
+            stopped: manifest
+                .get("stopped")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            archives,
+        })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let mut manifest = Table::new();
+        manifest.insert("container".into(), Value::String(self.container.clone()));
+        manifest.insert("host".into(), Value::String(self.host.clone()));
+        manifest.insert("created".into(), Value::String(self.created.clone()));
+        manifest.insert("stopped".into(), Value::Boolean(self.stopped));
+        let volumes = self
+            .archives
+            .iter()
+            .map(|archive| {
+                let mut volume = Table::new();
+                volume.insert("name".into(), Value::String(archive.volume.clone()));
+                volume.insert("file".into(), Value::String(archive.file.clone()));
+                volume.insert(
+                    "size".into(),
+                    Value::Integer(i64::try_from(archive.size).unwrap_or(i64::MAX)),
+                );
+                volume.insert("sha256".into(), Value::String(archive.sha256.clone()));
+                Value::Table(volume)
+            })
+            .collect();
+        manifest.insert("volumes".into(), Value::Array(volumes));
+        let content = toml::to_string(&manifest).map_err(|e| Error::other(e.to_string()))?;
+        write(self.dir.join(MANIFEST), content)
+    }
+
+    /// The name shown when choosing a snapshot.
+    #[must_use]
+    pub fn label(&self) -> String {
+        let volumes = self
+            .archives
+            .iter()
+            .map(|archive| archive.volume.as_str())
+            .collect::<Vec<&str>>();
+        format!(
+            "{} on {} at {} ({})",
+            self.container,
+            self.host,
+            self.created,
+            volumes.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backup, MANIFEST};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::{Path, PathBuf};
+
+    fn snapshot(root: &Path, container: &str, created: &str) -> PathBuf {
+        let dir = root.join("local").join(container).join(created);
+        create_dir_all(&dir).expect("snapshot dir");
+        write(
+            dir.join(MANIFEST),
+            format!("container = \"{container}\"\nhost = \"local\"\ncreated = \"{created}\"\n"),
+        )
+        .expect("manifest");
+        dir
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_snapshot_of_each_period() {
+        let root = std::env::temp_dir().join(format!("docks-retain-{}", std::process::id()));
+        let backup = Backup {
+            dir: root.clone(),
+            stop: false,
+            image: String::from("alpine:3"),
+        };
+        let newest = snapshot(&root, "db", "2026-01-10T12:00:00+00:00");
+        let same_day = snapshot(&root, "db", "2026-01-10T08:00:00+00:00");
+        let previous_day = snapshot(&root, "db", "2026-01-09T12:00:00+00:00");
+        let same_week = snapshot(&root, "db", "2026-01-08T12:00:00+00:00");
+        let previous_week = snapshot(&root, "db", "2026-01-01T12:00:00+00:00");
+        let december = snapshot(&root, "db", "2025-12-20T12:00:00+00:00");
+        let november = snapshot(&root, "db", "2025-11-15T12:00:00+00:00");
+        let other = snapshot(&root, "web", "2025-01-01T12:00:00+00:00");
+        let retention = [
+            (String::from("daily"), 2),
+            (String::from("weekly"), 2),
+            (String::from("monthly"), 3),
+        ];
+        let mut deleted = backup.retain("local", "db", &retention).expect("retain");
+        deleted.sort();
+        let kept = [
+            &newest,
+            &previous_day,
+            &previous_week,
+            &december,
+            &november,
+            &other,
+        ]
+        .iter()
+        .all(|dir| dir.is_dir());
+        remove_dir_all(&root).expect("cleanup");
+        assert_eq!(deleted, vec![same_week, same_day]);
+        assert!(kept);
+    }
+
+    #[test]
+    fn no_retention_deletes_nothing() {
+        let root = std::env::temp_dir().join(format!("docks-keep-{}", std::process::id()));
+        let backup = Backup {
+            dir: root.clone(),
+            stop: false,
+            image: String::from("alpine:3"),
+        };
+        let first = snapshot(&root, "db", "2026-01-10T12:00:00+00:00");
+        let second = snapshot(&root, "db", "2026-01-10T08:00:00+00:00");
+        let deleted = backup.retain("local", "db", &[]).expect("retain");
+        let kept = first.is_dir() && second.is_dir();
+        remove_dir_all(&root).expect("cleanup");
+        assert!(deleted.is_empty());
+        assert!(kept);
+    }
+}
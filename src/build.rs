@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::env::temp_dir;
+use std::fs::{create_dir_all, read_to_string, remove_file, File};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// One `docker buildx build` producing every tag of an image from a single context.
+#[derive(Clone, Debug, Default)]
+pub struct Build {
+    pub context: PathBuf,
+    pub references: Vec<String>,
+    pub platforms: Vec<String>,
+    pub dockerfile: Option<PathBuf>,
+    pub target: Option<String>,
+    pub build_args: BTreeMap<String, String>,
+    pub labels: BTreeMap<String, String>,
+    pub secrets: Vec<String>,
+    pub ssh: Vec<String>,
+    pub cache_from: Vec<String>,
+    pub cache_to: Vec<String>,
+    pub push: bool,
+    pub log: Option<PathBuf>,
+    pub sbom: bool,
+    pub provenance: Option<String>,
+}
+
+pub const BUILDER: &str = "docks";
+
+/// Held while checking for and creating the builder, publish jobs building at once would
+/// otherwise all try to create it.
+static CREATING: Mutex<()> = Mutex::new(());
+
+#[must_use]
+pub fn has_builder() -> bool {
+    Command::new("docker")
+        .args(["buildx", "inspect", BUILDER])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Make sure the `docks` buildx builder exists, the default docker driver cannot build for
+/// several platforms at once.
+///
+/// # Errors
+///
+/// When docker is missing or the builder cannot be created.
+pub fn builder() -> Result<(), Error> {
+    let _creating = CREATING
+        .lock()
+        .map_err(|_| Error::other("a build thread panicked"))?;
+    if has_builder() {
+        return Ok(());
+    }
+    let created = Command::new("docker")
+        .args([
+            "buildx",
+            "create",
+            "--name",
+            BUILDER,
+            "--driver",
+            "docker-container",
+            "--bootstrap",
+        ])
+        .status()?;
+    if created.success() {
+        return Ok(());
+    }
+    Err(Error::other(format!(
+        "cannot create the {BUILDER} buildx builder"
+    )))
+}
+
+/// The `os/arch` docker reports for a `docker info` architecture, like `x86_64`.
+#[must_use]
+pub fn platform(os: &str, architecture: &str) -> String {
+    let architecture = match architecture {
+        "x86_64" | "amd64" => "amd64",
+        "aarch64" | "arm64" => "arm64",
+        "armv7l" | "armhf" => "arm/v7",
+        "armv6l" => "arm/v6",
+        "i386" | "i686" => "386",
+        other => other,
+    };
+    format!("{os}/{architecture}")
+}
+
+impl Build {
+    #[must_use]
+    pub fn new(context: PathBuf, references: Vec<String>) -> Self {
+        Self {
+            context,
+            references,
+            push: true,
+            ..Self::default()
+        }
+    }
+
+    /// Everything but the files of the context that changes the built image, secret values
+    /// excepted since they must not end in the lock file.
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        let pairs = |map: &BTreeMap<String, String>| {
+            map.iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<String>>()
+                .join("\n")
+        };
+        let mut fingerprint = format!(
+            "dockerfile={}\ntarget={}\nplatforms={}\nargs:\n{}\nlabels:\n{}\nsecrets={}\nssh={}",
+            self.dockerfile
+                .as_ref()
+                .map(|d| d.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            self.target.clone().unwrap_or_default(),
+            self.platforms.join(","),
+            pairs(&self.build_args),
+            pairs(&self.labels),
+            self.secrets
+                .iter()
+                .filter_map(|s| s.split(',').next())
+                .collect::<Vec<&str>>()
+                .join(","),
+            self.ssh.join(",")
+        );
+        if self.sbom {
+            fingerprint.push_str("\nsbom=true");
+        }
+        if let Some(mode) = &self.provenance {
+            fingerprint.push_str(format!("\nprovenance={mode}").as_str());
+        }
+        fingerprint
+    }
+
+    fn metadata(&self) -> PathBuf {
+        let name = self
+            .references
+            .first()
+            .map(|r| r.replace(['/', ':'], "-"))
+            .unwrap_or_default();
+        temp_dir().join(format!("docks-{}-{name}.json", std::process::id()))
+    }
+
+    /// Several platforms, attestations and cache exports other than inline need the `docks`
+    /// builder.
+    #[must_use]
+    pub fn needs_builder(&self) -> bool {
+        self.platforms.is_empty().eq(&false)
+            || self.sbom
+            || self.provenance.is_some()
+            || self
+                .cache_to
+                .iter()
+                .any(|cache| cache.starts_with("type=inline").eq(&false))
+    }
+
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![String::from("build")];
+        for reference in &self.references {
+            args.push(String::from("-t"));
+            args.push(reference.to_string());
+        }
+        if let Some(dockerfile) = &self.dockerfile {
+            args.push(String::from("--file"));
+            args.push(dockerfile.to_string_lossy().to_string());
+        }
+        if let Some(target) = &self.target {
+            args.push(String::from("--target"));
+            args.push(target.to_string());
+        }
+        for (key, value) in &self.build_args {
+            args.push(String::from("--build-arg"));
+            args.push(format!("{key}={value}"));
+        }
+        for (key, value) in &self.labels {
+            args.push(String::from("--label"));
+            args.push(format!("{key}={value}"));
+        }
+        for secret in &self.secrets {
+            args.push(String::from("--secret"));
+            args.push(secret.to_string());
+        }
+        for ssh in &self.ssh {
+            args.push(String::from("--ssh"));
+            args.push(ssh.to_string());
+        }
+        for cache in &self.cache_from {
+            args.push(String::from("--cache-from"));
+            args.push(cache.to_string());
+        }
+        for cache in &self.cache_to {
+            args.push(String::from("--cache-to"));
+            args.push(cache.to_string());
+        }
+        if self.sbom {
+            args.push(String::from("--sbom=true"));
+        }
+        if let Some(mode) = &self.provenance {
+            args.push(format!("--provenance=mode={mode}"));
+        }
+        if self.needs_builder() {
+            args.push(String::from("--builder"));
+            args.push(String::from(BUILDER));
+        }
+        if self.platforms.is_empty().eq(&false) {
+            args.push(String::from("--platform"));
+            args.push(self.platforms.join(","));
+        }
+        if self.push {
+            args.push(String::from("--push"));
+        } else {
+            args.push(String::from("--load"));
+        }
+        args.push(String::from("--metadata-file"));
+        args.push(self.metadata().to_string_lossy().to_string());
+        args.push(String::from("."));
+        args
+    }
+
+    /// Build and push every reference at once, all of them share the returned digest, the one
+    /// of the manifest list when several platforms are built. The output of buildx goes to the
+    /// `log` file when there is one.
+    ///
+    /// # Errors
+    ///
+    /// When docker is missing or the build fails.
+    pub fn run(&self) -> Result<String, Error> {
+        if self.references.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no tag to build"));
+        }
+        if self.needs_builder() {
+            builder()?;
+        }
+        let mut command = Command::new("docker");
+        command
+            .arg("buildx")
+            .args(self.args())
+            .current_dir(self.context.as_path())
+            .stdin(Stdio::null());
+        if let Some(log) = &self.log {
+            if let Some(parent) = log.parent() {
+                create_dir_all(parent)?;
+            }
+            let file = File::create(log)?;
+            command.stdout(file.try_clone()?).stderr(file);
+        }
+        let status = command
+            .status()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "docker not found"))?;
+        let metadata = self.metadata();
+        if status.success().eq(&false) {
+            let _ = remove_file(&metadata);
+            return Err(Error::other(format!(
+                "docker buildx failed for {}{}",
+                self.references.join(", "),
+                self.log
+                    .as_ref()
+                    .map(|log| format!(", see {}", log.display()))
+                    .unwrap_or_default()
+            )));
+        }
+        let digest = read_to_string(&metadata)
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(content.as_str()).ok())
+            .and_then(|json| {
+                json.get("containerimage.digest")
+                    .and_then(serde_json::Value::as_str)
+                    .map(String::from)
+            })
+            .unwrap_or_default();
+        let _ = remove_file(&metadata);
+        Ok(digest)
+    }
+}
+
+fn manifest(reference: &str) -> Option<serde_json::Value> {
+    let output = Command::new("docker")
+        .args(["buildx", "imagetools", "inspect", "--raw", reference])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success().eq(&false) {
+        return None;
+    }
+    serde_json::from_slice(output.stdout.as_slice()).ok()
+}
+
+/// The compressed size of a pushed image, the one of its first platform for a manifest list.
+#[must_use]
+pub fn size(reference: &str) -> Option<u64> {
+    let manifest = manifest(reference)?;
+    if let Some(manifests) = manifest
+        .get("manifests")
+        .and_then(serde_json::Value::as_array)
+    {
+        let digest = manifests
+            .iter()
+            .find(|m| {
+                m.pointer("/platform/os")
+                    .and_then(serde_json::Value::as_str)
+                    .is_some_and(|os| os.ne("unknown"))
+            })
+            .and_then(|m| m.get("digest"))
+            .and_then(serde_json::Value::as_str)?;
+        let repository = match reference.rsplit_once(':') {
+            Some((repository, tag)) if tag.contains('/').eq(&false) => repository,
+            _ => reference,
+        };
+        return size(format!("{repository}@{digest}").as_str());
+    }
+    let layers = manifest
+        .get("layers")
+        .and_then(serde_json::Value::as_array)?
+        .iter()
+        .filter_map(|layer| layer.get("size").and_then(serde_json::Value::as_u64))
+        .sum::<u64>();
+    let config = manifest
+        .pointer("/config/size")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or_default();
+    Some(layers + config)
+}
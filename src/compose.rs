@@ -0,0 +1,661 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::env::var;
+use std::fmt::{Display, Formatter};
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+pub const COMPOSE_FILE: &str = "compose.yaml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "allow" => Some(Self::Allow),
+            "warn" => Some(Self::Warn),
+            "error" | "deny" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Warn => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rules {
+    pub latest: Level,
+    pub untagged: Level,
+    pub restart: Level,
+    pub bind: Level,
+    pub interpolation: Level,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            latest: Level::Warn,
+            untagged: Level::Warn,
+            restart: Level::Warn,
+            bind: Level::Error,
+            interpolation: Level::Error,
+        }
+    }
+}
+
+impl Rules {
+    #[must_use]
+    pub fn from_config(config: &toml::Value) -> Self {
+        let mut rules = Self::default();
+        if let Some(lint) = config.get("lint").and_then(toml::Value::as_table) {
+            for (rule, level) in lint {
+                let Some(level) = level.as_str().and_then(Level::parse) else {
+                    continue;
+                };
+                match rule.as_str() {
+                    "latest" => rules.latest = level,
+                    "untagged" => rules.untagged = level,
+                    "restart" => rules.restart = level,
+                    "bind" => rules.bind = level,
+                    "interpolation" => rules.interpolation = level,
+                    _ => {}
+                }
+            }
+        }
+        rules
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub level: Level,
+    pub service: String,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.service.is_empty() {
+            write!(f, "{}: {}", self.level, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.level, self.service, self.message)
+        }
+    }
+}
+
+pub struct Compose {
+    pub path: PathBuf,
+    pub document: Value,
+    pub images: Vec<String>,
+    pub findings: Vec<Finding>,
+}
+
+impl Compose {
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.findings.iter().any(|f| f.level.eq(&Level::Error))
+    }
+
+    #[must_use]
+    pub fn has_warnings(&self) -> bool {
+        self.findings.iter().any(|f| f.level.eq(&Level::Warn))
+    }
+
+    #[must_use]
+    pub fn services(&self) -> Vec<String> {
+        self.document
+            .get("services")
+            .and_then(Value::as_mapping)
+            .map(|services| {
+                services
+                    .keys()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Services named in a `depends_on` that this compose file does not define.
+    #[must_use]
+    pub fn external_services(&self) -> Vec<String> {
+        let services = self.services();
+        let mut external: Vec<String> = Vec::new();
+        if let Some(mapping) = self.document.get("services").and_then(Value::as_mapping) {
+            for service in mapping.values() {
+                let names: Vec<String> = match service.get("depends_on") {
+                    Some(Value::Sequence(list)) => list
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect(),
+                    Some(Value::Mapping(map)) => map
+                        .keys()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for name in names {
+                    if services.contains(&name).eq(&false) && external.contains(&name).eq(&false) {
+                        external.push(name);
+                    }
+                }
+            }
+        }
+        external
+    }
+
+    /// Networks declared by this compose file, split between the ones it creates, named
+    /// like docker compose names them, and the `external` ones another project must create.
+    #[must_use]
+    pub fn networks(&self) -> (Vec<String>, Vec<String>) {
+        let mut created: Vec<String> = Vec::new();
+        let mut external: Vec<String> = Vec::new();
+        let project = self
+            .path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if let Some(networks) = self.document.get("networks").and_then(Value::as_mapping) {
+            for (key, network) in networks {
+                let key = key.as_str().unwrap_or_default();
+                let name = network.get("name").and_then(Value::as_str);
+                if network
+                    .get("external")
+                    .is_some_and(|e| e.as_bool().unwrap_or(true))
+                {
+                    external.push(name.unwrap_or(key).to_string());
+                } else {
+                    created.push(name.map_or_else(|| format!("{project}_{key}"), String::from));
+                }
+            }
+        }
+        (created, external)
+    }
+
+    /// The named volumes declared by this compose file, with the project prefix docker
+    /// compose gives them unless they are external or named.
+    #[must_use]
+    pub fn volumes(&self) -> Vec<String> {
+        let project = self
+            .path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let mut volumes: Vec<String> = Vec::new();
+        if let Some(declared) = self.document.get("volumes").and_then(Value::as_mapping) {
+            for (key, volume) in declared {
+                let key = key.as_str().unwrap_or_default();
+                let name = volume.get("name").and_then(Value::as_str);
+                let external = volume
+                    .get("external")
+                    .is_some_and(|e| e.as_bool().unwrap_or(true));
+                volumes.push(match name {
+                    Some(name) => name.to_string(),
+                    None if external => key.to_string(),
+                    None => format!("{project}_{key}"),
+                });
+            }
+        }
+        volumes
+    }
+}
+
+/// Load `compose.yaml` from `dir`, interpolate it with the process environment and the
+/// `.env` file next to it, and lint every service against `rules`.
+///
+/// `exists` decides whether a bind mount source is present on the target host, it
+/// receives the source path as written in the compose file, relative paths included.
+///
+/// # Errors
+///
+/// When the compose file cannot be read or is not valid yaml.
+pub fn lint(dir: &Path, rules: &Rules, exists: &dyn Fn(&str) -> bool) -> Result<Compose, Error> {
+    lint_with(dir, rules, exists, &BTreeMap::new())
+}
+
+/// The compose files of the containers listed under `host` in the docks.toml `config`, read
+/// from ./containers for `local` and from $DOCKS_PUBLIC_DIR, where deploy uploads them from,
+/// for the servers. Tables without an ip are not hosts and have none.
+///
+/// # Errors
+///
+/// When $DOCKS_PUBLIC_DIR is not set for a server or a compose file cannot be read.
+pub fn deployed(config: &toml::Value, host: &str) -> Result<Vec<Compose>, Error> {
+    let Some(table) = config.get(host) else {
+        return Ok(Vec::new());
+    };
+    let root = if host.eq("local") {
+        String::from("./containers")
+    } else if table.get("ip").is_some() {
+        var("DOCKS_PUBLIC_DIR")
+            .map_err(|_| Error::new(ErrorKind::NotFound, "missing DOCKS_PUBLIC_DIR"))?
+    } else {
+        return Ok(Vec::new());
+    };
+    let mut composes: Vec<Compose> = Vec::new();
+    for container in table
+        .get("containers")
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_str)
+    {
+        let dir = PathBuf::from(format!("{root}/{container}"));
+        composes.push(
+            lint(dir.as_path(), &Rules::default(), &|_| true).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("cannot read the compose file of {container} on {host}: {e}"),
+                )
+            })?,
+        );
+    }
+    Ok(composes)
+}
+
+/// The compose files of `dir` given to docker compose: compose.yaml then, when the active
+/// profile has one, its `compose.<profile>.yaml` override.
+#[must_use]
+pub fn files(dir: &Path) -> Vec<String> {
+    let mut files = vec![COMPOSE_FILE.to_string()];
+    if let Some(profile) = crate::config::profile() {
+        let name = format!("compose.{profile}.yaml");
+        if dir.join(name.as_str()).is_file() {
+            files.push(name);
+        }
+    }
+    files
+}
+
+/// Merge an override file into `base` the way docker compose does for mappings, anything
+/// else being replaced.
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Mapping(base), Value::Mapping(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// Like [`lint`], with `secrets` injected at deploy time resolving variables too.
+///
+/// # Errors
+///
+/// When the compose file cannot be read or is not valid yaml.
+pub fn lint_with(
+    dir: &Path,
+    rules: &Rules,
+    exists: &dyn Fn(&str) -> bool,
+    secrets: &BTreeMap<String, String>,
+) -> Result<Compose, Error> {
+    let path = dir.join(COMPOSE_FILE);
+    let mut raw = Value::Null;
+    for file in files(dir) {
+        let file = dir.join(file);
+        let content = read_to_string(&file)?;
+        let value: Value = serde_yaml::from_str(content.as_str())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", file.display())))?;
+        merge(&mut raw, value);
+    }
+    let mut env = dotenv(dir);
+    env.extend(secrets.iter().map(|(k, v)| (k.clone(), v.clone())));
+    let mut findings: Vec<Finding> = Vec::new();
+    let mut unresolved: Vec<String> = Vec::new();
+    let document = interpolate_value(raw, &env, &mut unresolved);
+    unresolved.sort();
+    unresolved.dedup();
+    for name in &unresolved {
+        push(
+            &mut findings,
+            rules.interpolation,
+            "",
+            format!("variable ${{{name}}} is not set in the environment or in .env"),
+        );
+    }
+    let mut images: Vec<String> = Vec::new();
+    match document.get("services") {
+        Some(Value::Mapping(services)) if services.is_empty() => push(
+            &mut findings,
+            Level::Error,
+            "",
+            String::from("services must not be empty"),
+        ),
+        Some(Value::Mapping(services)) => {
+            for (name, service) in services {
+                let name = name.as_str().unwrap_or_default();
+                lint_service(name, service, rules, exists, &mut images, &mut findings);
+            }
+        }
+        Some(_) => push(
+            &mut findings,
+            Level::Error,
+            "",
+            String::from("services must be a mapping"),
+        ),
+        None => push(
+            &mut findings,
+            Level::Error,
+            "",
+            String::from("missing the services section"),
+        ),
+    }
+    images.sort();
+    images.dedup();
+    Ok(Compose {
+        path,
+        document,
+        images,
+        findings,
+    })
+}
+
+fn push(findings: &mut Vec<Finding>, level: Level, service: &str, message: String) {
+    if level.ne(&Level::Allow) {
+        findings.push(Finding {
+            level,
+            service: service.to_string(),
+            message,
+        });
+    }
+}
+
+fn lint_service(
+    name: &str,
+    service: &Value,
+    rules: &Rules,
+    exists: &dyn Fn(&str) -> bool,
+    images: &mut Vec<String>,
+    findings: &mut Vec<Finding>,
+) {
+    let Some(service) = service.as_mapping() else {
+        push(
+            findings,
+            Level::Error,
+            name,
+            String::from("service must be a mapping"),
+        );
+        return;
+    };
+    match service.get("image") {
+        Some(Value::String(image)) => {
+            images.push(image.to_string());
+            match tag(image) {
+                Tag::Digest => {}
+                Tag::Missing => push(
+                    findings,
+                    rules.untagged,
+                    name,
+                    format!("image {image} has no tag and resolves to latest"),
+                ),
+                Tag::Named(tag) if tag.eq("latest") => push(
+                    findings,
+                    rules.latest,
+                    name,
+                    format!("image {image} uses the latest tag"),
+                ),
+                Tag::Named(_) => {}
+            }
+        }
+        Some(_) => push(
+            findings,
+            Level::Error,
+            name,
+            String::from("image must be a string"),
+        ),
+        None if service.contains_key("build") => {}
+        None => push(
+            findings,
+            Level::Error,
+            name,
+            String::from("service needs an image or a build section"),
+        ),
+    }
+    if service.get("restart").is_none() && service.get("deploy").is_none() {
+        push(
+            findings,
+            rules.restart,
+            name,
+            String::from("no restart policy"),
+        );
+    }
+    if let Some(volumes) = service.get("volumes") {
+        let Some(volumes) = volumes.as_sequence() else {
+            push(
+                findings,
+                Level::Error,
+                name,
+                String::from("volumes must be a list"),
+            );
+            return;
+        };
+        for source in volumes.iter().filter_map(bind_source) {
+            if exists(source.as_str()).eq(&false) {
+                push(
+                    findings,
+                    rules.bind,
+                    name,
+                    format!("bind mount source {source} does not exist on the target"),
+                );
+            }
+        }
+    }
+}
+
+enum Tag<'a> {
+    Missing,
+    Digest,
+    Named(&'a str),
+}
+
+fn tag(image: &str) -> Tag<'_> {
+    if image.contains('@') {
+        return Tag::Digest;
+    }
+    let name = image.rsplit('/').next().unwrap_or(image);
+    name.split_once(':')
+        .map_or(Tag::Missing, |(_, t)| Tag::Named(t))
+}
+
+#[must_use]
+pub fn bind_source(volume: &Value) -> Option<String> {
+    match volume {
+        Value::String(short) => {
+            let (source, _) = short.split_once(':')?;
+            if source.starts_with('/') || source.starts_with('.') || source.starts_with('~') {
+                return Some(source.to_string());
+            }
+            None
+        }
+        Value::Mapping(long) => {
+            if long.get("type").and_then(Value::as_str).eq(&Some("bind")) {
+                return long.get("source").and_then(Value::as_str).map(String::from);
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Read the `KEY=value` pairs of the `.env` file in `dir`, like docker compose does.
+#[must_use]
+pub fn dotenv(dir: &Path) -> HashMap<String, String> {
+    let mut env: HashMap<String, String> = HashMap::new();
+    if let Ok(content) = read_to_string(dir.join(".env")) {
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                    .unwrap_or(value);
+                env.insert(key.trim().to_string(), value.to_string());
+            }
+        }
+    }
+    env
+}
+
+fn interpolate_value(
+    value: Value,
+    env: &HashMap<String, String>,
+    missing: &mut Vec<String>,
+) -> Value {
+    match value {
+        Value::String(s) => Value::String(interpolate(s.as_str(), env, missing)),
+        Value::Sequence(items) => Value::Sequence(
+            items
+                .into_iter()
+                .map(|v| interpolate_value(v, env, missing))
+                .collect(),
+        ),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(k, v)| (k, interpolate_value(v, env, missing)))
+                .collect::<Mapping>(),
+        ),
+        other => other,
+    }
+}
+
+/// Expand `$VAR`, `${VAR}`, `${VAR:-default}` and `${VAR-default}` in `input`, the process
+/// environment wins over `env`. `$$` is an escaped dollar. Names without a value and without
+/// a default are pushed in `missing` and expand to an empty string.
+pub fn interpolate(
+    input: &str,
+    env: &HashMap<String, String>,
+    missing: &mut Vec<String>,
+) -> String {
+    let lookup = |name: &str| var(name).ok().or_else(|| env.get(name).cloned());
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.ne(&'$') {
+            output.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                output.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut expression = String::new();
+                for c in chars.by_ref() {
+                    if c.eq(&'}') {
+                        break;
+                    }
+                    expression.push(c);
+                }
+                let (name, default, empty_is_unset) =
+                    if let Some((name, default)) = expression.split_once(":-") {
+                        (name, Some(default), true)
+                    } else if let Some((name, default)) = expression.split_once('-') {
+                        (name, Some(default), false)
+                    } else {
+                        (expression.as_str(), None, false)
+                    };
+                match (lookup(name), default) {
+                    (Some(value), Some(default)) if empty_is_unset && value.is_empty() => {
+                        output.push_str(default);
+                    }
+                    (Some(value), _) => output.push_str(value.as_str()),
+                    (None, Some(default)) => output.push_str(default),
+                    (None, None) => missing.push(name.to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || c.eq(&'_') => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c.eq(&'_') {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match lookup(name.as_str()) {
+                    Some(value) => output.push_str(value.as_str()),
+                    None => missing.push(name),
+                }
+            }
+            _ => output.push('$'),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interpolate;
+    use std::collections::HashMap;
+
+    fn expand(input: &str) -> (String, Vec<String>) {
+        let env = HashMap::from([
+            (String::from("DOCKS_TEST_TAG"), String::from("1.2")),
+            (String::from("DOCKS_TEST_EMPTY"), String::new()),
+        ]);
+        let mut missing: Vec<String> = Vec::new();
+        let output = interpolate(input, &env, &mut missing);
+        (output, missing)
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        assert_eq!(expand("app:$DOCKS_TEST_TAG").0, "app:1.2");
+        assert_eq!(expand("app:${DOCKS_TEST_TAG}-slim").0, "app:1.2-slim");
+        assert_eq!(expand("${DOCKS_TEST_UNSET:-latest}").0, "latest");
+        assert_eq!(expand("${DOCKS_TEST_UNSET-latest}").0, "latest");
+    }
+
+    #[test]
+    fn empty_values_only_fall_back_with_a_colon() {
+        assert_eq!(expand("${DOCKS_TEST_EMPTY:-latest}").0, "latest");
+        assert_eq!(expand("${DOCKS_TEST_EMPTY-latest}").0, "");
+    }
+
+    #[test]
+    fn dollars_are_escaped_or_kept() {
+        assert_eq!(expand("$$HOME").0, "$HOME");
+        assert_eq!(expand("cost $5").0, "cost $5");
+        assert_eq!(expand("end $").0, "end $");
+    }
+
+    #[test]
+    fn unset_variables_are_reported() {
+        let (output, missing) = expand("${DOCKS_TEST_UNSET}:$DOCKS_TEST_OTHER");
+        assert_eq!(output, ":");
+        assert_eq!(missing, vec!["DOCKS_TEST_UNSET", "DOCKS_TEST_OTHER"]);
+    }
+}
@@ -0,0 +1,623 @@
+use crate::compose::dotenv;
+use crate::git::matches;
+use std::collections::{BTreeMap, HashMap};
+use std::env::var;
+use std::fs::{read_dir, read_to_string};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const CONFIG_FILE: &str = "docks.toml";
+pub const INCLUDE: &str = "include";
+pub const PROFILE_VAR: &str = "DOCKS_PROFILE";
+
+/// A variable without value nor default, with the file and the key using it.
+#[derive(Clone, Debug)]
+pub struct Unresolved {
+    pub file: String,
+    pub key: String,
+    pub name: String,
+}
+
+/// Whether `key` holds shell commands, run by a shell expanding their variables itself.
+fn shell(key: &str) -> bool {
+    key.starts_with("cron.jobs[") && key.ends_with("].commands")
+}
+
+/// Whether `name` can be a variable name.
+fn variable(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c.eq(&'_'))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c.eq(&'_'))
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` in `input`, the process environment winning over
+/// `env`. `$${VAR}` writes `${VAR}` as is, any other `$` is left alone. Names without a value
+/// and without a default are pushed in `missing` and expand to an empty string.
+fn braced(input: &str, env: &HashMap<String, String>, missing: &mut Vec<String>) -> String {
+    let lookup = |name: &str| var(name).ok().or_else(|| env.get(name).cloned());
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(at) = rest.find("${") {
+        let escaped = rest[..at].ends_with('$');
+        output.push_str(&rest[..at - usize::from(escaped)]);
+        let after = &rest[at + 2..];
+        let Some(end) = after.find('}') else {
+            output.push_str(&rest[at..]);
+            return output;
+        };
+        let expression = &after[..end];
+        let (name, default) = expression
+            .split_once(":-")
+            .map_or((expression, None), |(name, default)| (name, Some(default)));
+        if escaped || variable(name).eq(&false) {
+            output.push_str(&rest[at..at + end + 3]);
+        } else {
+            match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+                (Some(value), _) => output.push_str(value.as_str()),
+                (None, Some(default)) => output.push_str(default),
+                (None, None) => missing.push(name.to_string()),
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn expand(
+    value: Value,
+    key: &str,
+    env: &HashMap<String, String>,
+    file: &str,
+    unresolved: &mut Vec<Unresolved>,
+) -> Value {
+    match value {
+        Value::String(s) if shell(key) => Value::String(s),
+        Value::String(s) => {
+            let mut missing: Vec<String> = Vec::new();
+            let expanded = braced(s.as_str(), env, &mut missing);
+            unresolved.extend(missing.into_iter().map(|name| Unresolved {
+                file: file.to_string(),
+                key: key.to_string(),
+                name,
+            }));
+            Value::String(expanded)
+        }
+        Value::Array(items) if shell(key) => Value::Array(items),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(at, item)| {
+                    expand(item, format!("{key}[{at}]").as_str(), env, file, unresolved)
+                })
+                .collect(),
+        ),
+        Value::Table(table) => Value::Table(
+            table
+                .into_iter()
+                .map(|(k, v)| {
+                    let path = if key.is_empty() {
+                        k.clone()
+                    } else {
+                        format!("{key}.{k}")
+                    };
+                    let v = expand(v, path.as_str(), env, file, unresolved);
+                    (k, v)
+                })
+                .collect::<Table>(),
+        ),
+        other => other,
+    }
+}
+
+/// Parse `content`, read from `file`, and expand `${VAR}` and `${VAR:-default}` in every string
+/// but the cron job commands, with the process environment first, then `env`.
+///
+/// # Errors
+///
+/// When the content is not valid toml or a variable has no value, every unresolved variable
+/// is listed with the key using it.
+pub fn parse(content: &str, file: &str, env: &HashMap<String, String>) -> Result<Value, Error> {
+    let value = toml::from_str::<Value>(content)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{file}: {e}")))?;
+    let mut unresolved: Vec<Unresolved> = Vec::new();
+    let value = expand(value, "", env, file, &mut unresolved);
+    if unresolved.is_empty() {
+        return Ok(value);
+    }
+    let lines = unresolved
+        .iter()
+        .map(|u| format!("  {}: {} uses ${{{}}}", u.file, u.key, u.name))
+        .collect::<Vec<String>>();
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{} variables are not set in the environment or in .env :\n{}",
+            unresolved.len(),
+            lines.join("\n")
+        ),
+    ))
+}
+
+/// Read the config file at `path` and expand its variables, the `.env` file next to it
+/// supplying the ones missing from the environment.
+///
+/// # Errors
+///
+/// When the file cannot be read, is not valid toml or uses unresolved variables.
+pub fn load(path: &Path) -> Result<Value, Error> {
+    let content = read_to_string(path)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    parse(
+        content.as_str(),
+        path.display().to_string().as_str(),
+        &dotenv(directory(path)),
+    )
+}
+
+/// The directory of the config file at `path`, where its `.env` file lives.
+fn directory(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| dir.as_os_str().is_empty().eq(&false))
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Read the config file at `path` as written, without expanding its variables, with the ones
+/// neither the environment nor the `.env` file next to it set.
+///
+/// # Errors
+///
+/// When the file cannot be read or is not valid toml.
+pub fn raw(path: &Path) -> Result<(Value, Vec<Unresolved>), Error> {
+    let content = read_to_string(path)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    let file = path.display().to_string();
+    let value = toml::from_str::<Value>(content.as_str())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{file}: {e}")))?;
+    let mut unresolved: Vec<Unresolved> = Vec::new();
+    expand(
+        value.clone(),
+        "",
+        &dotenv(directory(path)),
+        file.as_str(),
+        &mut unresolved,
+    );
+    Ok((value, unresolved))
+}
+
+/// The project root, the nearest directory holding docks.toml from `start` upward.
+#[must_use]
+pub fn root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(CONFIG_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+/// The files matched by `pattern`, relative to `dir`, a `*` matching in the file name only.
+fn included(dir: &Path, pattern: &str, by: &str, edited: Edited) -> Result<Vec<PathBuf>, Error> {
+    let path = dir.join(pattern);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if name.contains('*').eq(&false) {
+        if path.is_file() || edited.iter().any(|(file, _)| same(file, path.as_path())) {
+            return Ok(vec![path]);
+        }
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} included by {by} not found", path.display()),
+        ));
+    }
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let listed = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let Ok(entries) = read_dir(listed) else {
+        return Ok(Vec::new());
+    };
+    let mut files = entries
+        .flatten()
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|file| matches(name.as_str(), file.as_str()))
+        .map(|file| parent.join(file))
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Config file contents replacing the ones on disk, by path.
+type Edited<'a> = &'a [(PathBuf, String)];
+
+/// Whether `a` and `b` are the same file, compared as written when one does not exist yet.
+fn same(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a.eq(&b),
+        _ => a.components().eq(b.components()),
+    }
+}
+
+/// Load `path` then, depth first, every file its `include` list names, each file once, the
+/// files of `edited` being read from their content there.
+fn gather(
+    path: &Path,
+    seen: &mut Vec<PathBuf>,
+    files: &mut Vec<(String, Value)>,
+    edited: Edited,
+) -> Result<(), Error> {
+    let content = edited
+        .iter()
+        .find(|(file, _)| same(file, path))
+        .map(|(_, content)| content);
+    let canonical = match (path.canonicalize(), content) {
+        (Ok(canonical), _) => canonical,
+        (Err(_), Some(_)) => path.to_path_buf(),
+        (Err(e), None) => return Err(Error::new(e.kind(), format!("{}: {e}", path.display()))),
+    };
+    if seen.contains(&canonical) {
+        return Ok(());
+    }
+    seen.push(canonical);
+    let file = path.display().to_string();
+    let mut value = match content {
+        Some(content) => parse(content, file.as_str(), &dotenv(directory(path)))?,
+        None => load(path)?,
+    };
+    let patterns = match value.as_table_mut().and_then(|table| table.remove(INCLUDE)) {
+        None => Vec::new(),
+        Some(Value::Array(patterns)) if patterns.iter().all(Value::is_str) => patterns
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect::<Vec<String>>(),
+        Some(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{file}: include must be an array of paths"),
+            ))
+        }
+    };
+    files.push((file.clone(), value));
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for pattern in patterns {
+        for included in included(dir, pattern.as_str(), file.as_str(), edited)? {
+            gather(included.as_path(), seen, files, edited)?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` and every file it includes, in the order they are merged.
+///
+/// # Errors
+///
+/// When a file cannot be loaded or an included path does not exist.
+pub fn files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<(String, Value)> = Vec::new();
+    gather(path, &mut Vec::new(), &mut files, &[])?;
+    Ok(files
+        .into_iter()
+        .map(|(file, _)| PathBuf::from(file))
+        .collect())
+}
+
+/// The file each value of a merged config comes from, by dotted key.
+pub type Origins = BTreeMap<String, String>;
+
+/// A config merged from several files.
+#[derive(Clone, Debug)]
+pub struct Layered {
+    pub value: Value,
+    pub origins: Origins,
+}
+
+/// The active profile, from `--profile` or `DOCKS_PROFILE`.
+#[must_use]
+pub fn profile() -> Option<String> {
+    var(PROFILE_VAR).ok().filter(|p| p.is_empty().eq(&false))
+}
+
+/// The overrides of `path` for `profile`, `docks.prod.toml` for `docks.toml`.
+#[must_use]
+pub fn profile_path(path: &Path, profile: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{stem}.{profile}.toml"))
+}
+
+/// The dotted key of `name` in the table at `key`, quoted when it is not a bare key.
+#[must_use]
+pub fn dotted(key: &str, name: &str) -> String {
+    let name = if name.is_empty().eq(&false)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ['-', '_'].contains(&c))
+    {
+        name.to_string()
+    } else {
+        format!("{name:?}")
+    };
+    if key.is_empty() {
+        name
+    } else {
+        format!("{key}.{name}")
+    }
+}
+
+/// Whether the key `name` holds a secret, like `password`, `token` or `smtp_password`.
+fn secret(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ["password", "passphrase", "token", "secret"]
+        .iter()
+        .any(|word| name.eq(word) || name.ends_with(format!("_{word}").as_str()))
+}
+
+fn lines(value: &Value, key: &str, origins: &Origins, width: usize, out: &mut Vec<String>) {
+    match value {
+        Value::Table(table) => {
+            for (name, value) in table {
+                let path = dotted(key, name);
+                if secret(name) && value.is_table().eq(&false) && value.ne(&Value::from("")) {
+                    out.push(format!(
+                        "{path:width$} = \"********\"  # {}",
+                        origins.get(path.as_str()).map_or("", String::as_str)
+                    ));
+                } else {
+                    lines(value, path.as_str(), origins, width, out);
+                }
+            }
+        }
+        _ => out.push(format!(
+            "{key:width$} = {value}  # {}",
+            origins.get(key).map_or("", String::as_str)
+        )),
+    }
+}
+
+fn record(value: &Value, key: &str, file: &str, origins: &mut Origins) {
+    match value {
+        Value::Table(table) => {
+            for (name, value) in table {
+                record(value, dotted(key, name).as_str(), file, origins);
+            }
+        }
+        _ => {
+            origins.insert(key.to_string(), file.to_string());
+        }
+    }
+}
+
+/// Merge `value` from `file` into `base`, tables key by key, anything else replaced.
+fn overlay(base: &mut Table, value: Table, key: &str, file: &str, origins: &mut Origins) {
+    for (name, value) in value {
+        let path = dotted(key, name.as_str());
+        match (base.get_mut(name.as_str()), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                overlay(existing, table, path.as_str(), file, origins);
+            }
+            (_, value) => {
+                origins.retain(|k, _| {
+                    k.ne(&path) && k.starts_with(format!("{path}.").as_str()).eq(&false)
+                });
+                record(&value, path.as_str(), file, origins);
+                base.insert(name, value);
+            }
+        }
+    }
+}
+
+impl Layered {
+    /// Load `path` and the files it includes, with the overrides of `profile` on top of them.
+    ///
+    /// # Errors
+    ///
+    /// When a file cannot be loaded, a key is set by several files or the profile has no
+    /// override file.
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Self, Error> {
+        Self::edited(path, profile, &[])
+    }
+
+    /// Load `path` like [`Layered::load`], the files of `edited` being read from the content
+    /// given with them instead of from disk.
+    ///
+    /// # Errors
+    ///
+    /// When a file cannot be loaded, a key is set by several files or the profile has no
+    /// override file.
+    pub fn edited(
+        path: &Path,
+        profile: Option<&str>,
+        edited: &[(PathBuf, String)],
+    ) -> Result<Self, Error> {
+        let mut files: Vec<(String, Value)> = Vec::new();
+        gather(path, &mut Vec::new(), &mut files, edited)?;
+        let mut layered = Self {
+            value: Value::Table(Table::new()),
+            origins: Origins::new(),
+        };
+        let mut duplicates: Vec<String> = Vec::new();
+        for (file, value) in files {
+            duplicates.extend(layered.include(value, file.as_str()));
+        }
+        if duplicates.is_empty().eq(&false) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} keys are defined in several files :\n{}",
+                    duplicates.len(),
+                    duplicates.join("\n")
+                ),
+            ));
+        }
+        if let Some(profile) = profile {
+            let overrides = profile_path(path, profile);
+            if overrides.is_file().eq(&false) {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "{} not found for the {profile} profile",
+                        overrides.display()
+                    ),
+                ));
+            }
+            let mut files: Vec<(String, Value)> = Vec::new();
+            gather(overrides.as_path(), &mut Vec::new(), &mut files, edited)?;
+            for (file, value) in files {
+                layered.merge(value, file.as_str());
+            }
+        }
+        Ok(layered)
+    }
+
+    /// Add the values of `value`, read from `file`, unless one is already set by another
+    /// file, the conflicting keys being returned with their files.
+    fn include(&mut self, value: Value, file: &str) -> Vec<String> {
+        let mut added = Origins::new();
+        record(&value, "", file, &mut added);
+        let duplicates = added
+            .keys()
+            .filter_map(|key| {
+                let (existing, other) = self.origins.iter().find(|(k, _)| {
+                    k.eq(&key)
+                        || k.starts_with(format!("{key}.").as_str())
+                        || key.starts_with(format!("{k}.").as_str())
+                })?;
+                Some(format!("  {existing}: {other}, {file}"))
+            })
+            .collect::<Vec<String>>();
+        if duplicates.is_empty() {
+            self.merge(value, file);
+        }
+        duplicates
+    }
+
+    /// The file setting `key` of the `table` section or, when none does, the first one
+    /// setting a value in it.
+    #[must_use]
+    pub fn file(&self, table: &str, key: &str) -> Option<PathBuf> {
+        let section = dotted("", table);
+        let prefix = format!("{section}.");
+        self.origins
+            .get(dotted(section.as_str(), key).as_str())
+            .or_else(|| {
+                self.origins
+                    .iter()
+                    .find(|(k, _)| k.starts_with(prefix.as_str()))
+                    .map(|(_, file)| file)
+            })
+            .map(PathBuf::from)
+    }
+
+    /// The file setting `key`, the one of its closest parent when it is not a value.
+    #[must_use]
+    pub fn origin(&self, key: &str) -> Option<&str> {
+        let mut key = key;
+        while key.is_empty().eq(&false) {
+            let prefix = format!("{key}.");
+            if let Some((_, file)) = self
+                .origins
+                .iter()
+                .find(|(k, _)| k.as_str().eq(key) || k.starts_with(prefix.as_str()))
+            {
+                return Some(file.as_str());
+            }
+            key = match key.rfind(['.', '[']) {
+                Some(at) => &key[..at],
+                None => "",
+            };
+        }
+        None
+    }
+
+    /// Put the values of `value`, read from `file`, over the current ones.
+    pub fn merge(&mut self, value: Value, file: &str) {
+        let Value::Table(table) = value else {
+            return;
+        };
+        if let Value::Table(base) = &mut self.value {
+            overlay(base, table, "", file, &mut self.origins);
+        }
+    }
+
+    /// Every value as `key = value`, followed by the file it comes from, secrets masked.
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let width = self
+            .origins
+            .keys()
+            .map(String::len)
+            .max()
+            .unwrap_or_default();
+        let mut out: Vec<String> = Vec::new();
+        lines(&self.value, "", &self.origins, width, &mut out);
+        out.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{braced, parse};
+    use std::collections::HashMap;
+
+    fn expand(input: &str) -> (String, Vec<String>) {
+        let env = HashMap::from([
+            (String::from("DOCKS_TEST_TAG"), String::from("1.2")),
+            (String::from("DOCKS_TEST_EMPTY"), String::new()),
+        ]);
+        let mut missing: Vec<String> = Vec::new();
+        let output = braced(input, &env, &mut missing);
+        (output, missing)
+    }
+
+    #[test]
+    fn braces_are_expanded() {
+        assert_eq!(expand("v${DOCKS_TEST_TAG}").0, "v1.2");
+        assert_eq!(expand("${DOCKS_TEST_UNSET:-2.0}").0, "2.0");
+        assert_eq!(expand("${DOCKS_TEST_EMPTY:-2.0}").0, "2.0");
+        assert_eq!(
+            expand("${DOCKS_TEST_UNSET}"),
+            (String::new(), vec![String::from("DOCKS_TEST_UNSET")])
+        );
+    }
+
+    #[test]
+    fn other_dollars_are_left_alone() {
+        assert_eq!(expand("$DOCKS_TEST_TAG $1 $$").0, "$DOCKS_TEST_TAG $1 $$");
+        assert_eq!(expand("$$HOME").0, "$$HOME");
+        assert_eq!(expand("$${DOCKS_TEST_TAG}").0, "${DOCKS_TEST_TAG}");
+        assert_eq!(
+            expand("${DOCKS_TEST_TAG-1.0} ${#}").0,
+            "${DOCKS_TEST_TAG-1.0} ${#}"
+        );
+        assert_eq!(expand("${DOCKS_TEST_TAG").0, "${DOCKS_TEST_TAG");
+        assert!(expand("$DOCKS_TEST_UNSET").1.is_empty());
+    }
+
+    #[test]
+    fn cron_commands_are_not_expanded() {
+        let crontab = r#"
+[[cron.jobs]]
+name = "loop"
+schedule = "* * * * *"
+commands = ["for f in a b; do echo $f ${f}; done"]
+log = "${DOCKS_TEST_UNSET:-/tmp}/loop.log"
+"#;
+        let value = parse(crontab, "crontab.toml", &HashMap::new()).unwrap();
+        let job = &value["cron"]["jobs"][0];
+        assert_eq!(
+            job["commands"][0].as_str(),
+            Some("for f in a b; do echo $f ${f}; done")
+        );
+        assert_eq!(job["log"].as_str(), Some("/tmp/loop.log"));
+    }
+}
@@ -0,0 +1,161 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+use std::fs::{read, read_link, read_to_string};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+pub const DOCKERIGNORE: &str = ".dockerignore";
+
+/// A `.dockerignore` line as a gitignore one: docker matches every pattern from the root of
+/// the context, so `foo.txt` only excludes the top one while gitignore would exclude any.
+fn anchored(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => ("!", pattern.trim()),
+        None => ("", line),
+    };
+    let mut pattern = pattern;
+    while let Some(rest) = pattern
+        .strip_prefix("./")
+        .or_else(|| pattern.strip_prefix('/'))
+    {
+        pattern = rest;
+    }
+    if pattern.is_empty() || pattern.eq(".") {
+        return None;
+    }
+    if pattern.starts_with("**") {
+        return Some(format!("{negated}{pattern}"));
+    }
+    Some(format!("{negated}/{pattern}"))
+}
+
+fn dockerignore(dir: &Path) -> Result<Gitignore, Error> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let path = dir.join(DOCKERIGNORE);
+    if path.is_file() {
+        for line in read_to_string(&path)?.lines().filter_map(anchored) {
+            builder
+                .add_line(Some(path.clone()), line.as_str())
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Hash the build context in `dir` like docker sends it to the builder: every file not
+/// excluded by the `.dockerignore` of the context, plus the `dockerfile` even when ignored.
+///
+/// # Errors
+///
+/// When the context or its `.dockerignore` cannot be read.
+pub fn hash(dir: &Path, dockerfile: &Path) -> Result<String, Error> {
+    if dir.is_dir().eq(&false) {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not a directory", dir.display()),
+        ));
+    }
+    let ignore = dockerignore(dir)?;
+    let root = dir.to_path_buf();
+    let walk = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .hidden(false)
+        .sort_by_file_name(std::ffi::OsStr::cmp)
+        .filter_entry(move |e| {
+            let relative = e.path().strip_prefix(&root).unwrap_or(e.path());
+            relative.as_os_str().is_empty()
+                || ignore
+                    .matched_path_or_any_parents(relative, e.path().is_dir())
+                    .is_ignore()
+                    .eq(&false)
+        })
+        .build();
+    let mut hasher = Sha256::new();
+    for entry in walk {
+        let entry = entry.map_err(|e| Error::other(e.to_string()))?;
+        let Some(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        file(&mut hasher, relative, entry.path())?;
+    }
+    if dockerfile.is_file() {
+        file(&mut hasher, Path::new("Dockerfile"), dockerfile)?;
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn file(hasher: &mut Sha256, relative: &Path, path: &Path) -> Result<(), Error> {
+    hasher.update(relative.to_string_lossy().as_bytes());
+    hasher.update([0]);
+    if path.is_symlink() {
+        hasher.update(read_link(path)?.to_string_lossy().as_bytes());
+    } else {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            hasher.update(path.metadata()?.permissions().mode().to_le_bytes());
+        }
+        let content = read(path)?;
+        hasher.update(content.len().to_le_bytes());
+        hasher.update(content);
+    }
+    hasher.update([0]);
+    Ok(())
+}
+
+/// Mix the hash of a context with the build options applied to it.
+#[must_use]
+pub fn combine(hash: &str, options: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(hash.as_bytes());
+    hasher.update([0]);
+    hasher.update(options.as_bytes());
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anchored, hash};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::Path;
+
+    #[test]
+    fn patterns_are_anchored_at_the_root() {
+        assert_eq!(anchored("foo.txt").as_deref(), Some("/foo.txt"));
+        assert_eq!(anchored("./sub/*.log").as_deref(), Some("/sub/*.log"));
+        assert_eq!(anchored("/target").as_deref(), Some("/target"));
+        assert_eq!(anchored("!keep.txt").as_deref(), Some("!/keep.txt"));
+        assert_eq!(anchored("**/*.tmp").as_deref(), Some("**/*.tmp"));
+        assert_eq!(anchored("# comment"), None);
+        assert_eq!(anchored("  "), None);
+    }
+
+    #[test]
+    fn nested_files_are_not_ignored_by_root_patterns() {
+        let dir = std::env::temp_dir().join(format!("docks-context-{}", std::process::id()));
+        create_dir_all(dir.join("sub")).expect("context");
+        write(dir.join(".dockerignore"), "foo.txt\n").expect("dockerignore");
+        write(dir.join("foo.txt"), "a").expect("file");
+        write(dir.join("sub/foo.txt"), "a").expect("file");
+        let dockerfile = Path::new("Dockerfile");
+        let before = hash(dir.as_path(), dockerfile).expect("hash");
+        write(dir.join("foo.txt"), "b").expect("file");
+        let ignored = hash(dir.as_path(), dockerfile).expect("hash");
+        write(dir.join("sub/foo.txt"), "b").expect("file");
+        let nested = hash(dir.as_path(), dockerfile).expect("hash");
+        remove_dir_all(&dir).expect("cleanup");
+        assert_eq!(before, ignored);
+        assert_ne!(ignored, nested);
+    }
+}
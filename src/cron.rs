@@ -0,0 +1,673 @@
+use crate::backup::Backup;
+use crate::compose::{self, Rules};
+use crate::config;
+use crate::gc::Host;
+use crate::hub::home;
+use crate::registry::option;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::env::temp_dir;
+use std::fs::{create_dir_all, read_to_string, remove_file, write, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+use toml::{Table, Value};
+
+pub const CRONTAB: &str = "crontab.toml";
+pub const BADGE_DIR: &str = ".docks/badges";
+pub const PERIODS: [&str; 3] = ["daily", "weekly", "monthly"];
+
+/// The volumes a `backup` job archives, on the host of the container unless `host` is set,
+/// into `destination` instead of `[backup].dir` when it is set.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub container: String,
+    pub volumes: Vec<String>,
+    pub host: Option<String>,
+    pub destination: Option<String>,
+    pub stop: Option<bool>,
+}
+
+/// An `on_success` or `on_failure` entry, `badge` or `notify` with its options.
+#[derive(Clone, Debug)]
+pub struct Action {
+    pub kind: String,
+    pub options: Table,
+}
+
+/// One `[[cron.jobs]]` entry running either shell `commands` or a `backup`.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub name: String,
+    pub schedule: String,
+    pub commands: Vec<String>,
+    pub backup: Option<Target>,
+    pub retention: Vec<(String, usize)>,
+    pub log: Option<PathBuf>,
+    pub retries: usize,
+    pub retry_delay: u64,
+    pub on_success: Vec<Action>,
+    pub on_failure: Vec<Action>,
+}
+
+/// The jobs of crontab.toml with the notification settings their actions use.
+#[derive(Clone, Debug)]
+pub struct Crontab {
+    pub config: Value,
+    pub jobs: Vec<Job>,
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .filter(|s| s.is_empty().eq(&false))
+        .map(String::from)
+        .collect()
+}
+
+fn actions(value: Option<&Value>, job: &str) -> Result<Vec<Action>, Error> {
+    let mut actions: Vec<Action> = Vec::new();
+    for action in value.and_then(Value::as_array).into_iter().flatten() {
+        let pair = action.as_array().map(Vec::as_slice);
+        let Some([Value::String(kind), Value::Table(options)]) = pair else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{job}: actions are [\"badge\" or \"notify\", {{ options }}] pairs"),
+            ));
+        };
+        if ["badge", "notify"].contains(&kind.as_str()).eq(&false) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{job}: unknown action {kind}"),
+            ));
+        }
+        actions.push(Action {
+            kind: kind.clone(),
+            options: options.clone(),
+        });
+    }
+    Ok(actions)
+}
+
+fn retention(value: Option<&Value>, job: &str) -> Result<Vec<(String, usize)>, Error> {
+    let mut retention: Vec<(String, usize)> = Vec::new();
+    for rule in value.and_then(Value::as_array).into_iter().flatten() {
+        let pair = rule.as_array().map(Vec::as_slice);
+        let Some([Value::String(period), Value::Integer(count)]) = pair else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{job}: retention rules are [\"daily\", 7] pairs"),
+            ));
+        };
+        if PERIODS.contains(&period.as_str()).eq(&false) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{job}: retention period must be one of {}",
+                    PERIODS.join(", ")
+                ),
+            ));
+        }
+        let count = usize::try_from(*count)
+            .ok()
+            .filter(|count| count.ge(&1))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{job}: {period} retention must keep at least 1 snapshot, not {count}"),
+                )
+            })?;
+        retention.push((period.clone(), count));
+    }
+    Ok(retention)
+}
+
+fn target(value: Option<&Value>, job: &str) -> Result<Option<Target>, Error> {
+    let Some(backup) = value else {
+        return Ok(None);
+    };
+    let field = |name: &str| backup.get(name).and_then(Value::as_str).map(String::from);
+    let container = field("container").ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{job}: backup needs a container"),
+        )
+    })?;
+    Ok(Some(Target {
+        container,
+        volumes: strings(backup.get("volumes")),
+        host: field("host"),
+        destination: field("destination"),
+        stop: backup.get("stop").and_then(Value::as_bool),
+    }))
+}
+
+fn matches(field: &str, value: u32, min: u32, max: u32) -> bool {
+    field.split(',').any(|item| {
+        let (range, step) = item.split_once('/').map_or((item, 1), |(range, step)| {
+            (range, step.parse::<u32>().unwrap_or(1).max(1))
+        });
+        let (start, end) = if range.eq("*") {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start.parse::<u32>().unwrap_or(max + 1),
+                end.parse::<u32>().unwrap_or(min),
+            )
+        } else {
+            let start = range.parse::<u32>().unwrap_or(max + 1);
+            (start, if item.contains('/') { max } else { start })
+        };
+        (start..=end).step_by(step as usize).any(|v| v.eq(&value))
+    })
+}
+
+/// Whether the five fields cron `schedule` fires at the minute of `now`. When both the day of
+/// the month and the day of the week are restricted, neither starting with `*`, either one
+/// matching is enough.
+///
+/// # Errors
+///
+/// When the schedule does not have five fields.
+pub fn due(schedule: &str, now: &DateTime<Local>) -> Result<bool, Error> {
+    let fields = schedule.split_whitespace().collect::<Vec<&str>>();
+    let [minute, hour, day, month, weekday] = fields.as_slice() else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{schedule} is not a five fields cron schedule"),
+        ));
+    };
+    let weekday_matches = matches(weekday, now.weekday().num_days_from_sunday(), 0, 7)
+        || (now.weekday().num_days_from_sunday().eq(&0) && matches(weekday, 7, 0, 7));
+    let day_matches = matches(day, now.day(), 1, 31);
+    let days = match (day.starts_with('*'), weekday.starts_with('*')) {
+        (false, false) => day_matches || weekday_matches,
+        _ => day_matches && weekday_matches,
+    };
+    Ok(matches(minute, now.minute(), 0, 59)
+        && matches(hour, now.hour(), 0, 23)
+        && matches(month, now.month(), 1, 12)
+        && days)
+}
+
+fn append(log: Option<&PathBuf>, line: &str) {
+    let Some(log) = log else {
+        println!("{line}");
+        return;
+    };
+    if let Some(dir) = log.parent() {
+        let _ = create_dir_all(dir);
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log) {
+        let _ = writeln!(file, "{line}");
+    }
+}
+
+fn badge(label: &str, message: &str, color: &str) -> String {
+    let color = match color {
+        "green" => "#4c1",
+        "red" => "#e05d44",
+        "yellow" => "#dfb317",
+        "orange" => "#fe7d37",
+        "blue" => "#007ec6",
+        "grey" | "gray" => "#555",
+        other => other,
+    };
+    let width = |text: &str| text.chars().count() * 7 + 10;
+    let (left, right) = (width(label), width(message));
+    let total = left + right;
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{total}\" height=\"20\">\
+<rect width=\"{left}\" height=\"20\" fill=\"#555\"/>\
+<rect x=\"{left}\" width=\"{right}\" height=\"20\" fill=\"{color}\"/>\
+<g fill=\"#fff\" font-family=\"Verdana,sans-serif\" font-size=\"11\">\
+<text x=\"5\" y=\"14\">{label}</text><text x=\"{}\" y=\"14\">{message}</text></g></svg>\n",
+        left + 5
+    )
+}
+
+impl Job {
+    fn from_value(job: &Value) -> Result<Self, Error> {
+        let name = job
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "cron jobs need a name"))?
+            .to_string();
+        let field = |key: &str| job.get(key).and_then(Value::as_str).unwrap_or_default();
+        let number = |key: &str| {
+            job.get(key)
+                .and_then(Value::as_integer)
+                .and_then(|n| u64::try_from(n).ok())
+                .unwrap_or_default()
+        };
+        let commands = strings(job.get("commands"));
+        let backup = target(job.get("backup"), name.as_str())?;
+        if commands.is_empty() == backup.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{name}: a job runs either commands or a backup"),
+            ));
+        }
+        let schedule = field("schedule").to_string();
+        due(schedule.as_str(), &Local::now())?;
+        Ok(Self {
+            schedule,
+            commands,
+            backup,
+            retention: retention(job.get("retention"), name.as_str())?,
+            log: Some(field("log"))
+                .filter(|log| log.is_empty().eq(&false))
+                .map(PathBuf::from),
+            retries: usize::try_from(number("retries")).unwrap_or_default(),
+            retry_delay: number("retry_delay"),
+            on_success: actions(job.get("on_success"), name.as_str())?,
+            on_failure: actions(job.get("on_failure"), name.as_str())?,
+            name,
+        })
+    }
+}
+
+impl Crontab {
+    /// Load crontab.toml from `dir`, a missing file has no job.
+    ///
+    /// # Errors
+    ///
+    /// When the file is not valid toml, uses unset variables or a job is malformed.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(CRONTAB);
+        let config = if path.is_file() {
+            config::load(path.as_path())?
+        } else {
+            Value::Table(Table::new())
+        };
+        let jobs = config
+            .get("cron")
+            .and_then(|cron| cron.get("jobs"))
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(Job::from_value)
+            .collect::<Result<Vec<Job>, Error>>()?;
+        Ok(Self { config, jobs })
+    }
+
+    #[must_use]
+    pub fn job(&self, name: &str) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.name.eq(name))
+    }
+
+    /// The jobs to run at the minute of `now`.
+    #[must_use]
+    pub fn due(&self, now: &DateTime<Local>) -> Vec<&Job> {
+        self.jobs
+            .iter()
+            .filter(|job| due(job.schedule.as_str(), now).unwrap_or(false))
+            .collect()
+    }
+
+    /// Run `job`, retried `retries` times `retry_delay` seconds apart, then its `on_success` or
+    /// `on_failure` actions. A failing action is written to the job log.
+    ///
+    /// # Errors
+    ///
+    /// The error of the last attempt.
+    pub fn run(&self, job: &Job, docks: &Value) -> Result<(), Error> {
+        let log = job.log.as_ref();
+        let mut result = Ok(());
+        for attempt in 0..=job.retries {
+            if attempt.gt(&0) {
+                sleep(Duration::from_secs(job.retry_delay));
+            }
+            append(
+                log,
+                format!(
+                    "{} {} attempt {}",
+                    Local::now().to_rfc3339(),
+                    job.name,
+                    attempt + 1
+                )
+                .as_str(),
+            );
+            result = match &job.backup {
+                Some(target) => Self::backup(job, target, docks),
+                None => Self::commands(job),
+            };
+            match &result {
+                Ok(()) => break,
+                Err(e) => append(log, format!("{}: {e}", job.name).as_str()),
+            }
+        }
+        let actions = if result.is_ok() {
+            &job.on_success
+        } else {
+            &job.on_failure
+        };
+        for action in actions {
+            if let Err(e) = self.action(job, action, result.as_ref().err()) {
+                append(log, format!("{} {}: {e}", job.name, action.kind).as_str());
+            }
+        }
+        result
+    }
+
+    fn commands(job: &Job) -> Result<(), Error> {
+        for command in &job.commands {
+            let mut shell = Command::new("sh");
+            shell.args(["-c", command.as_str()]).stdin(Stdio::null());
+            if let Some(log) = &job.log {
+                let file = OpenOptions::new().create(true).append(true).open(log)?;
+                shell.stdout(file.try_clone()?).stderr(file);
+            }
+            let status = shell
+                .status()
+                .map_err(|_| Error::new(ErrorKind::NotFound, "sh not found"))?;
+            if status.success().eq(&false) {
+                return Err(Error::other(format!("{command} failed")));
+            }
+        }
+        Ok(())
+    }
+
+    fn backup(job: &Job, target: &Target, docks: &Value) -> Result<(), Error> {
+        let mut backup = Backup::from_config(docks, Path::new("."))?;
+        if let Some(destination) = &target.destination {
+            backup.dir = home(destination.as_str(), Path::new("."));
+        }
+        let host = match &target.host {
+            Some(host) => Host::named(docks, host.as_str())?,
+            None => Host::deploying(docks, target.container.as_str(), None).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("{e}, set backup.host in the {} job", job.name),
+                )
+            })?,
+        };
+        let dir = PathBuf::from(format!("./containers/{}", target.container));
+        let compose = compose::lint(dir.as_path(), &Rules::default(), &|_| true)?;
+        let snapshot = backup.snapshot(
+            &host,
+            target.container.as_str(),
+            &compose,
+            &target.volumes,
+            target.stop.unwrap_or(backup.stop),
+        )?;
+        append(
+            job.log.as_ref(),
+            format!("{} saved in {}", job.name, snapshot.dir.display()).as_str(),
+        );
+        for deleted in backup.retain(
+            host.name.as_str(),
+            target.container.as_str(),
+            &job.retention,
+        )? {
+            append(
+                job.log.as_ref(),
+                format!("{} deleted {}", job.name, deleted.display()).as_str(),
+            );
+        }
+        Ok(())
+    }
+
+    fn action(&self, job: &Job, action: &Action, error: Option<&Error>) -> Result<(), Error> {
+        let option = |name: &str| {
+            action
+                .options
+                .get(name)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        if action.kind.eq("badge") {
+            let label = Some(option("label"))
+                .filter(|label| label.is_empty().eq(&false))
+                .unwrap_or_else(|| job.name.clone());
+            let path = Some(option("save_at"))
+                .filter(|path| path.is_empty().eq(&false))
+                .map_or_else(
+                    || PathBuf::from(BADGE_DIR).join(format!("{}.svg", job.name)),
+                    PathBuf::from,
+                );
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir)?;
+            }
+            return write(
+                path,
+                badge(
+                    label.as_str(),
+                    option("message").as_str(),
+                    option("color").as_str(),
+                ),
+            );
+        }
+        self.notify(job, action, error)
+    }
+
+    /// Send the `notify` action by email through curl with `[notifications.email]`.
+    fn notify(&self, job: &Job, action: &Action, error: Option<&Error>) -> Result<(), Error> {
+        let notifications = self.config.get("notifications");
+        if notifications
+            .and_then(|n| n.get("enabled"))
+            .and_then(Value::as_bool)
+            .eq(&Some(false))
+        {
+            return Ok(());
+        }
+        let kind = action
+            .options
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("email");
+        if kind.ne("email") {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{kind} notifications are not supported"),
+            ));
+        }
+        let setting = |table: Option<&Value>, name: &str| match table.and_then(|t| t.get(name)) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        let email = notifications.and_then(|n| n.get("email"));
+        let server = setting(email, "smtp_server");
+        let port = setting(email, "smtp_port");
+        let username = setting(email, "smtp_username");
+        let password = setting(email, "smtp_password");
+        let ssl = setting(email, "ssl").eq("true");
+        let tls = setting(email, "tls").eq("true");
+        let options = Value::Table(action.options.clone());
+        let from = Some(setting(Some(&options), "from"))
+            .filter(|from| from.is_empty().eq(&false))
+            .unwrap_or_else(|| setting(email, "from_address"));
+        let template = Some(setting(Some(&options), "template"))
+            .filter(|t| t.is_empty().eq(&false))
+            .unwrap_or_else(|| setting(self.config.get("email"), "default"));
+        let to = strings(action.options.get("to"));
+        let cc = strings(action.options.get("cc"));
+        let status = if error.is_some() {
+            "failed"
+        } else {
+            "succeeded"
+        };
+        let mut body = format!("{} {status} at {}\n", job.name, Local::now().to_rfc3339());
+        if template.eq("minimal").eq(&false) {
+            body.push_str(format!("schedule: {}\n", job.schedule).as_str());
+        }
+        if ["detailed", "beautiful"].contains(&template.as_str()) {
+            if let Some(e) = error {
+                body.push_str(format!("error: {e}\n").as_str());
+            }
+            if let Some(log) = job.log.as_ref().and_then(|log| read_to_string(log).ok()) {
+                let lines = log.lines().collect::<Vec<&str>>();
+                body.push('\n');
+                body.push_str(lines[lines.len().saturating_sub(40)..].join("\n").as_str());
+                body.push('\n');
+            }
+        }
+        let message = temp_dir().join(format!(
+            "docks-{}-{}-{}-mail.txt",
+            job.name,
+            std::process::id(),
+            Local::now().timestamp_micros()
+        ));
+        let scheme = if ssl { "smtps" } else { "smtp" };
+        let mut args: Vec<String> = vec![
+            String::from("-sS"),
+            String::from("--url"),
+            format!("{scheme}://{server}:{port}"),
+            String::from("--mail-from"),
+            from.clone(),
+        ];
+        if tls {
+            args.push(String::from("--ssl-reqd"));
+        }
+        let mut config = String::new();
+        if username.is_empty().eq(&false) {
+            args.extend([String::from("-K"), String::from("-")]);
+            config = option("user", format!("{username}:{password}").as_str());
+        }
+        for rcpt in to.iter().chain(cc.iter()) {
+            args.extend([String::from("--mail-rcpt"), rcpt.clone()]);
+        }
+        args.extend([
+            String::from("-H"),
+            format!("Subject: [docks] {} {status}", job.name),
+            String::from("-H"),
+            format!("From: {from}"),
+            String::from("-H"),
+            format!("To: {}", to.join(", ")),
+        ]);
+        if cc.is_empty().eq(&false) {
+            args.extend([String::from("-H"), format!("Cc: {}", cc.join(", "))]);
+        }
+        args.extend([
+            String::from("-F"),
+            format!("=<{};type=text/plain", message.display()),
+        ]);
+        for attachment in strings(action.options.get("attachment")) {
+            if Path::new(attachment.as_str()).is_file() {
+                args.extend([String::from("-F"), format!("=@{attachment};encoder=base64")]);
+            }
+        }
+        let mut file = OpenOptions::new();
+        file.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            file.mode(0o600);
+        }
+        file.open(&message)?.write_all(body.as_bytes())?;
+        let sent = Command::new("curl")
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "curl not found"))
+            .and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(config.as_bytes())?;
+                }
+                child.wait()
+            });
+        remove_file(&message)?;
+        if sent?.success() {
+            return Ok(());
+        }
+        Err(Error::other(format!(
+            "cannot send the notification of {} to {}",
+            job.name,
+            to.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{due, matches, retention};
+    use chrono::{DateTime, Local, TimeZone};
+    use toml::Value;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 1, day, hour, minute, 0)
+            .single()
+            .expect("local time")
+    }
+
+    #[test]
+    fn fields_match_lists_ranges_and_steps() {
+        assert!(matches("*", 17, 0, 59));
+        assert!(matches("5", 5, 0, 59));
+        assert!(matches("1,15,30", 15, 0, 59));
+        assert!(matches("10-20", 20, 0, 59));
+        assert!(matches("10-20", 21, 0, 59).eq(&false));
+        assert!(matches("*/15", 45, 0, 59));
+        assert!(matches("*/15", 50, 0, 59).eq(&false));
+        assert!(matches("10-30/10", 30, 0, 59));
+        assert!(matches("10-30/10", 25, 0, 59).eq(&false));
+        assert!(matches("5/20", 45, 0, 59));
+        assert!(matches("x", 0, 0, 59).eq(&false));
+    }
+
+    #[test]
+    fn schedules_fire_at_their_minute() {
+        assert!(due("0 3 * * *", &at(5, 3, 0)).expect("schedule"));
+        assert!(due("0 3 * * *", &at(5, 3, 1)).expect("schedule").eq(&false));
+        assert!(due("*/5 * * * *", &at(5, 12, 35)).expect("schedule"));
+        assert!(due("0 0 * 2 *", &at(5, 0, 0)).expect("schedule").eq(&false));
+        assert!(due("0 3 * *", &at(5, 3, 0)).is_err());
+    }
+
+    #[test]
+    fn sunday_is_zero_or_seven() {
+        assert!(due("0 12 * * 0", &at(4, 12, 0)).expect("schedule"));
+        assert!(due("0 12 * * 7", &at(4, 12, 0)).expect("schedule"));
+        assert!(due("0 12 * * 1-5", &at(4, 12, 0))
+            .expect("schedule")
+            .eq(&false));
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_match_either() {
+        // the 15th or any Monday: the 5th is a Monday, the 15th a Thursday
+        assert!(due("0 12 15 * 1", &at(5, 12, 0)).expect("schedule"));
+        assert!(due("0 12 15 * 1", &at(15, 12, 0)).expect("schedule"));
+        assert!(due("0 12 15 * 1", &at(6, 12, 0))
+            .expect("schedule")
+            .eq(&false));
+    }
+
+    #[test]
+    fn a_star_day_or_weekday_needs_both() {
+        assert!(due("0 12 * * 1", &at(6, 12, 0))
+            .expect("schedule")
+            .eq(&false));
+        assert!(due("0 12 15 * *", &at(5, 12, 0))
+            .expect("schedule")
+            .eq(&false));
+        // */2 starts with a star, so the weekday must match too
+        assert!(due("0 12 */2 * 1", &at(7, 12, 0))
+            .expect("schedule")
+            .eq(&false));
+        assert!(due("0 12 */2 * 1", &at(5, 12, 0)).expect("schedule"));
+    }
+
+    #[test]
+    fn retention_keeps_at_least_one_snapshot() {
+        let rules = |content: &str| {
+            let value = toml::from_str::<Value>(content).expect("toml");
+            retention(value.get("retention"), "backup")
+        };
+        assert_eq!(
+            rules("retention = [[\"daily\", 7], [\"monthly\", 1]]").expect("retention"),
+            vec![(String::from("daily"), 7), (String::from("monthly"), 1)]
+        );
+        assert!(rules("retention = [[\"daily\", 0]]").is_err());
+        assert!(rules("retention = [[\"weekly\", -2]]").is_err());
+        assert!(rules("retention = [[\"yearly\", 1]]").is_err());
+    }
+}
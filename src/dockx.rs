@@ -1,33 +1,13 @@
-use std::collections::HashMap;
+use docks::config;
+use docks::hub::{self, Hub, HUB_FILE};
+use docks::{log, sbom, LOG_WITHOUT_SPACE};
 use std::env::{set_current_dir, var};
-use std::fs::read_to_string;
-use std::io::{Error, ErrorKind};
-use std::process::{Command, ExitCode};
-use toml::Value;
-
-fn docker(verb: &str, args: &[&str], path: &str) -> Result<(), Error> {
-    if let Ok(mut child) = Command::new("docker")
-        .arg(verb)
-        .args(args)
-        .current_dir(path)
-        .spawn()
-    {
-        if let Ok(status) = child.wait() {
-            if status.success() {
-                return Ok(());
-            }
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Docker exited with status no 0",
-            ));
-        }
-    }
-    Err(Error::new(ErrorKind::NotFound, "docker not found"))
-}
+use std::path::Path;
+use std::process::ExitCode;
 
 fn main() -> ExitCode {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
+    if args.len() < 2 {
         println!("dockx");
         return ExitCode::FAILURE;
     }
@@ -41,45 +21,63 @@ fn main() -> ExitCode {
         return ExitCode::SUCCESS;
     }
     if args[1] == "--publish" {
-        return publish();
+        return publish(args.iter().any(|arg| arg == "--force"), hub::jobs(&args));
+    }
+    if args[1] == "--prune" {
+        return prune(args.iter().any(|arg| arg == "--yes"));
+    }
+    if args[1] == "--sbom" && args.len() == 3 {
+        set_current_dir(var("DOCKX_ROOT").expect("DOCKX_ROOT not set").as_str()).expect("failed to checkout on directory");
+        if let Err(e) = sbom::print(Path::new("hub"), args[2].as_str()) {
+            log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
     }
     ExitCode::FAILURE
 }
 
-fn publish() -> ExitCode {
+fn publish(force: bool, jobs: Option<usize>) -> ExitCode {
     set_current_dir(var("DOCKX_ROOT").expect("DOCKX_ROOT not set").as_str()).expect("failed to checkout on directory");
-
-    let h = read_to_string("hub/hub.toml").expect("No hub.toml");
-
-    let config: Value = toml::from_str(h.as_str()).expect("Invalid hub.toml");
-    let mut tg: HashMap<String, Vec<String>> = HashMap::new();
-    let conf = config.as_table().expect("Invalid config");
-    let hub = conf.get("hub").expect("Missing hub").as_table().expect("Invalid config");
-    let username = hub.get("username").expect("Missing username").as_str().expect("Invalid config");
-    let images = hub.get("images").expect("Missing images").as_array().expect("Invalid config");
-    let tags = hub.get("tags").expect("Missing tags").as_table().expect("Invalid config");
-    for (name, tags) in tags {
-        tg.insert(name.to_string(), tags.as_array().expect("Invalid config").into_iter().map(|v| v.as_str().expect("Invalid config").to_string()).collect::<Vec<String>>());
-    }
-    for image in images {
-        let name = image.get("name").expect("Missing name").as_str().expect("Invalid config").to_string();
-        let path = image.get("path").expect("Missing path").as_str().expect("Invalid config").to_string();
-        let mut images_tags: Vec<String> = Vec::new();
-        let tags = image.get("tags").expect("Missing tags").as_array().expect("Invalid config");
-        for tag in tags {
-            let x = tag.as_str().expect("Invalid config").to_string();
-            let t = tg.get(&x).expect("invalid config");
-            for current in t {
-                images_tags.push(current.to_string());
-            }
-        }
-        for images_tag in &images_tags {
-            docker("buildx", &["build", "-t", format!("{username}/{name}:{images_tag}").as_str()], &path.as_str()).expect("docker buildx");
-            docker("push", &[format!("{username}/{name}:{images_tag}").as_str()], &path.as_str()).expect("docker push");
+    let mut hub = match Hub::load(Path::new("hub").join(HUB_FILE).as_path()) {
+        Ok(hub) => hub,
+        Err(e) => {
+            log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+            return ExitCode::FAILURE;
         }
+    };
+    if let Some(jobs) = jobs {
+        hub.jobs = jobs;
+    }
+    if let Err(e) = hub::publish(&hub, force) {
+        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
 
-        images_tags.clear();
+fn prune(yes: bool) -> ExitCode {
+    set_current_dir(var("DOCKX_ROOT").expect("DOCKX_ROOT not set").as_str()).expect("failed to checkout on directory");
+    let result = Hub::load(Path::new("hub").join(HUB_FILE).as_path()).and_then(|hub| {
+        let protected = if Path::new(config::CONFIG_FILE).is_file() {
+            hub::deployed(&config::Layered::load(Path::new(config::CONFIG_FILE), config::profile().as_deref())?.value)?
+        } else {
+            Vec::new()
+        };
+        let pruned = hub::prune(&hub, &protected, false)?;
+        if pruned.is_empty() {
+            log("No tag to prune", LOG_WITHOUT_SPACE);
+        } else if yes {
+            let pruned = hub::prune(&hub, &protected, true)?;
+            log(format!("{} tags deleted", pruned.len()).as_str(), LOG_WITHOUT_SPACE);
+        } else {
+            log(format!("{} tags to delete, run again with --yes to delete them", pruned.len()).as_str(), LOG_WITHOUT_SPACE);
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+        return ExitCode::FAILURE;
     }
-    dbg!(images);
     ExitCode::SUCCESS
 }
@@ -0,0 +1,391 @@
+use crate::config;
+use crate::cron::{self, PERIODS};
+use crate::schema;
+use chrono::Local;
+use std::fs::{read_to_string, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table};
+
+/// Config files opened for a structural edit, written back together once they all validate.
+#[derive(Debug, Default)]
+pub struct Editor {
+    documents: Vec<(PathBuf, DocumentMut)>,
+}
+
+/// A new cron job, running either `commands` or a backup of `container`.
+#[derive(Clone, Debug, Default)]
+pub struct NewJob {
+    pub name: String,
+    pub schedule: String,
+    pub commands: Vec<String>,
+    pub container: Option<String>,
+    pub stop: bool,
+    pub retention: Vec<(String, i64)>,
+    pub log: Option<String>,
+    pub retries: i64,
+    pub retry_delay: i64,
+}
+
+/// A parent table only written through its children, `[cron]` for `[[cron.jobs]]`.
+fn implicit() -> Item {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    Item::Table(table)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+impl Editor {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The document of the config file at `path`, read on first use, an empty one when the
+    /// file does not exist yet.
+    ///
+    /// # Errors
+    ///
+    /// When the file cannot be read or parsed.
+    pub fn document(&mut self, path: &Path) -> Result<&mut DocumentMut, Error> {
+        let at = match self.documents.iter().position(|(p, _)| p.eq(path)) {
+            Some(at) => at,
+            None => {
+                let document = if path.is_file() {
+                    read_to_string(path)?.parse::<DocumentMut>().map_err(|e| {
+                        Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+                    })?
+                } else {
+                    DocumentMut::new()
+                };
+                self.documents.push((path.to_path_buf(), document));
+                self.documents.len() - 1
+            }
+        };
+        Ok(&mut self.documents[at].1)
+    }
+
+    /// Check every edited file against the schema of its kind, docks.toml for the files it
+    /// includes, and docks.toml merged again with its includes as edited, then write them all.
+    ///
+    /// # Errors
+    ///
+    /// When a file does not validate or the merge fails, nothing being written then, or a file
+    /// cannot be written.
+    pub fn save(self) -> Result<Vec<PathBuf>, Error> {
+        let mut errors: Vec<String> = Vec::new();
+        for (path, document) in &self.documents {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let Some(schema) =
+                schema::schema(name.as_str()).or_else(|| schema::schema(config::CONFIG_FILE))
+            else {
+                continue;
+            };
+            let parsed =
+                toml::from_str::<toml::Value>(document.to_string().as_str()).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+                })?;
+            errors.extend(
+                schema::validate(&parsed, &schema)
+                    .into_iter()
+                    .map(|(_, message)| format!("  {}: {message}", path.display())),
+            );
+        }
+        if errors.is_empty().eq(&false) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "nothing saved, the changes do not validate :\n{}",
+                    errors.join("\n")
+                ),
+            ));
+        }
+        let docks = Path::new(config::CONFIG_FILE);
+        if docks.is_file() || self.documents.iter().any(|(path, _)| path.eq(docks)) {
+            let edited = self
+                .documents
+                .iter()
+                .map(|(path, document)| (path.clone(), document.to_string()))
+                .collect::<Vec<(PathBuf, String)>>();
+            config::Layered::edited(docks, None, &edited)
+                .map_err(|e| Error::new(e.kind(), format!("nothing saved, {e}")))?;
+        }
+        let mut written: Vec<PathBuf> = Vec::new();
+        for (path, document) in self.documents {
+            write(&path, document.to_string())?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Add the server `name` to `document`.
+///
+/// # Errors
+///
+/// When `document` already has a table of that name.
+pub fn add_host(
+    document: &mut DocumentMut,
+    name: &str,
+    username: &str,
+    ip: &str,
+    port: &str,
+) -> Result<(), Error> {
+    if name.eq("local") || document.contains_key(name) {
+        return Err(invalid(format!("{name} already exists")));
+    }
+    let mut table = Table::new();
+    table.insert("username", value(username));
+    table.insert("ip", value(ip));
+    table.insert("port", value(port));
+    table.insert("containers", value(Array::new()));
+    document.insert(name, Item::Table(table));
+    Ok(())
+}
+
+/// Remove the server `name` from `document`, returning the containers it deployed.
+///
+/// # Errors
+///
+/// When `document` has no server of that name.
+pub fn remove_host(document: &mut DocumentMut, name: &str) -> Result<Vec<String>, Error> {
+    if document
+        .get(name)
+        .and_then(|table| table.get("ip"))
+        .is_none()
+    {
+        return Err(invalid(format!("{name} is not a server")));
+    }
+    let removed = document.remove(name);
+    Ok(removed
+        .as_ref()
+        .and_then(|table| table.get("containers"))
+        .and_then(Item::as_array)
+        .map(|containers| {
+            containers
+                .iter()
+                .filter_map(|c| c.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Remove `container` from the `containers` list of every table of `document`.
+pub fn unassign(document: &mut DocumentMut, container: &str) {
+    for (_, table) in document.iter_mut() {
+        if let Some(containers) = table.get_mut("containers").and_then(Item::as_array_mut) {
+            let before = containers.len();
+            containers.retain(|c| c.as_str().ne(&Some(container)));
+            if containers.len().ne(&before) {
+                containers.fmt();
+            }
+        }
+    }
+}
+
+/// Make `containers` the list of `host`, `local` included, keeping the comments of its table.
+///
+/// # Errors
+///
+/// When `host` is not a table of `document`.
+pub fn set_containers(
+    document: &mut DocumentMut,
+    host: &str,
+    containers: &[String],
+) -> Result<(), Error> {
+    if host.eq("local") && document.contains_key(host).eq(&false) {
+        document.insert(host, Item::Table(Table::new()));
+    }
+    let table = document
+        .get_mut(host)
+        .and_then(Item::as_table_like_mut)
+        .ok_or_else(|| invalid(format!("{host} is not a server")))?;
+    match table.get_mut("containers").and_then(Item::as_array_mut) {
+        Some(list) => {
+            list.retain(|c| {
+                c.as_str()
+                    .is_some_and(|c| containers.iter().any(|k| k.eq(c)))
+            });
+            for container in containers {
+                if list
+                    .iter()
+                    .any(|c| c.as_str().eq(&Some(container.as_str())))
+                    .eq(&false)
+                {
+                    list.push(container.as_str());
+                }
+            }
+            list.fmt();
+        }
+        None => {
+            table.insert(
+                "containers",
+                value(containers.iter().map(String::as_str).collect::<Array>()),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The tag groups of `hub.tags` in a loaded `config`.
+#[must_use]
+pub fn tag_groups(config: &toml::Value) -> Vec<String> {
+    config
+        .get("hub")
+        .and_then(|hub| hub.get("tags"))
+        .and_then(toml::Value::as_array)
+        .map(|pairs| {
+            pairs
+                .iter()
+                .filter_map(|pair| pair.as_array()?.first()?.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Append a `[[hub.images]]` entry to `document`.
+///
+/// # Errors
+///
+/// When an image has the same name or `hub.images` is not an array of tables.
+pub fn add_image(
+    document: &mut DocumentMut,
+    name: &str,
+    path: &str,
+    tags: &[String],
+    after: &[String],
+) -> Result<(), Error> {
+    let hub = document
+        .entry("hub")
+        .or_insert_with(implicit)
+        .as_table_like_mut()
+        .ok_or_else(|| invalid(String::from("hub is not a table")))?;
+    let images = hub
+        .entry("images")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| {
+            invalid(String::from(
+                "hub.images uses an older layout, run docks migrate-config first",
+            ))
+        })?;
+    if images
+        .iter()
+        .any(|image| image.get("name").and_then(Item::as_str).eq(&Some(name)))
+    {
+        return Err(invalid(format!("the hub image {name} already exists")));
+    }
+    let mut image = Table::new();
+    image.insert("name", value(name));
+    image.insert(
+        "tags",
+        value(tags.iter().map(String::as_str).collect::<Array>()),
+    );
+    image.insert("path", value(path));
+    if after.is_empty().eq(&false) {
+        image.insert(
+            "after",
+            value(after.iter().map(String::as_str).collect::<Array>()),
+        );
+    }
+    images.push(image);
+    Ok(())
+}
+
+/// The names of the jobs of a crontab.toml `document`.
+#[must_use]
+pub fn job_names(document: &DocumentMut) -> Vec<String> {
+    document
+        .get("cron")
+        .and_then(|cron| cron.get("jobs"))
+        .and_then(Item::as_array_of_tables)
+        .map(|jobs| {
+            jobs.iter()
+                .filter_map(|job| job.get("name").and_then(Item::as_str).map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Append `job` to the `[[cron.jobs]]` of a crontab.toml `document`.
+///
+/// # Errors
+///
+/// When the name is taken, the schedule is not a valid cron expression, the job has both or
+/// none of commands and a backup, or a retention period is unknown or keeps no snapshot.
+pub fn add_job(document: &mut DocumentMut, job: &NewJob) -> Result<(), Error> {
+    if job_names(document).contains(&job.name) {
+        return Err(invalid(format!("the cron job {} already exists", job.name)));
+    }
+    cron::due(job.schedule.as_str(), &Local::now())?;
+    if job.commands.is_empty() == job.container.is_none() {
+        return Err(invalid(format!(
+            "{}: a job runs either commands or a backup",
+            job.name
+        )));
+    }
+    if let Some((period, _)) = job
+        .retention
+        .iter()
+        .find(|(period, _)| PERIODS.contains(&period.as_str()).eq(&false))
+    {
+        return Err(invalid(format!(
+            "{period} is not one of {}",
+            PERIODS.join(", ")
+        )));
+    }
+    if let Some((period, count)) = job.retention.iter().find(|(_, count)| count.lt(&1)) {
+        return Err(invalid(format!(
+            "{period} retention must keep at least 1 snapshot, not {count}"
+        )));
+    }
+    let mut table = Table::new();
+    table.insert("name", value(job.name.as_str()));
+    table.insert("schedule", value(job.schedule.as_str()));
+    if job.commands.is_empty().eq(&false) {
+        table.insert(
+            "commands",
+            value(job.commands.iter().map(String::as_str).collect::<Array>()),
+        );
+    }
+    if let Some(container) = &job.container {
+        let mut backup = InlineTable::new();
+        backup.insert("container", container.as_str().into());
+        backup.insert("stop", job.stop.into());
+        table.insert("backup", value(backup));
+    }
+    if job.retention.is_empty().eq(&false) {
+        let mut retention = Array::new();
+        for (period, count) in &job.retention {
+            let mut pair = Array::new();
+            pair.push(period.as_str());
+            pair.push(*count);
+            retention.push(pair);
+        }
+        table.insert("retention", value(retention));
+    }
+    if let Some(log) = &job.log {
+        table.insert("log", value(log.as_str()));
+    }
+    if job.retries.gt(&0) {
+        table.insert("retries", value(job.retries));
+        table.insert("retry_delay", value(job.retry_delay));
+    }
+    let cron = document
+        .entry("cron")
+        .or_insert_with(implicit)
+        .as_table_like_mut()
+        .ok_or_else(|| invalid(String::from("cron is not a table")))?;
+    cron.entry("jobs")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| invalid(String::from("cron.jobs must be [[cron.jobs]] tables")))?
+        .push(table);
+    Ok(())
+}
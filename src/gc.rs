@@ -0,0 +1,601 @@
+use crate::compose::Compose;
+use chrono::{DateTime, Duration, Local};
+use std::io::{Error, ErrorKind};
+use std::process::{Command, Stdio};
+
+/// A docker host, reached over ssh when `ssh` holds its `user@ip`.
+#[derive(Clone, Debug)]
+pub struct Host {
+    pub name: String,
+    pub ssh: Option<String>,
+    pub port: String,
+}
+
+/// What the compose files deployed on a host use, never removed.
+#[derive(Clone, Debug, Default)]
+pub struct Protected {
+    pub images: Vec<String>,
+    pub projects: Vec<String>,
+    pub volumes: Vec<String>,
+    pub networks: Vec<String>,
+}
+
+/// A removable image, container, volume or network, `size` in bytes when docker reports it.
+#[derive(Clone, Debug)]
+pub struct Item {
+    pub kind: &'static str,
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+}
+
+/// The items a host can reclaim.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub items: Vec<Item>,
+}
+
+impl Protected {
+    /// Everything `composes` reference.
+    #[must_use]
+    pub fn from_composes(composes: &[Compose]) -> Self {
+        let mut protected = Self::default();
+        for compose in composes {
+            protected.images.extend(compose.images.iter().cloned());
+            protected.projects.extend(
+                compose
+                    .path
+                    .parent()
+                    .and_then(|dir| dir.file_name())
+                    .map(|name| name.to_string_lossy().to_lowercase()),
+            );
+            protected.volumes.extend(compose.volumes());
+            let (created, external) = compose.networks();
+            protected.networks.extend(created);
+            protected.networks.extend(external);
+        }
+        protected
+    }
+
+    fn image(&self, repository: &str, tag: &str) -> bool {
+        let reference = format!("{repository}:{tag}");
+        self.images.iter().any(|image| {
+            let image = image.strip_prefix("docker.io/").unwrap_or(image);
+            image.eq(&reference) || (tag.eq("latest") && image.eq(repository))
+        })
+    }
+}
+
+/// Quote `arg` for the shell running a command over ssh.
+#[must_use]
+pub fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Parse a size printed by docker like `1.5GB`, `12.3kB` or `0B`, in decimal units.
+#[must_use]
+// The value is rounded and never negative, docker sizes being far below u64::MAX.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn bytes(size: &str) -> u64 {
+    let size = size.trim();
+    let at = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(at);
+    let factor: f64 = match unit.to_lowercase().as_str() {
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        _ => 1.0,
+    };
+    let value = number.trim().parse::<f64>().unwrap_or_default() * factor;
+    if value.is_sign_negative() {
+        return 0;
+    }
+    value.round() as u64
+}
+
+/// Print `bytes` the way docker does, in decimal units.
+#[must_use]
+// Sizes are printed with one decimal, the precision lost beyond 2^52 bytes never shows.
+#[allow(clippy::cast_precision_loss)]
+pub fn human(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "kB", "MB", "GB"] {
+        if value.lt(&1000.0) {
+            return format!("{value:.1}{unit}");
+        }
+        value /= 1000.0;
+    }
+    format!("{value:.1}TB")
+}
+
+/// The stopped containers of a `docker container ls` `listing` outside the protected compose
+/// projects, with the images of the containers kept.
+fn stopped(listing: &str, protected: &Protected) -> (Vec<Item>, Vec<String>) {
+    let mut items: Vec<Item> = Vec::new();
+    let mut used: Vec<String> = Vec::new();
+    for line in listing.lines() {
+        let fields = line.split('|').collect::<Vec<&str>>();
+        let [id, image, state, size, name, project] = fields.as_slice() else {
+            continue;
+        };
+        let stopped = ["exited", "created", "dead"].contains(state);
+        if stopped && protected.projects.iter().any(|p| p.eq(project)).eq(&false) {
+            items.push(Item {
+                kind: "container",
+                id: id.to_string(),
+                name: name.to_string(),
+                size: bytes(size.split(' ').next().unwrap_or_default()),
+            });
+        } else {
+            used.push(image.to_string());
+        }
+    }
+    (items, used)
+}
+
+/// The images of a `docker image ls` `listing` neither `used` by a container nor protected,
+/// dangling or created before `limit`.
+fn unused(
+    listing: &str,
+    used: &[String],
+    protected: &Protected,
+    limit: DateTime<Local>,
+) -> Vec<Item> {
+    let mut items: Vec<Item> = Vec::new();
+    for line in listing.lines() {
+        let fields = line.split('|').collect::<Vec<&str>>();
+        let [id, repository, tag, created, size] = fields.as_slice() else {
+            continue;
+        };
+        let dangling = repository.eq(&"<none>");
+        let reference = format!("{repository}:{tag}");
+        let in_use = used.iter().any(|image| {
+            image.eq(&reference) || image.eq(id) || (tag.eq(&"latest") && image.eq(repository))
+        });
+        let old = created
+            .rsplit_once(' ')
+            .and_then(|(date, _)| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z").ok())
+            .is_some_and(|date| date.lt(&limit));
+        if in_use || protected.image(repository, tag) || (dangling.eq(&false) && old.eq(&false)) {
+            continue;
+        }
+        items.push(Item {
+            kind: "image",
+            id: id.to_string(),
+            name: if dangling { id.to_string() } else { reference },
+            size: bytes(size),
+        });
+    }
+    items
+}
+
+/// The size of each volume listed by `docker system df -v`.
+fn volume_sizes(usage: &str) -> Vec<(String, u64)> {
+    usage
+        .split("Local Volumes space usage:")
+        .nth(1)
+        .unwrap_or_default()
+        .lines()
+        .skip_while(|line| line.starts_with("VOLUME NAME").eq(&false))
+        .skip(1)
+        .take_while(|line| line.trim().is_empty().eq(&false))
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<&str>>();
+            Some((columns.first()?.to_string(), bytes(columns.last()?)))
+        })
+        .collect()
+}
+
+/// The hosts of docks.toml listing `container`, `local` included.
+#[must_use]
+pub fn hosts(config: &toml::Value, container: &str) -> Vec<String> {
+    config
+        .as_table()
+        .into_iter()
+        .flatten()
+        .filter(|(name, table)| name.as_str().eq("local") || table.get("ip").is_some())
+        .filter(|(_, table)| {
+            table
+                .get("containers")
+                .and_then(toml::Value::as_array)
+                .is_some_and(|c| c.iter().any(|c| c.as_str().eq(&Some(container))))
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+impl Host {
+    #[must_use]
+    pub fn local() -> Self {
+        Self {
+            name: String::from("local"),
+            ssh: None,
+            port: String::from("22"),
+        }
+    }
+
+    /// The server named `server` in docks.toml, `None` when it has no ip.
+    #[must_use]
+    pub fn from_config(config: &toml::Value, server: &str) -> Option<Self> {
+        let table = config.get(server)?;
+        let field = |name: &str| {
+            table
+                .get(name)
+                .and_then(toml::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let ip = field("ip");
+        if ip.is_empty() {
+            return None;
+        }
+        let port = field("port");
+        Some(Self {
+            name: server.to_string(),
+            ssh: Some(format!("{}@{ip}", field("username"))),
+            port: if port.is_empty() {
+                String::from("22")
+            } else {
+                port
+            },
+        })
+    }
+
+    /// The host deploying `container`: `host` when given, which must list it in docks.toml,
+    /// otherwise the only host listing it.
+    ///
+    /// # Errors
+    ///
+    /// When no host lists `container`, `host` does not, or several do and `host` is `None`.
+    pub fn deploying(
+        config: &toml::Value,
+        container: &str,
+        host: Option<&str>,
+    ) -> Result<Self, Error> {
+        let listing = hosts(config, container);
+        let name = match (host, listing.as_slice()) {
+            (_, []) => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("{container} is not deployed in docks.toml"),
+                ))
+            }
+            (Some(host), _) if listing.iter().any(|h| h.eq(host)) => host,
+            (Some(host), _) => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("{container} is not deployed on {host} in docks.toml"),
+                ))
+            }
+            (None, [name]) => name.as_str(),
+            (None, _) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{container} is listed on {}, choose the host",
+                        listing.join(" and ")
+                    ),
+                ))
+            }
+        };
+        Self::named(config, name)
+    }
+
+    /// The host named `name` in docks.toml, `local` included.
+    ///
+    /// # Errors
+    ///
+    /// When docks.toml has no server of that name.
+    pub fn named(config: &toml::Value, name: &str) -> Result<Self, Error> {
+        if name.eq("local") {
+            return Ok(Self::local());
+        }
+        Self::from_config(config, name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{name} is not a server")))
+    }
+
+    /// The directory docker compose runs `container` from on this host.
+    #[must_use]
+    pub fn project(&self, container: &str) -> String {
+        match self.ssh {
+            Some(_) => container.to_string(),
+            None => format!("./containers/{container}"),
+        }
+    }
+
+    /// A docker command running on this host, arguments are quoted for the remote shell.
+    #[must_use]
+    pub fn command(&self, args: &[&str]) -> Command {
+        match &self.ssh {
+            Some(target) => {
+                let mut command = Command::new("ssh");
+                command
+                    .args(["-p", self.port.as_str(), target.as_str(), "docker"])
+                    .args(args.iter().map(|arg| quote(arg)));
+                command
+            }
+            None => {
+                let mut command = Command::new("docker");
+                command.args(args);
+                command
+            }
+        }
+    }
+
+    /// Run docker on this host and return what it printed.
+    ///
+    /// # Errors
+    ///
+    /// When docker or ssh is missing or the command fails.
+    pub fn docker(&self, args: &[&str]) -> Result<String, Error> {
+        let output = self
+            .command(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "docker or ssh not found"))?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+        Err(Error::other(format!(
+            "docker {} failed on {}",
+            args.first().copied().unwrap_or_default(),
+            self.name
+        )))
+    }
+
+    /// List what can be reclaimed: stopped containers, dangling images, images unused for
+    /// more than `days`, unused volumes and networks, leaving out what `protected` holds.
+    ///
+    /// # Errors
+    ///
+    /// When docker cannot be reached on the host.
+    pub fn report(&self, days: i64, protected: &Protected) -> Result<Report, Error> {
+        let mut items: Vec<Item> = Vec::new();
+        let containers = self.docker(&[
+            "container",
+            "ls",
+            "-a",
+            "--format",
+            "{{.ID}}|{{.Image}}|{{.State}}|{{.Size}}|{{.Names}}|{{.Label \"com.docker.compose.project\"}}",
+        ])?;
+        let (stopped, used) = stopped(containers.as_str(), protected);
+        items.extend(stopped);
+        let images = self.docker(&[
+            "image",
+            "ls",
+            "-a",
+            "--format",
+            "{{.ID}}|{{.Repository}}|{{.Tag}}|{{.CreatedAt}}|{{.Size}}",
+        ])?;
+        items.extend(unused(
+            images.as_str(),
+            &used,
+            protected,
+            Local::now() - Duration::days(days),
+        ));
+        let usage = self.docker(&["system", "df", "-v"]).unwrap_or_default();
+        let sizes = volume_sizes(usage.as_str());
+        for volume in self
+            .docker(&["volume", "ls", "-q", "--filter", "dangling=true"])?
+            .lines()
+            .filter(|volume| protected.volumes.iter().any(|v| v.eq(volume)).eq(&false))
+        {
+            items.push(Item {
+                kind: "volume",
+                id: volume.to_string(),
+                name: volume.to_string(),
+                size: sizes
+                    .iter()
+                    .find(|(name, _)| name.eq(volume))
+                    .map(|(_, size)| *size)
+                    .unwrap_or_default(),
+            });
+        }
+        for network in self
+            .docker(&[
+                "network",
+                "ls",
+                "--filter",
+                "dangling=true",
+                "--format",
+                "{{.Name}}",
+            ])?
+            .lines()
+            .filter(|network| ["bridge", "host", "none"].contains(network).eq(&false))
+            .filter(|network| protected.networks.iter().any(|n| n.eq(network)).eq(&false))
+        {
+            items.push(Item {
+                kind: "network",
+                id: network.to_string(),
+                name: network.to_string(),
+                size: 0,
+            });
+        }
+        Ok(Report { items })
+    }
+
+    /// Remove the items of `report`, containers first so their images and volumes are free,
+    /// and return the bytes freed with the items docker refused to remove.
+    pub fn remove(&self, report: &Report) -> (u64, Vec<(Item, Error)>) {
+        let mut freed: u64 = 0;
+        let mut failed: Vec<(Item, Error)> = Vec::new();
+        for kind in ["container", "image", "volume", "network"] {
+            for item in report.items.iter().filter(|item| item.kind.eq(kind)) {
+                match self.docker(&[kind, "rm", item.id.as_str()]) {
+                    Ok(_) => freed += item.size,
+                    Err(e) => failed.push((item.clone(), e)),
+                }
+            }
+        }
+        (freed, failed)
+    }
+}
+
+impl Report {
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.items.iter().map(|item| item.size).sum()
+    }
+
+    /// The items by kind with their size, as one block to print.
+    #[must_use]
+    pub fn summary(&self, host: &str) -> String {
+        let mut summary = format!("\n{host}: {} reclaimable\n", human(self.size()));
+        for kind in ["container", "image", "volume", "network"] {
+            let items = self
+                .items
+                .iter()
+                .filter(|item| item.kind.eq(kind))
+                .collect::<Vec<&Item>>();
+            if items.is_empty() {
+                continue;
+            }
+            let size: u64 = items.iter().map(|item| item.size).sum();
+            summary.push_str(format!("  {} {kind}s, {}\n", items.len(), human(size)).as_str());
+            for item in items {
+                summary.push_str(format!("    {:10}  {}\n", human(item.size), item.name).as_str());
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytes, hosts, human, stopped, unused, volume_sizes, Host, Protected};
+    use chrono::{DateTime, Local};
+
+    fn protected() -> Protected {
+        Protected {
+            images: vec![String::from("docker.io/postgres:16")],
+            projects: vec![String::from("zuu")],
+            ..Protected::default()
+        }
+    }
+
+    #[test]
+    fn docker_sizes_are_parsed_in_decimal_units() {
+        assert_eq!(bytes("1.5GB"), 1_500_000_000);
+        assert_eq!(bytes("0B"), 0);
+        assert_eq!(bytes("12.3kB"), 12_300);
+        assert_eq!(bytes(" 2MB "), 2_000_000);
+        assert_eq!(bytes("-1MB"), 0);
+        assert_eq!(bytes("N/A"), 0);
+    }
+
+    #[test]
+    fn sizes_are_printed_like_docker() {
+        assert_eq!(human(0), "0.0B");
+        assert_eq!(human(999), "999.0B");
+        assert_eq!(human(1000), "1.0kB");
+        assert_eq!(human(1_500_000_000), "1.5GB");
+        assert_eq!(human(2_000_000_000_000_000), "2000.0TB");
+    }
+
+    #[test]
+    fn stopped_containers_of_protected_projects_are_kept() {
+        let listing = "\
+a1|zuu:stable|exited|1.2kB (virtual 80MB)|zuu-app-1|zuu
+b2|nginx:1.27|exited|10B (virtual 40MB)|old-web-1|old
+c3|redis:7|running|0B (virtual 30MB)|cache-1|cache
+broken line";
+        let (items, used) = stopped(listing, &protected());
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| (item.id.as_str(), item.size))
+                .collect::<Vec<(&str, u64)>>(),
+            vec![("b2", 10)]
+        );
+        assert_eq!(used, vec!["zuu:stable", "redis:7"]);
+    }
+
+    #[test]
+    fn images_used_protected_or_recent_are_kept() {
+        let limit = DateTime::parse_from_rfc3339("2026-01-01T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Local);
+        let listing = "\
+i1|zuu|stable|2025-06-01 10:00:00 +0000 UTC|80MB
+i2|postgres|16|2025-06-01 10:00:00 +0000 UTC|400MB
+i3|nginx|1.25|2025-06-01 10:00:00 +0000 UTC|40MB
+i4|nginx|1.27|2026-02-01 10:00:00 +0000 UTC|41MB
+i5|<none>|<none>|2026-02-01 10:00:00 +0000 UTC|5MB";
+        let items = unused(listing, &[String::from("zuu:stable")], &protected(), limit);
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["nginx:1.25", "i5"]
+        );
+        assert_eq!(items[0].size, 40_000_000);
+    }
+
+    #[test]
+    fn volume_sizes_are_read_from_system_df() {
+        let usage = "\
+Images space usage:
+
+REPOSITORY   TAG   IMAGE ID   CREATED   SIZE
+
+Local Volumes space usage:
+
+VOLUME NAME   LINKS     SIZE
+zuu_data      1         1.5GB
+old_cache     0         0B
+
+Build cache usage: 0B";
+        assert_eq!(
+            volume_sizes(usage),
+            vec![
+                (String::from("zuu_data"), 1_500_000_000),
+                (String::from("old_cache"), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_container_listed_on_two_hosts_needs_one_chosen() {
+        let docks = toml::from_str::<toml::Value>(
+            r#"
+[local]
+containers = ["zuu", "teams"]
+
+[lab]
+username = "ops"
+ip = "10.0.0.2"
+port = "2222"
+containers = ["zuu"]
+
+[lint]
+containers = ["zuu"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(hosts(&docks, "zuu"), vec!["lab", "local"]);
+        let e = Host::deploying(&docks, "zuu", None).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "zuu is listed on lab and local, choose the host"
+        );
+        let lab = Host::deploying(&docks, "zuu", Some("lab")).unwrap();
+        assert_eq!(lab.name, "lab");
+        assert_eq!(lab.ssh.as_deref(), Some("ops@10.0.0.2"));
+        assert_eq!(lab.port, "2222");
+        assert_eq!(
+            Host::deploying(&docks, "zuu", Some("local")).unwrap().name,
+            "local"
+        );
+        assert_eq!(
+            Host::deploying(&docks, "teams", None).unwrap().name,
+            "local"
+        );
+        assert!(Host::deploying(&docks, "teams", Some("lab")).is_err());
+        assert!(Host::deploying(&docks, "picsou", None).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::Local;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The state of the git repository holding a build context.
+#[derive(Clone, Debug, Default)]
+pub struct Revision {
+    pub sha: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+    pub date: String,
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if out.is_empty().eq(&false) {
+            return Some(out);
+        }
+    }
+    None
+}
+
+/// Read the commit, the tag pointing at it and the branch checked out in `dir`.
+#[must_use]
+pub fn revision(dir: &Path) -> Revision {
+    Revision {
+        sha: git(dir, &["rev-parse", "--short", "HEAD"]),
+        tag: git(dir, &["describe", "--tags", "--exact-match", "HEAD"]),
+        branch: git(dir, &["rev-parse", "--abbrev-ref", "HEAD"]).filter(|b| b.ne("HEAD")),
+        date: Local::now().format("%Y%m%d").to_string(),
+    }
+}
+
+/// Replace the characters docker refuses in a tag.
+#[must_use]
+pub fn sanitize(tag: &str) -> String {
+    let tag: String = tag
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c.eq(&'_') || c.eq(&'.') || c.eq(&'-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let tag = tag.trim_start_matches(['.', '-']);
+    tag.chars().take(128).collect()
+}
+
+/// The `1.4.2`, `1.4` and `1` tags of a `v1.4.2` git tag, only the full version for a
+/// pre-release like `1.5.0-rc.1`.
+#[must_use]
+pub fn semver(tag: &str) -> Vec<String> {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    let (core, pre) = version
+        .split_once(['-', '+'])
+        .map_or((version, false), |(core, _)| (core, true));
+    let parts = core.split('.').collect::<Vec<&str>>();
+    if parts.len().ne(&3)
+        || parts
+            .iter()
+            .any(|p| p.is_empty() || p.parse::<u64>().is_err())
+    {
+        return Vec::new();
+    }
+    if pre {
+        return vec![sanitize(version)];
+    }
+    vec![
+        core.to_string(),
+        format!("{}.{}", parts[0], parts[1]),
+        parts[0].to_string(),
+    ]
+}
+
+/// Expand the `{sha}`, `{tag}`, `{semver}`, `{branch}` and `{date}` placeholders of a tag
+/// template, a template whose placeholder has no value in `revision` gives no tag.
+///
+/// # Errors
+///
+/// When the template uses an unknown placeholder.
+pub fn expand(template: &str, revision: &Revision) -> Result<Vec<String>, Error> {
+    if template.contains('{').eq(&false) {
+        return Ok(vec![template.to_string()]);
+    }
+    let mut tags: Vec<String> = vec![String::new()];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unclosed placeholder in tag {template}"),
+                )
+            })?;
+        let literal = &rest[..start];
+        let values: Vec<String> = match &rest[start + 1..end] {
+            "sha" => revision.sha.iter().cloned().collect(),
+            "tag" => revision.tag.iter().map(|t| sanitize(t)).collect(),
+            "semver" => revision.tag.iter().flat_map(|t| semver(t)).collect(),
+            "branch" => revision.branch.iter().map(|b| sanitize(b)).collect(),
+            "date" => vec![revision.date.to_string()],
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown placeholder {{{other}}} in tag {template}"),
+                ))
+            }
+        };
+        tags = tags
+            .iter()
+            .flat_map(|prefix| values.iter().map(move |v| format!("{prefix}{literal}{v}")))
+            .collect();
+        rest = &rest[end + 1..];
+    }
+    Ok(tags
+        .into_iter()
+        .map(|tag| sanitize(format!("{tag}{rest}").as_str()))
+        .collect())
+}
+
+/// Match `text` against a pattern where `*` stands for any run of characters.
+#[must_use]
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern.eq(text);
+    };
+    let Some(mut remaining) = text.strip_prefix(first) else {
+        return false;
+    };
+    let pieces = rest.split('*').collect::<Vec<&str>>();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i.eq(&(pieces.len() - 1)) {
+            return remaining.ends_with(piece);
+        }
+        match remaining.find(piece) {
+            Some(at) => remaining = &remaining[at + piece.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand, matches, semver, Revision};
+
+    fn revision(tag: Option<&str>) -> Revision {
+        Revision {
+            sha: Some(String::from("abc1234")),
+            tag: tag.map(String::from),
+            branch: Some(String::from("feature/login")),
+            date: String::from("20260102"),
+        }
+    }
+
+    #[test]
+    fn releases_give_every_semver_level() {
+        assert_eq!(semver("v1.4.2"), vec!["1.4.2", "1.4", "1"]);
+        assert_eq!(semver("2.0.10"), vec!["2.0.10", "2.0", "2"]);
+    }
+
+    #[test]
+    fn pre_releases_only_give_the_full_version() {
+        assert_eq!(semver("v1.5.0-rc.1"), vec!["1.5.0-rc.1"]);
+        assert_eq!(semver("1.5.0+build.7"), vec!["1.5.0-build.7"]);
+    }
+
+    #[test]
+    fn other_tags_are_not_semver() {
+        assert!(semver("v1.4").is_empty());
+        assert!(semver("release-1").is_empty());
+        assert!(semver("v1..2").is_empty());
+    }
+
+    #[test]
+    fn placeholders_are_expanded() {
+        let revision = revision(Some("v1.4.2"));
+        let tag = |template: &str| expand(template, &revision).expect("template");
+        assert_eq!(tag("stable"), vec!["stable"]);
+        assert_eq!(tag("{branch}-{sha}"), vec!["feature-login-abc1234"]);
+        assert_eq!(tag("{semver}"), vec!["1.4.2", "1.4", "1"]);
+        assert_eq!(
+            tag("v{semver}-slim"),
+            vec!["v1.4.2-slim", "v1.4-slim", "v1-slim"]
+        );
+        assert_eq!(tag("{tag}"), vec!["v1.4.2"]);
+        assert_eq!(tag("nightly-{date}"), vec!["nightly-20260102"]);
+    }
+
+    #[test]
+    fn placeholders_without_value_give_no_tag() {
+        let revision = revision(None);
+        assert!(expand("{semver}", &revision).expect("template").is_empty());
+        assert!(expand("{tag}-{sha}", &revision)
+            .expect("template")
+            .is_empty());
+    }
+
+    #[test]
+    fn unknown_or_unclosed_placeholders_fail() {
+        let revision = revision(None);
+        assert!(expand("{commit}", &revision).is_err());
+        assert!(expand("{sha", &revision).is_err());
+    }
+
+    #[test]
+    fn stars_match_any_run() {
+        assert!(matches("v*", "v1.2.3"));
+        assert!(matches("*", ""));
+        assert!(matches("feature/*", "feature/login"));
+        assert!(matches("*-rc*", "1.0-rc2"));
+        assert!(matches("a*bc*c", "abcc"));
+        assert!(matches("main", "main"));
+        assert!(matches("v*", "1.2.3").eq(&false));
+        assert!(matches("main", "maintenance").eq(&false));
+        assert!(matches("a*a", "a").eq(&false));
+        assert!(matches("*a*a", "ba").eq(&false));
+    }
+}
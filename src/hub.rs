@@ -0,0 +1,1066 @@
+use crate::build::Build;
+use crate::compose;
+use crate::config;
+use crate::context;
+use crate::gc;
+use crate::git::{self, Revision};
+use crate::lock::Lock;
+use crate::plan::{self, Dependencies};
+use crate::registry::{Registry, Tag};
+use crate::sbom::{self, Mode};
+use crate::sign::Signing;
+use crate::vuln::{self, Database, Thresholds, SEVERITIES};
+use crate::{build, log, LOG_WITHOUT_SPACE, LOG_WITH_SPACE};
+use std::collections::BTreeMap;
+use std::env::var;
+use std::io::{Error, ErrorKind};
+use std::path::{absolute, Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::available_parallelism;
+use std::time::{Duration, Instant};
+use toml::Value;
+
+pub const HUB_FILE: &str = "hub.toml";
+
+/// Where the buildx output of each image goes, relative to the hub root.
+pub const LOG_DIR: &str = ".docks/logs";
+
+/// One `[[hub.images]]` entry.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub name: String,
+    pub path: PathBuf,
+    pub groups: Vec<String>,
+    pub tags: Vec<String>,
+    pub platforms: Vec<String>,
+    pub dockerfile: PathBuf,
+    pub target: Option<String>,
+    pub build_args: BTreeMap<String, String>,
+    pub tag_args: BTreeMap<String, BTreeMap<String, String>>,
+    pub labels: BTreeMap<String, String>,
+    pub secrets: Vec<String>,
+    pub ssh: Vec<String>,
+    pub cache_from: Vec<String>,
+    pub cache_to: Vec<String>,
+    pub after: Vec<String>,
+    pub sbom: Option<Mode>,
+    pub provenance: Option<String>,
+    pub vulnerabilities: Thresholds,
+}
+
+/// The `[hub]` section shared by docks.toml and hub.toml.
+#[derive(Clone, Debug)]
+pub struct Hub {
+    pub root: PathBuf,
+    pub username: String,
+    pub registry: String,
+    pub groups: Vec<(String, Vec<String>)>,
+    pub channels: BTreeMap<String, Channel>,
+    pub images: Vec<Image>,
+    pub jobs: usize,
+    pub signing: Option<Signing>,
+    pub database: PathBuf,
+    pub retention: Vec<(String, usize)>,
+    pub account: Registry,
+}
+
+/// When a static tag of `[hub.channels]` is published, from the branch or the git tag of
+/// the commit being built.
+#[derive(Clone, Debug, Default)]
+pub struct Channel {
+    pub branches: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl Channel {
+    #[must_use]
+    pub fn accepts(&self, revision: &Revision) -> bool {
+        let branch = revision.branch.as_ref().is_some_and(|branch| {
+            self.branches
+                .iter()
+                .any(|pattern| git::matches(pattern, branch))
+        });
+        let tag = revision
+            .tag
+            .as_ref()
+            .is_some_and(|tag| self.tags.iter().any(|pattern| git::matches(pattern, tag)));
+        branch || tag
+    }
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn home(path: &str, root: &Path) -> PathBuf {
+    path.strip_prefix("~/").map_or_else(
+        || root.join(path),
+        |rest| PathBuf::from(var("HOME").unwrap_or_default()).join(rest),
+    )
+}
+
+fn map(value: Option<&Value>, location: &str) -> Result<BTreeMap<String, String>, Error> {
+    let mut map: BTreeMap<String, String> = BTreeMap::new();
+    let Some(value) = value else {
+        return Ok(map);
+    };
+    let table = value
+        .as_table()
+        .ok_or_else(|| invalid(format!("{location} must be a table")))?;
+    for (key, value) in table {
+        let value = match value {
+            Value::String(s) => s.to_string(),
+            Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => value.to_string(),
+            _ => return Err(invalid(format!("{location}.{key} must be a string"))),
+        };
+        map.insert(key.to_string(), value);
+    }
+    Ok(map)
+}
+
+/// Turn `cache_from` or `cache_to` entries in buildx `--cache-*` values, an entry is either
+/// the raw buildx value or a table like `{ type = "registry", ref = "user/{name}:cache" }`.
+fn caches(value: Option<&Value>, name: &str, root: &Path) -> Result<Vec<String>, Error> {
+    let mut caches: Vec<String> = Vec::new();
+    for cache in value.and_then(Value::as_array).into_iter().flatten() {
+        let cache = match cache {
+            Value::String(raw) => raw.to_string(),
+            Value::Table(table) => {
+                let kind = table
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| invalid(format!("a cache of hub image {name} has no type")))?;
+                let mut parts = vec![format!("type={kind}")];
+                for (key, value) in table {
+                    if key.eq("type") {
+                        continue;
+                    }
+                    let value = match value {
+                        Value::String(s) => s.to_string(),
+                        other => other.to_string(),
+                    };
+                    let value = if kind.eq("local") && (key.eq("src") || key.eq("dest")) {
+                        home(value.as_str(), root).display().to_string()
+                    } else {
+                        value
+                    };
+                    parts.push(format!("{key}={value}"));
+                }
+                parts.join(",")
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "the caches of hub image {name} must be strings or tables"
+                )))
+            }
+        };
+        caches.push(cache.replace("{name}", name));
+    }
+    Ok(caches)
+}
+
+fn sbom_mode(value: Option<&Value>, location: &str) -> Result<Option<Mode>, Error> {
+    match value {
+        None | Some(Value::Boolean(false)) => Ok(None),
+        Some(Value::Boolean(true)) => Ok(Some(Mode::Buildx)),
+        Some(Value::String(mode)) if mode.eq("buildx") => Ok(Some(Mode::Buildx)),
+        Some(Value::String(mode)) if mode.eq("scan") => Ok(Some(Mode::Scan)),
+        Some(_) => Err(invalid(format!(
+            "{location} sbom must be true, false, \"buildx\" or \"scan\""
+        ))),
+    }
+}
+
+fn provenance(value: Option<&Value>, location: &str) -> Result<Option<String>, Error> {
+    match value {
+        None | Some(Value::Boolean(false)) => Ok(None),
+        Some(Value::Boolean(true)) => Ok(Some(String::from("min"))),
+        Some(Value::String(mode)) if mode.eq("min") || mode.eq("max") => Ok(Some(mode.clone())),
+        Some(_) => Err(invalid(format!(
+            "{location} provenance must be true, false, \"min\" or \"max\""
+        ))),
+    }
+}
+
+fn thresholds(value: Option<&Value>, location: &str) -> Result<Thresholds, Error> {
+    let mut thresholds = Thresholds::new();
+    for (severity, max) in value.and_then(Value::as_table).into_iter().flatten() {
+        if severity.eq("database") {
+            continue;
+        }
+        if SEVERITIES.contains(&severity.as_str()).eq(&false) {
+            return Err(invalid(format!(
+                "{location} vulnerabilities.{severity} is not one of {}",
+                SEVERITIES.join(", ")
+            )));
+        }
+        let max = max
+            .as_integer()
+            .and_then(|max| usize::try_from(max).ok())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{location} vulnerabilities.{severity} must be a positive number"
+                ))
+            })?;
+        thresholds.insert(severity.to_string(), max);
+    }
+    Ok(thresholds)
+}
+
+/// Whether `tag` can come from the tag template `pattern`, a placeholder only matches values
+/// of its shape (`{date}` eight digits, `{sha}` hex, `{semver}` a version) and `*` anything.
+#[must_use]
+pub fn produces(pattern: &str, tag: &str) -> bool {
+    fn shape(placeholder: &str, value: &[char]) -> bool {
+        match placeholder {
+            "date" => value.len().eq(&8) && value.iter().all(char::is_ascii_digit),
+            "sha" => value.len().ge(&4) && value.iter().all(char::is_ascii_hexdigit),
+            "semver" => {
+                value.first().is_some_and(char::is_ascii_digit)
+                    && value
+                        .iter()
+                        .all(|c| c.is_ascii_alphanumeric() || c.eq(&'.') || c.eq(&'-'))
+            }
+            _ => value.is_empty().eq(&false),
+        }
+    }
+    fn walk(pattern: &[char], tag: &[char]) -> bool {
+        match pattern.first() {
+            None => tag.is_empty(),
+            Some('*') => (0..=tag.len()).any(|at| walk(&pattern[1..], &tag[at..])),
+            Some('{') => {
+                let Some(end) = pattern.iter().position(|c| c.eq(&'}')) else {
+                    return false;
+                };
+                let placeholder = pattern[1..end].iter().collect::<String>();
+                (1..=tag.len()).any(|at| {
+                    shape(placeholder.as_str(), &tag[..at]) && walk(&pattern[end + 1..], &tag[at..])
+                })
+            }
+            Some(c) => tag.first().is_some_and(|t| t.eq(c)) && walk(&pattern[1..], &tag[1..]),
+        }
+    }
+    walk(
+        pattern.chars().collect::<Vec<char>>().as_slice(),
+        tag.chars().collect::<Vec<char>>().as_slice(),
+    )
+}
+
+fn retention(value: Option<&Value>) -> Result<Vec<(String, usize)>, Error> {
+    let mut retention: Vec<(String, usize)> = Vec::new();
+    for pair in value.and_then(Value::as_array).into_iter().flatten() {
+        let pattern = pair.get(0).and_then(Value::as_str);
+        let keep = pair
+            .get(1)
+            .and_then(Value::as_integer)
+            .and_then(|keep| usize::try_from(keep).ok());
+        let (Some(pattern), Some(keep), Some(2)) = (pattern, keep, pair.as_array().map(Vec::len))
+        else {
+            return Err(invalid(String::from(
+                "hub.retention entries must be [\"tag pattern\", count] pairs",
+            )));
+        };
+        retention.push((pattern.to_string(), keep));
+    }
+    Ok(retention)
+}
+
+fn secrets(value: Option<&Value>, name: &str, root: &Path) -> Result<Vec<String>, Error> {
+    let mut secrets: Vec<String> = Vec::new();
+    for secret in value.and_then(Value::as_array).into_iter().flatten() {
+        let id = secret
+            .get("id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(format!("a secret of hub image {name} has no id")))?;
+        let location = format!("secret {id} of hub image {name}");
+        if let Some(src) = secret.get("src").and_then(Value::as_str) {
+            let src = home(src, root);
+            secrets.push(format!("id={id},src={}", src.display()));
+        } else if let Some(env) = secret.get("env").and_then(Value::as_str) {
+            if var(env).is_err() {
+                return Err(invalid(format!(
+                    "{location} reads the unset variable {env}"
+                )));
+            }
+            secrets.push(format!("id={id},env={env}"));
+        } else {
+            return Err(invalid(format!(
+                "{location} needs a src file or an env variable"
+            )));
+        }
+    }
+    Ok(secrets)
+}
+
+/// The account images are pushed to: `hub.username`, then `docker.username`, then `$USER`.
+#[must_use]
+pub fn username(config: &Value) -> String {
+    config
+        .get("hub")
+        .and_then(|hub| hub.get("username"))
+        .or_else(|| {
+            config
+                .get("docker")
+                .and_then(|docker| docker.get("username"))
+        })
+        .and_then(Value::as_str)
+        .filter(|username| username.is_empty().eq(&false))
+        .map_or_else(|| var("USER").unwrap_or_default(), String::from)
+}
+
+/// The host of the `[registry]` url as image references use it, without its scheme, empty
+/// for docker hub.
+#[must_use]
+pub fn registry(config: &Value) -> String {
+    let url = config
+        .get("registry")
+        .and_then(|registry| registry.get("url"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+impl Hub {
+    /// Read the hub of the config file at `path`, image paths are relative to its directory.
+    ///
+    /// # Errors
+    ///
+    /// When the file cannot be read, uses unset variables or its hub section is not valid.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config = config::load(path)?;
+        Self::from_config(&config, path.parent().unwrap_or_else(|| Path::new(".")))
+    }
+
+    /// Read the hub of an already parsed config living in `root`.
+    ///
+    /// # Errors
+    ///
+    /// When the hub section is missing or uses one of the older layouts.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Self, Error> {
+        let root = absolute(root)?;
+        let root = root.as_path();
+        let hub = config
+            .get("hub")
+            .and_then(Value::as_table)
+            .ok_or_else(|| invalid(String::from("missing the [hub] section")))?;
+        let groups = match hub.get("tags") {
+            Some(Value::Array(pairs)) => {
+                let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+                for pair in pairs {
+                    let Some([name, tags]) = pair.as_array().map(Vec::as_slice) else {
+                        return Err(invalid(String::from(
+                            "hub.tags entries must be [\"group\", [\"tag\", ...]] pairs",
+                        )));
+                    };
+                    let name = name.as_str().ok_or_else(|| {
+                        invalid(String::from("hub.tags group names must be strings"))
+                    })?;
+                    groups.push((name.to_string(), strings(Some(tags))));
+                }
+                groups
+            }
+            Some(Value::Table(table)) => {
+                let pairs = table
+                    .iter()
+                    .map(|(group, tags)| format!("[\"{group}\", {tags}]"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                return Err(invalid(format!(
+                    "hub.tags as a table is the old dockx layout, write it as an array of pairs:\ntags = [{pairs}]"
+                )));
+            }
+            Some(_) => {
+                return Err(invalid(String::from(
+                    "hub.tags must be an array of [\"group\", [\"tag\", ...]] pairs",
+                )))
+            }
+            None => Vec::new(),
+        };
+        let platforms = strings(hub.get("platforms"));
+        let vulnerabilities = thresholds(hub.get("vulnerabilities"), "hub")?;
+        let mut channels: BTreeMap<String, Channel> = BTreeMap::new();
+        if let Some(table) = hub.get("channels") {
+            let table = table
+                .as_table()
+                .ok_or_else(|| invalid(String::from("hub.channels must be a table")))?;
+            for (tag, rule) in table {
+                channels.insert(
+                    tag.to_string(),
+                    Channel {
+                        branches: strings(rule.get("branches")),
+                        tags: strings(rule.get("tags")),
+                    },
+                );
+            }
+        }
+        let mut images: Vec<Image> = Vec::new();
+        for image in hub
+            .get("images")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+        {
+            if let Some(old) = image.as_array() {
+                let name = old.first().and_then(Value::as_str).unwrap_or("name");
+                let tags = old.get(1).map(ToString::to_string).unwrap_or_default();
+                return Err(invalid(format!(
+                    "hub.images = [[name, [tags]]] is the old docks layout, declare each image as a table:\n[[hub.images]]\nname = \"{name}\"\ntags = [] # tag groups of hub.tags, was {tags}\npath = \"./{name}\""
+                )));
+            }
+            let name = image
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid(String::from("hub.images entries need a name")))?;
+            let path = image
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid(format!("hub image {name} needs a path")))?;
+            let image_groups = strings(image.get("tags"));
+            let mut tags: Vec<String> = Vec::new();
+            for group in &image_groups {
+                let (_, values) = groups.iter().find(|(g, _)| g.eq(group)).ok_or_else(|| {
+                    invalid(format!(
+                        "hub image {name} uses the unknown tag group {group}"
+                    ))
+                })?;
+                for tag in values {
+                    if tags.contains(tag).eq(&false) {
+                        tags.push(tag.to_string());
+                    }
+                }
+            }
+            let location = format!("hub image {name}");
+            let mut tag_args: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+            if let Some(overrides) = image.get("tag_args").and_then(Value::as_table) {
+                for (tag, args) in overrides {
+                    if tags.contains(tag).eq(&false) {
+                        return Err(invalid(format!(
+                            "{location} overrides the build args of {tag} which is not one of its tags"
+                        )));
+                    }
+                    tag_args.insert(
+                        tag.to_string(),
+                        map(Some(args), format!("{location} tag_args.{tag}").as_str())?,
+                    );
+                }
+            }
+            let path = root.join(path);
+            images.push(Image {
+                name: name.to_string(),
+                groups: image_groups,
+                tags,
+                platforms: image
+                    .get("platforms")
+                    .map_or_else(|| platforms.clone(), |p| strings(Some(p))),
+                dockerfile: path.join(
+                    image
+                        .get("dockerfile")
+                        .and_then(Value::as_str)
+                        .unwrap_or("Dockerfile"),
+                ),
+                target: image
+                    .get("target")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                build_args: map(
+                    image.get("build_args"),
+                    format!("{location} build_args").as_str(),
+                )?,
+                tag_args,
+                labels: map(image.get("labels"), format!("{location} labels").as_str())?,
+                secrets: secrets(image.get("secrets"), name, root)?,
+                ssh: strings(image.get("ssh"))
+                    .iter()
+                    .map(|ssh| match ssh.split_once('=') {
+                        Some((id, key)) => format!("{id}={}", home(key, root).display()),
+                        None => ssh.to_string(),
+                    })
+                    .collect(),
+                cache_from: caches(
+                    image.get("cache_from").or_else(|| hub.get("cache_from")),
+                    name,
+                    root,
+                )?,
+                cache_to: caches(
+                    image.get("cache_to").or_else(|| hub.get("cache_to")),
+                    name,
+                    root,
+                )?,
+                after: strings(image.get("after")),
+                sbom: sbom_mode(
+                    image.get("sbom").or_else(|| hub.get("sbom")),
+                    location.as_str(),
+                )?,
+                provenance: provenance(
+                    image.get("provenance").or_else(|| hub.get("provenance")),
+                    location.as_str(),
+                )?,
+                vulnerabilities: {
+                    let mut merged = vulnerabilities.clone();
+                    merged.extend(thresholds(image.get("vulnerabilities"), location.as_str())?);
+                    merged
+                },
+                path,
+            });
+        }
+        for image in &images {
+            for after in &image.after {
+                if images.iter().any(|i| i.name.eq(after)).eq(&false) {
+                    return Err(invalid(format!(
+                        "hub image {} is built after the unknown image {after}",
+                        image.name
+                    )));
+                }
+            }
+        }
+        let jobs = match hub.get("jobs") {
+            Some(jobs) => jobs
+                .as_integer()
+                .and_then(|jobs| usize::try_from(jobs).ok())
+                .filter(|jobs| jobs.gt(&0))
+                .ok_or_else(|| invalid(String::from("hub.jobs must be a positive number")))?,
+            None => available_parallelism().map_or(1, usize::from),
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            username: username(config),
+            registry: registry(config),
+            signing: Signing::from_config(config, root)?,
+            database: vuln::database(config, root),
+            retention: retention(hub.get("retention"))?,
+            account: Registry::from_config(config),
+            groups,
+            channels,
+            images,
+            jobs,
+        })
+    }
+
+    #[must_use]
+    pub fn repository(&self, image: &Image) -> String {
+        if self.registry.is_empty() {
+            return format!("{}/{}", self.username, image.name);
+        }
+        format!("{}/{}/{}", self.registry, self.username, image.name)
+    }
+
+    /// The tags of `image` for `revision`: the placeholders of its templates expanded and the
+    /// channel tags whose rule does not accept the revision left out.
+    ///
+    /// # Errors
+    ///
+    /// When a template uses an unknown placeholder.
+    pub fn tags(&self, image: &Image, revision: &Revision) -> Result<Vec<(String, String)>, Error> {
+        let mut tags: Vec<(String, String)> = Vec::new();
+        for template in &image.tags {
+            if self
+                .channels
+                .get(template)
+                .is_some_and(|channel| channel.accepts(revision).eq(&false))
+            {
+                continue;
+            }
+            for tag in git::expand(template.as_str(), revision)? {
+                if tags.iter().any(|(_, t)| t.eq(&tag)).eq(&false) {
+                    tags.push((template.to_string(), tag));
+                }
+            }
+        }
+        Ok(tags)
+    }
+
+    /// The builds needed to publish every tag of `image`, one per distinct set of build args
+    /// so that tags sharing their args are built once.
+    ///
+    /// # Errors
+    ///
+    /// When a tag template uses an unknown placeholder.
+    pub fn builds(&self, image: &Image) -> Result<Vec<Build>, Error> {
+        let repository = self.repository(image);
+        let revision = git::revision(image.path.as_path());
+        let mut builds: Vec<Build> = Vec::new();
+        for (template, tag) in self.tags(image, &revision)? {
+            let mut args = image.build_args.clone();
+            if let Some(overrides) = image.tag_args.get(&template) {
+                args.extend(overrides.clone());
+            }
+            let reference = format!("{repository}:{tag}");
+            if let Some(build) = builds.iter_mut().find(|b| b.build_args.eq(&args)) {
+                build.references.push(reference);
+                continue;
+            }
+            let mut build = Build::new(image.path.clone(), vec![reference]);
+            build.platforms.clone_from(&image.platforms);
+            build.dockerfile = Some(image.dockerfile.clone());
+            build.target.clone_from(&image.target);
+            build.build_args = args;
+            build.labels.clone_from(&image.labels);
+            build.secrets.clone_from(&image.secrets);
+            build.ssh.clone_from(&image.ssh);
+            build.cache_from.clone_from(&image.cache_from);
+            build.cache_to.clone_from(&image.cache_to);
+            build.sbom = image.sbom.eq(&Some(Mode::Buildx));
+            build.provenance.clone_from(&image.provenance);
+            builds.push(build);
+        }
+        Ok(builds)
+    }
+}
+
+/// The `--jobs N` option of a publish command line, overriding `hub.jobs`.
+#[must_use]
+pub fn jobs(args: &[String]) -> Option<usize> {
+    args.iter()
+        .position(|arg| arg.eq("--jobs"))
+        .and_then(|at| args.get(at + 1))
+        .and_then(|jobs| jobs.parse::<usize>().ok())
+        .filter(|jobs| jobs.gt(&0))
+}
+
+/// One build of a publish run.
+#[derive(Debug)]
+pub struct Row {
+    pub image: String,
+    pub references: Vec<String>,
+    pub duration: Duration,
+    pub size: Option<u64>,
+    pub digest: String,
+}
+
+/// What a publish run did.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub built: Vec<String>,
+    pub skipped: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+/// Build `build` for the host platform without pushing it and scan the loaded image, an
+/// image whose findings exceed its thresholds is not pushed.
+fn gate(
+    hub: &Hub,
+    image: &Image,
+    build: &Build,
+    name: &str,
+    database: &Database,
+) -> Result<(), Error> {
+    let mut local = build.clone();
+    local.references.truncate(1);
+    local.platforms.clear();
+    local.cache_to.clear();
+    local.sbom = false;
+    local.provenance = None;
+    local.push = false;
+    local.log = Some(hub.root.join(LOG_DIR).join(format!("{name}-scan.log")));
+    local.run()?;
+    let reference = local.references.first().cloned().unwrap_or_default();
+    let mut contents = sbom::inspect(reference.as_str(), false)?;
+    contents
+        .packages
+        .extend(sbom::lockfiles(image.path.as_path()));
+    let findings = database.scan(&contents);
+    println!("{}", vuln::report(reference.as_str(), &findings));
+    let exceeded = vuln::exceeded(&findings, &image.vulnerabilities);
+    if exceeded.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{} not pushed, {}", image.name, exceeded.join(", ")),
+    ))
+}
+
+fn publish_image(
+    hub: &Hub,
+    image: &Image,
+    force: bool,
+    database: Option<&Database>,
+    lock: &Mutex<Lock>,
+    summary: &Mutex<Summary>,
+) -> Result<(), Error> {
+    let files = context::hash(image.path.as_path(), image.dockerfile.as_path())?;
+    for (index, mut build) in hub.builds(image)?.into_iter().enumerate() {
+        let hash = context::combine(files.as_str(), build.fingerprint().as_str());
+        let fresh = lock.lock().is_ok_and(|lock| {
+            build
+                .references
+                .iter()
+                .all(|r| lock.is_fresh(r.as_str(), hash.as_str(), image.platforms.as_slice()))
+        });
+        if force.eq(&false) && fresh {
+            if let Ok(mut summary) = summary.lock() {
+                summary.skipped.extend(build.references);
+            }
+            continue;
+        }
+        let name = if index.eq(&0) {
+            image.name.to_string()
+        } else {
+            format!("{}-{index}", image.name)
+        };
+        let file = hub.root.join(LOG_DIR).join(format!("{name}.log"));
+        log(
+            format!(
+                "building {}, output in {}",
+                build.references.join(", "),
+                file.display()
+            )
+            .as_str(),
+            LOG_WITH_SPACE,
+        );
+        build.log = Some(file);
+        let now = Instant::now();
+        if let Some(database) = database.filter(|_| image.vulnerabilities.is_empty().eq(&false)) {
+            gate(hub, image, &build, name.as_str(), database)?;
+        }
+        let digest = build.run()?;
+        let duration = now.elapsed();
+        log(
+            format!(
+                "{} pushed in {}s with digest {digest}",
+                image.name,
+                duration.as_secs()
+            )
+            .as_str(),
+            LOG_WITH_SPACE,
+        );
+        let first = build.references.first().cloned().unwrap_or_default();
+        if let Some(signing) = &hub.signing {
+            signing.sign(first.as_str(), digest.as_str())?;
+            log(
+                format!("{} signed with {}", image.name, signing.key.display()).as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+        let document = match image.sbom {
+            Some(Mode::Buildx) => Some(sbom::fetch(first.as_str())?),
+            Some(Mode::Scan) => Some(sbom::scan(first.as_str())?),
+            None => None,
+        };
+        let sbom = match &document {
+            Some(document) => sbom::save(
+                hub.root.as_path(),
+                digest.as_str(),
+                first.as_str(),
+                document,
+            )?,
+            None => String::new(),
+        };
+        if let Ok(mut lock) = lock.lock() {
+            for reference in &build.references {
+                lock.record(
+                    reference.as_str(),
+                    hash.as_str(),
+                    digest.as_str(),
+                    image.platforms.as_slice(),
+                );
+                lock.set_sbom(reference.as_str(), sbom.as_str());
+            }
+            lock.save()?;
+        }
+        let size = build::size(first.as_str());
+        if let Ok(mut summary) = summary.lock() {
+            summary.built.extend(build.references.clone());
+            summary.rows.push(Row {
+                image: image.name.to_string(),
+                references: build.references,
+                duration,
+                size,
+                digest,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn table(rows: &[Row]) {
+    let width = rows
+        .iter()
+        .map(|row| row.image.len())
+        .max()
+        .unwrap_or_default()
+        .max(5);
+    println!(
+        "\n{:width$}  {:>6}  {:>10}  {:>4}  digest",
+        "image", "time", "size", "tags"
+    );
+    for row in rows {
+        println!(
+            "{:width$}  {:>5}s  {:>10}  {:>4}  {}",
+            row.image,
+            row.duration.as_secs(),
+            row.size.map_or_else(|| String::from("-"), gc::human),
+            row.references.len(),
+            row.digest
+        );
+    }
+    println!();
+}
+
+/// Build every image of `hub` once per set of build args with all the matching tags and push
+/// them, skipping the builds whose context and options did not change since the run recorded
+/// in the lock. Up to `hub.jobs` images are built at once, an image waits for the images of
+/// its `after` list and is skipped when one of them fails.
+///
+/// # Errors
+///
+/// When the `after` lists contain a cycle, an image cannot be published or the lock cannot
+/// be read.
+pub fn publish(hub: &Hub, force: bool) -> Result<Summary, Error> {
+    let lock = Mutex::new(Lock::load(hub.root.as_path())?);
+    let names = hub
+        .images
+        .iter()
+        .map(|image| image.name.to_string())
+        .collect::<Vec<String>>();
+    let dependencies: Dependencies = hub
+        .images
+        .iter()
+        .filter(|image| image.after.is_empty().eq(&false))
+        .map(|image| (image.name.to_string(), image.after.clone()))
+        .collect();
+    let stages = plan::stages(&names, &dependencies)?;
+    let database = if hub
+        .images
+        .iter()
+        .any(|image| image.vulnerabilities.is_empty().eq(&false))
+    {
+        Some(Database::load(hub.database.as_path())?)
+    } else {
+        None
+    };
+    let summary = Mutex::new(Summary::default());
+    let failed = plan::run(&stages, &dependencies, hub.jobs, |name| {
+        let image = hub
+            .images
+            .iter()
+            .find(|image| image.name.eq(name))
+            .ok_or_else(|| invalid(format!("unknown hub image {name}")))?;
+        publish_image(hub, image, force, database.as_ref(), &lock, &summary)
+    });
+    let mut summary = summary
+        .into_inner()
+        .map_err(|_| Error::other("a publish thread panicked"))?;
+    summary
+        .rows
+        .sort_by_key(|row| names.iter().position(|name| name.eq(&row.image)));
+    for reference in &summary.built {
+        log(format!("built {reference}").as_str(), LOG_WITH_SPACE);
+    }
+    for reference in &summary.skipped {
+        log(
+            format!("skipped {reference}, its context is unchanged").as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    if summary.rows.is_empty().eq(&false) {
+        table(&summary.rows);
+    }
+    for (image, e) in &failed {
+        log(format!("{image} : {e}").as_str(), LOG_WITH_SPACE);
+    }
+    log(
+        format!(
+            "{} tags published, {} unchanged tags skipped",
+            summary.built.len(),
+            summary.skipped.len()
+        )
+        .as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    if failed.is_empty() {
+        return Ok(summary);
+    }
+    Err(Error::other(format!(
+        "{} images not published : {}",
+        failed.len(),
+        failed
+            .iter()
+            .map(|(image, _)| image.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    )))
+}
+
+fn normalize(reference: &str) -> &str {
+    reference
+        .strip_prefix("docker.io/")
+        .or_else(|| reference.strip_prefix("index.docker.io/"))
+        .unwrap_or(reference)
+}
+
+/// The images used by the compose files of every container deployed on a host of the
+/// docks.toml `config`, the references `prune` keeps.
+///
+/// # Errors
+///
+/// When the compose file of a container cannot be read.
+pub fn deployed(config: &Value) -> Result<Vec<String>, Error> {
+    let mut images: Vec<String> = Vec::new();
+    for host in config
+        .as_table()
+        .into_iter()
+        .flatten()
+        .map(|(name, _)| name)
+    {
+        for compose in compose::deployed(config, host.as_str())? {
+            images.extend(compose.images);
+        }
+    }
+    images.sort();
+    images.dedup();
+    Ok(images)
+}
+
+/// Delete the registry tags of every hub image outside `hub.retention`: the tags matching a
+/// pattern beyond the newest kept for it. Tags matching no pattern and the `protected`
+/// references, those deployed, are kept. Nothing is deleted unless `apply` is set, the
+/// returned references are the deleted ones or the ones that would be.
+///
+/// # Errors
+///
+/// When the tags of a repository cannot be listed or a tag cannot be deleted.
+pub fn prune(hub: &Hub, protected: &[String], apply: bool) -> Result<Vec<String>, Error> {
+    if hub.retention.is_empty() {
+        return Err(invalid(String::from(
+            "hub.retention is empty, nothing would be pruned",
+        )));
+    }
+    let lock = Lock::load(hub.root.as_path())?;
+    let mut pruned: Vec<String> = Vec::new();
+    let mut done: Vec<String> = Vec::new();
+    let mut deleted: Vec<String> = Vec::new();
+    for image in &hub.images {
+        let repository = hub.repository(image);
+        if done.contains(&repository) {
+            continue;
+        }
+        done.push(repository.clone());
+        let path = format!("{}/{}", hub.username, image.name);
+        let tags = hub.account.tags(path.as_str())?;
+        let date = |tag: &Tag| {
+            tag.updated.clone().or_else(|| {
+                lock.get(format!("{repository}:{}", tag.name).as_str())
+                    .map(|entry| entry.published.clone())
+            })
+        };
+        let mut groups: Vec<Vec<&Tag>> = vec![Vec::new(); hub.retention.len()];
+        for tag in &tags {
+            if let Some(at) = hub
+                .retention
+                .iter()
+                .position(|(pattern, _)| produces(pattern, tag.name.as_str()))
+            {
+                groups[at].push(tag);
+            }
+        }
+        let mut kept: Vec<&Tag> = tags
+            .iter()
+            .filter(|tag| groups.iter().flatten().all(|t| t.name.ne(&tag.name)))
+            .collect();
+        let mut candidates: Vec<&Tag> = Vec::new();
+        for (group, (_, keep)) in groups.iter_mut().zip(&hub.retention) {
+            group.sort_by(|a, b| {
+                date(b)
+                    .cmp(&date(a))
+                    .then_with(|| vuln::compare(b.name.as_str(), a.name.as_str()))
+            });
+            for (at, tag) in group.iter().enumerate() {
+                let reference = format!("{repository}:{}", tag.name);
+                if at.lt(keep)
+                    || protected
+                        .iter()
+                        .any(|p| normalize(p).eq(normalize(reference.as_str())))
+                {
+                    kept.push(tag);
+                } else {
+                    candidates.push(tag);
+                }
+            }
+        }
+        for tag in candidates {
+            let reference = format!("{repository}:{}", tag.name);
+            let shared = kept.iter().find(|k| {
+                hub.account.url.is_empty().eq(&false)
+                    && k.digest.is_some()
+                    && k.digest.eq(&tag.digest)
+            });
+            if let Some(shared) = shared {
+                log(
+                    format!(
+                        "kept {reference}, it shares its digest with {}",
+                        shared.name
+                    )
+                    .as_str(),
+                    LOG_WITH_SPACE,
+                );
+                continue;
+            }
+            if apply {
+                let gone = hub.account.url.is_empty().eq(&false)
+                    && tag.digest.as_ref().is_some_and(|d| deleted.contains(d));
+                if gone.eq(&false) {
+                    hub.account.delete(path.as_str(), tag)?;
+                    deleted.extend(tag.digest.clone());
+                }
+                log(format!("deleted {reference}").as_str(), LOG_WITH_SPACE);
+            } else {
+                log(format!("would delete {reference}").as_str(), LOG_WITH_SPACE);
+            }
+            pruned.push(reference);
+        }
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::produces;
+
+    #[test]
+    fn placeholders_match_values_of_their_shape() {
+        assert!(produces("{date}", "20260102"));
+        assert!(produces("{date}", "2026010").eq(&false));
+        assert!(produces("{date}", "2026-01-02").eq(&false));
+        assert!(produces("{branch}-{sha}", "main-abc1234"));
+        assert!(produces("{branch}-{sha}", "feature-login-0f3e2d1"));
+        assert!(produces("{branch}-{sha}", "main-xyz").eq(&false));
+        assert!(produces("{semver}", "1.4.2"));
+        assert!(produces("{semver}", "1.5.0-rc.1"));
+        assert!(produces("{semver}", "v1.4.2").eq(&false));
+    }
+
+    #[test]
+    fn literals_and_stars_match() {
+        assert!(produces("hourly-*", "hourly-20260102"));
+        assert!(produces("hourly-*", "daily-20260102").eq(&false));
+        assert!(produces("stable", "stable"));
+        assert!(produces("stable", "stable-1").eq(&false));
+        assert!(produces("nightly-{date}", "nightly-20260102"));
+        assert!(produces("*", "anything"));
+    }
+
+    #[test]
+    fn placeholders_need_a_value() {
+        assert!(produces("{branch}-{sha}", "-abc1234").eq(&false));
+        assert!(produces("{tag}", "").eq(&false));
+        assert!(produces("{sha", "abc1234").eq(&false));
+    }
+}
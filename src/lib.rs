@@ -0,0 +1,29 @@
+pub mod backup;
+pub mod build;
+pub mod compose;
+pub mod config;
+pub mod context;
+pub mod cron;
+pub mod editor;
+pub mod gc;
+pub mod git;
+pub mod hub;
+pub mod lock;
+pub mod migrate;
+pub mod plan;
+pub mod registry;
+pub mod sbom;
+pub mod schema;
+pub mod secrets;
+pub mod sign;
+pub mod vuln;
+
+pub const LOG_WITHOUT_SPACE: &str = "";
+pub const LOG_WITH_SPACE: &str = " ";
+
+pub fn log(message: &str, t: &str) {
+    println!(
+        "{}",
+        format!("\x1b[1;32m{t}\x1b[0;37m {message}\x1b[0m").as_str()
+    );
+}
@@ -0,0 +1,134 @@
+use chrono::Local;
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const LOCK_FILE: &str = "docks.lock";
+
+/// What was pushed for one `user/image:tag` reference.
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    pub context: String,
+    pub digest: String,
+    pub platforms: Vec<String>,
+    pub published: String,
+    pub sbom: String,
+}
+
+/// The record of every published reference, kept in `docks.lock` next to the hub config.
+pub struct Lock {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Lock {
+    /// Load the lock file in `dir`, a missing file is an empty lock.
+    ///
+    /// # Errors
+    ///
+    /// When the lock file exists but is not valid toml.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(LOCK_FILE);
+        let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+        if let Ok(content) = read_to_string(&path) {
+            let table = toml::from_str::<Table>(content.as_str()).map_err(|e| {
+                Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+            })?;
+            for (reference, entry) in table {
+                let field = |name: &str| {
+                    entry
+                        .get(name)
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                entries.insert(
+                    reference,
+                    Entry {
+                        context: field("context"),
+                        digest: field("digest"),
+                        platforms: entry
+                            .get("platforms")
+                            .and_then(Value::as_array)
+                            .map(|p| {
+                                p.iter()
+                                    .filter_map(Value::as_str)
+                                    .map(String::from)
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        published: field("published"),
+                        sbom: field("sbom"),
+                    },
+                );
+            }
+        }
+        Ok(Self { path, entries })
+    }
+
+    #[must_use]
+    pub fn get(&self, reference: &str) -> Option<&Entry> {
+        self.entries.get(reference)
+    }
+
+    /// Whether `reference` was pushed from a context hashing to `context` for `platforms`.
+    #[must_use]
+    pub fn is_fresh(&self, reference: &str, context: &str, platforms: &[String]) -> bool {
+        self.get(reference)
+            .is_some_and(|entry| entry.context.eq(context) && entry.platforms.eq(platforms))
+    }
+
+    pub fn record(&mut self, reference: &str, context: &str, digest: &str, platforms: &[String]) {
+        self.entries.insert(
+            reference.to_string(),
+            Entry {
+                context: context.to_string(),
+                digest: digest.to_string(),
+                platforms: platforms.to_vec(),
+                published: Local::now().to_rfc3339(),
+                sbom: String::new(),
+            },
+        );
+    }
+
+    /// Attach the SBOM file, relative to the lock directory, to a recorded reference.
+    pub fn set_sbom(&mut self, reference: &str, sbom: &str) {
+        if let Some(entry) = self.entries.get_mut(reference) {
+            entry.sbom = sbom.to_string();
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.entries.iter()
+    }
+
+    /// # Errors
+    ///
+    /// When the lock file cannot be written.
+    pub fn save(&self) -> Result<(), Error> {
+        let mut table = Table::new();
+        for (reference, entry) in &self.entries {
+            let mut e = Table::new();
+            e.insert("context".into(), Value::String(entry.context.clone()));
+            e.insert("digest".into(), Value::String(entry.digest.clone()));
+            if entry.platforms.is_empty().eq(&false) {
+                e.insert(
+                    "platforms".into(),
+                    Value::Array(entry.platforms.iter().cloned().map(Value::String).collect()),
+                );
+            }
+            e.insert("published".into(), Value::String(entry.published.clone()));
+            if entry.sbom.is_empty().eq(&false) {
+                e.insert("sbom".into(), Value::String(entry.sbom.clone()));
+            }
+            table.insert(reference.clone(), Value::Table(e));
+        }
+        let content = toml::to_string(&table).map_err(|e| Error::other(e.to_string()))?;
+        write(
+            &self.path,
+            format!("# Generated by docks, records what has been published\n{content}"),
+        )
+    }
+}
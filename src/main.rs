@@ -1,41 +1,76 @@
 use chrono::Local;
-use inquire::{Confirm, Select, Text};
+use docks::backup::{Backup, Snapshot};
+use docks::build;
+use docks::compose::{self, Compose, Finding, Level, Rules};
+use docks::config;
+use docks::cron::{self, Crontab};
+use docks::editor::{self, Editor};
+use docks::gc::{self, Host, Protected};
+use docks::hub::{self, Hub};
+use docks::lock::Lock;
+use docks::migrate;
+use docks::schema;
+use docks::secrets::{self, Secrets, Store};
+use docks::sign::Signing;
+use docks::vuln::{self, Database};
+use docks::{log, plan, sbom, LOG_WITHOUT_SPACE, LOG_WITH_SPACE};
+use inquire::{Confirm, MultiSelect, Password, Select, Text};
 use is_executable::IsExecutable;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env::{current_dir, set_current_dir, var};
 use std::fs::{create_dir_all, File};
 use std::io::Write;
-use std::path::{Path, MAIN_SEPARATOR_STR};
-use std::process::{ExitStatus, Stdio};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR_STR};
+use std::process::{ExitCode, ExitStatus, Stdio};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 use std::{
-    fs::read_to_string,
     io,
     io::{Error, ErrorKind},
     process::Command,
 };
 use toml::Value;
-pub const TASKS: [&str; 30] = [
+pub const TASKS: [&str; 51] = [
     "init",
     "build",
+    "cache usage",
+    "cache prune",
     "clear",
     "check",
     "cd",
     "commit",
     "os",
     "deploy",
+    "migrate",
+    "lint",
     "enter",
     "exit",
     "edit",
+    "configure",
     "editor",
     "ls",
     "show containers",
     "show volumes",
+    "backup",
+    "restore",
+    "cron",
     "show networks",
+    "show config",
+    "migrate config",
+    "validate",
+    "export schemas",
     "login",
     "mkdir",
     "publish",
+    "hub prune",
+    "prune",
+    "sbom",
+    "scan image",
+    "import vulnerabilities",
+    "signing key",
+    "secrets set",
+    "secrets get",
+    "secrets list",
     "logout",
     "pull",
     "ps",
@@ -49,9 +84,6 @@ pub const TASKS: [&str; 30] = [
     "ps",
 ];
 
-const LOG_WITHOUT_SPACE: &str = "";
-const LOG_WITH_SPACE: &str = " ";
-
 fn docker(verb: &str, args: &[&str], path: &str) -> Result<(), Error> {
     if let Ok(mut child) = Command::new("docker")
         .arg(verb)
@@ -63,10 +95,7 @@ fn docker(verb: &str, args: &[&str], path: &str) -> Result<(), Error> {
             if status.success() {
                 return Ok(());
             }
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Docker exited with status no 0",
-            ));
+            return Err(Error::other("Docker exited with status no 0"));
         }
     }
     Err(Error::new(ErrorKind::NotFound, "docker not found"))
@@ -146,6 +175,18 @@ fn jump() {
 fn cd(dir: &str) -> io::Result<()> {
     set_current_dir(dir)
 }
+fn ssh_status(args: &[&str], user: &str, ip: &str) -> Result<bool, Error> {
+    if let Ok(mut cmd) = Command::new("ssh")
+        .arg(format!("{user}@{ip}").as_str())
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        return cmd.wait().map(|status| status.success());
+    }
+    Err(Error::new(ErrorKind::NotFound, "ssh not found"))
+}
 fn ssh_run(args: &[&str], user: &str, ip: &str) -> Result<(), Error> {
     if let Ok(mut cmd) = Command::new("ssh")
         .arg(format!("{user}@{ip}").as_str())
@@ -153,13 +194,31 @@ fn ssh_run(args: &[&str], user: &str, ip: &str) -> Result<(), Error> {
         .spawn()
     {
         return match cmd.wait() {
-            Ok(_) => Ok(()),
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(Error::other("ssh exited with status no 0")),
             Err(e) => Err(e),
         };
     }
     Err(Error::new(ErrorKind::NotFound, "ssh not found"))
 }
 
+/// Write `content` to `path` on the server with only the owner able to read it.
+fn ssh_write(content: &str, path: &str, user: &str, ip: &str) -> Result<(), Error> {
+    let mut child = Command::new("ssh")
+        .arg(format!("{user}@{ip}").as_str())
+        .arg(format!("umask 077 && cat > '{path}'"))
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "ssh not found"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    if child.wait()?.success() {
+        return Ok(());
+    }
+    Err(Error::other("ssh exited with status no 0"))
+}
+
 fn list_networks() -> Result<(), Error> {
     docker("network", &["ls"], "/tmp")
 }
@@ -184,7 +243,8 @@ fn upload_image(user: &str, ip: &str, image: &str, port: &str) -> Result<(), Err
         .spawn()
     {
         return match cmd.wait() {
-            Ok(_) => Ok(()),
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(Error::other("rsync exited with status no 0")),
             Err(e) => Err(e),
         };
     }
@@ -200,7 +260,7 @@ fn clear() -> Result<(), Error> {
 }
 fn login() -> Result<(), Error> {
     let username = Text::new("Please enter your docker username : ")
-        .with_default(env!("USER"))
+        .with_default(var("USER").unwrap_or_default().as_str())
         .prompt()
         .unwrap_or_default();
     if docker("login", &["-u", username.as_str()], "/tmp").is_ok() {
@@ -222,8 +282,8 @@ fn servers() -> Result<Vec<String>, Error> {
     let mut servers: Vec<String> = Vec::new();
     if let Ok(config) = configuration() {
         if let Some(tables) = config.as_table() {
-            for (server_name, _) in tables {
-                if server_name.ne("local") {
+            for (server_name, server) in tables {
+                if server_name.ne("local") && server.get("ip").is_some() {
                     servers.push(server_name.to_string());
                 }
             }
@@ -259,19 +319,24 @@ fn ssh() -> Result<ExitStatus, Error> {
     Err(Error::new(ErrorKind::NotFound, "ssh not found"))
 }
 fn configuration() -> Result<Value, Error> {
-    if let Ok(conf) = read_to_string("docks.toml") {
-        if let Ok(config) = toml::from_str::<Value>(&conf) {
-            return Ok(config);
-        }
-        return Err(Error::last_os_error());
-    }
-    Err(Error::last_os_error())
+    config::Layered::load(Path::new(config::CONFIG_FILE), config::profile().as_deref())
+        .map(|layered| layered.value)
 }
-fn log(message: &str, t: &str) {
-    println!(
-        "{}",
-        format!("\x1b[1;32m{t}\x1b[0;37m {message}\x1b[0m").as_str()
+
+/// Print the config of the active profile, every value with the file it comes from.
+fn show_config() -> Result<(), Error> {
+    let profile = config::profile();
+    let layered = config::Layered::load(Path::new(config::CONFIG_FILE), profile.as_deref())?;
+    log(
+        format!(
+            "Configuration of the {} profile",
+            profile.as_deref().unwrap_or("default")
+        )
+        .as_str(),
+        LOG_WITH_SPACE,
     );
+    println!("{}", layered.describe());
+    Ok(())
 }
 fn cmd(program: &str, args: &[&str]) -> Result<ExitStatus, Error> {
     if let Ok(mut child) = Command::new(program).args(args).current_dir(".").spawn() {
@@ -317,43 +382,395 @@ fn build() -> Result<(), Error> {
     }
     Err(Error::new(ErrorKind::NotFound, "Dockerfile not found"))
 }
+fn builders() -> Vec<&'static str> {
+    if build::has_builder() {
+        return vec!["default", build::BUILDER];
+    }
+    vec!["default"]
+}
+
+fn cache_usage() -> Result<(), Error> {
+    for builder in builders() {
+        log(
+            format!("Build cache of the {builder} builder").as_str(),
+            LOG_WITHOUT_SPACE,
+        );
+        if builder.eq("default") {
+            docker("buildx", &["du"], "/tmp")?;
+        } else {
+            docker("buildx", &["du", "--builder", builder], "/tmp")?;
+        }
+    }
+    Ok(())
+}
+
+fn cache_prune() -> Result<(), Error> {
+    assert!(cache_usage().is_ok());
+    let hours = Text::new("Keep the cache used in the last hours :")
+        .with_default("168")
+        .prompt()
+        .unwrap_or_default();
+    if hours.parse::<u64>().is_err() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the hours must be a number",
+        ));
+    }
+    if Confirm::new(format!("remove the build cache older than {hours} hours ?").as_str())
+        .with_default(false)
+        .prompt()
+        .unwrap_or_default()
+        .eq(&false)
+    {
+        return Ok(());
+    }
+    let until = format!("until={hours}h");
+    for builder in builders() {
+        let mut args = vec!["prune", "-f", "--filter", until.as_str()];
+        if builder.ne("default") {
+            args.extend(["--builder", builder]);
+        }
+        docker("buildx", &args, "/tmp")?;
+        log(
+            format!("The build cache of the {builder} builder has been pruned").as_str(),
+            LOG_WITHOUT_SPACE,
+        );
+    }
+    Ok(())
+}
+
 fn list_container() -> Result<(), Error> {
     docker("container", &["ls"], "/tmp")
 }
 
-fn deploy_local() -> Result<(), Error> {
+fn lint_report(target: &str, composes: &[Compose], interactive: bool) -> Result<(), Error> {
+    let mut errors = false;
+    let mut warnings = false;
+    for compose in composes {
+        for finding in &compose.findings {
+            log(
+                format!("{} {finding}", compose.path.display()).as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+        if compose.images.is_empty().eq(&false) {
+            log(
+                format!(
+                    "{} uses {}",
+                    compose.path.display(),
+                    compose.images.join(", ")
+                )
+                .as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+        errors |= compose.has_errors();
+        warnings |= compose.has_warnings();
+    }
+    if errors {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("compose files for {target} have errors"),
+        ));
+    }
+    if warnings
+        && interactive
+        && Confirm::new(format!("deploy on {target} despite the warnings ?").as_str())
+            .with_default(false)
+            .prompt()
+            .unwrap_or_default()
+            .eq(&false)
+    {
+        return Err(Error::new(
+            ErrorKind::Interrupted,
+            format!("deployment on {target} cancelled"),
+        ));
+    }
+    Ok(())
+}
+
+fn output(program: &str, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|_| Error::new(ErrorKind::NotFound, format!("{program} not found")))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+    Err(Error::other(format!("{program} exited with status no 0")))
+}
+
+fn host_platform(remote: Option<(&str, &str, &str)>) -> Option<String> {
+    let info = [
+        "docker",
+        "info",
+        "--format",
+        "{{.OSType}} {{.Architecture}}",
+    ];
+    let (host, line) = match remote {
+        Some((user, ip, port)) => {
+            let target = format!("{user}@{ip}");
+            let quoted = info.iter().map(|arg| gc::quote(arg)).collect::<Vec<_>>();
+            let mut args = vec!["-p", port, target.as_str()];
+            args.extend(quoted.iter().map(String::as_str));
+            (ip, output("ssh", &args))
+        }
+        None => ("local", output(info[0], &info[1..])),
+    };
+    let platform = line.and_then(|line| {
+        line.split_once(' ')
+            .map(|(os, architecture)| build::platform(os, architecture))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unexpected {line}")))
+    });
+    match platform {
+        Ok(platform) => Some(platform),
+        Err(e) => {
+            log(
+                format!("Cannot detect the platform of the {host} host: {e}").as_str(),
+                LOG_WITH_SPACE,
+            );
+            None
+        }
+    }
+}
+
+fn platform_warnings(composes: &mut [Compose], platform: Option<String>) {
+    let Some(platform) = platform else {
+        return;
+    };
+    let Ok(lock) = Lock::load(Path::new(".")) else {
+        return;
+    };
+    for compose in composes.iter_mut() {
+        for image in compose.images.clone() {
+            let reference = if image.rsplit('/').next().unwrap_or_default().contains(':') {
+                image.to_string()
+            } else {
+                format!("{image}:latest")
+            };
+            if let Some(entry) = lock.get(reference.as_str()) {
+                if entry.platforms.is_empty().eq(&false)
+                    && entry.platforms.contains(&platform).eq(&false)
+                {
+                    compose.findings.push(Finding {
+                        level: Level::Warn,
+                        service: String::new(),
+                        message: format!(
+                            "image {image} is published for {} but the host runs {platform}",
+                            entry.platforms.join(", ")
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn lint_local(
+    containers: &[String],
+    rules: &Rules,
+    secrets: &Secrets,
+) -> Result<Vec<Compose>, Error> {
+    let mut composes: Vec<Compose> = Vec::new();
+    for container in containers {
+        let dir = PathBuf::from(format!("./containers/{container}"));
+        if dir.join(compose::COMPOSE_FILE).is_file().eq(&false) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{}/{} not found", dir.display(), compose::COMPOSE_FILE),
+            ));
+        }
+        let exists = |source: &str| {
+            let source = source.strip_prefix("~/").map_or_else(
+                || dir.join(source),
+                |s| PathBuf::from(var("HOME").unwrap_or_default()).join(s),
+            );
+            source.exists()
+        };
+        let values = secrets.get(container).cloned().unwrap_or_default();
+        composes.push(compose::lint_with(dir.as_path(), rules, &exists, &values)?);
+    }
+    platform_warnings(&mut composes, host_platform(None));
+    Ok(composes)
+}
+
+fn lint_remote(
+    containers: &[String],
+    rules: &Rules,
+    secrets: &Secrets,
+    username: &str,
+    ip: &str,
+    port: &str,
+) -> Result<Vec<Compose>, Error> {
+    let public = var("DOCKS_PUBLIC_DIR").expect("missing DOCKS_PUBLIC_DIR");
+    let mut composes: Vec<Compose> = Vec::new();
+    for container in containers {
+        let dir = PathBuf::from(format!("{public}/{container}"));
+        let exists = |source: &str| {
+            let source = source.strip_prefix("~/").map_or_else(
+                || {
+                    if source.starts_with('/') {
+                        source.to_string()
+                    } else {
+                        format!("{container}/{source}")
+                    }
+                },
+                |s| format!("$HOME/{s}"),
+            );
+            ssh_status(
+                &["test", "-e", format!("\"{source}\"").as_str()],
+                username,
+                ip,
+            )
+            .unwrap_or(false)
+        };
+        let values = secrets.get(container).cloned().unwrap_or_default();
+        composes.push(compose::lint_with(dir.as_path(), rules, &exists, &values)?);
+    }
+    platform_warnings(&mut composes, host_platform(Some((username, ip, port))));
+    Ok(composes)
+}
+
+fn lint() -> Result<(), Error> {
+    let docks = configuration()?;
+    let rules = Rules::from_config(&docks);
+    let containers = docks
+        .get("local")
+        .and_then(|local| local.get("containers"))
+        .and_then(Value::as_array)
+        .map(|c| {
+            c.iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    let composes = lint_local(&containers, &rules, &load_secrets(&docks)?)?;
+    let report = lint_report("local", &composes, false);
+    let errors = composes
+        .iter()
+        .flat_map(|c| c.findings.iter())
+        .filter(|f| f.level.eq(&Level::Error))
+        .count();
+    log(
+        format!("{} compose files checked, {errors} errors", composes.len()).as_str(),
+        LOG_WITH_SPACE,
+    );
+    report
+}
+
+/// Restart `container` locally, its `secrets` are given to docker compose through its
+/// environment and never written to disk.
+fn compose_local(container: &str, secrets: Option<&BTreeMap<String, String>>) -> Result<(), Error> {
+    let x = format!("./containers/{container}");
+    log(
+        format!("Deploying {container} docker container locally").as_str(),
+        LOG_WITH_SPACE,
+    );
+    let files = compose::files(Path::new(x.as_str()))
+        .into_iter()
+        .flat_map(|file| [String::from("-f"), file])
+        .collect::<Vec<String>>();
+    let compose = |args: &[&str]| {
+        Command::new("docker")
+            .arg("compose")
+            .args(files.as_slice())
+            .args(args)
+            .envs(secrets.into_iter().flatten())
+            .current_dir(x.as_str())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if compose(&["down"]).eq(&false) {
+        return Err(Error::other(format!("fail to stop {container}")));
+    }
+    if compose(&["pull"]).eq(&false) {
+        return Err(Error::other(format!("fail to update {container}")));
+    }
+    if compose(&["up", "--remove-orphans", "-d"]).eq(&false) {
+        return Err(Error::other(format!("fail to start {container}")));
+    }
+    log(
+        format!("The {container} has been restarted successfully").as_str(),
+        LOG_WITH_SPACE,
+    );
+    Ok(())
+}
+
+/// Check the signatures of the images used by `composes`, the returned containers use an
+/// unsigned image and must not be deployed.
+fn signatures(
+    docks: &Value,
+    target: &str,
+    names: &[String],
+    composes: &[Compose],
+    interactive: bool,
+    allow_unsigned: bool,
+) -> Result<HashMap<String, String>, Error> {
+    let mut refused: HashMap<String, String> = HashMap::new();
+    let Some(signing) = Signing::from_config(docks, Path::new("."))? else {
+        return Ok(refused);
+    };
+    for (name, compose) in names.iter().zip(composes) {
+        for image in compose.images.iter().filter(|image| signing.applies(image)) {
+            if let Err(e) = signing.verify(image) {
+                log(format!("{name} on {target}: {e}").as_str(), LOG_WITH_SPACE);
+                refused.entry(name.to_string()).or_insert(e.to_string());
+            }
+        }
+    }
+    if refused.is_empty() {
+        return Ok(refused);
+    }
+    if allow_unsigned
+        || (interactive
+            && Confirm::new(format!("deploy the unsigned images on {target} anyway ?").as_str())
+                .with_default(false)
+                .prompt()
+                .unwrap_or_default())
+    {
+        log(
+            format!("Deploying unsigned images on {target}").as_str(),
+            LOG_WITH_SPACE,
+        );
+        return Ok(HashMap::new());
+    }
+    Ok(refused)
+}
+
+fn deploy_local(interactive: bool, allow_unsigned: bool) -> Result<(), Error> {
     if let Ok(docks) = configuration() {
         if let Some(table) = docks.as_table() {
             if let Some(local) = table.get("local") {
                 let containers = local.get("containers").unwrap().as_array().unwrap();
-                for container in containers {
-                    let x = format!("./containers/{}", container.as_str().unwrap_or_default());
-                    let xf = format!(
-                        "./containers/{}/compose.yaml",
-                        container.as_str().unwrap_or_default()
-                    );
-                    if Path::new(x.as_str()).is_dir() && Path::new(&xf).is_file() {
-                        assert!(
-                            docker("compose", &["down"], x.as_str()).is_ok(),
-                            "fail to stop container"
-                        );
-                        assert!(
-                            docker("compose", &["pull"], x.as_str()).is_ok(),
-                            "fail to update container"
-                        );
-                        assert!(
-                            docker("compose", &["up", "--remove-orphans", "-d"], x.as_str())
-                                .is_ok(),
-                            "fail to start container"
-                        );
-                    } else {
-                        return Err(Error::new(
-                            ErrorKind::NotFound,
-                            "docker container is not a dir",
-                        ));
-                    }
-                }
-                return Ok(());
+                let names = containers
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect::<Vec<String>>();
+                let secrets = load_secrets(&docks)?;
+                let composes = lint_local(&names, &Rules::from_config(&docks), &secrets)?;
+                lint_report("local", &composes, interactive)?;
+                let refused = signatures(
+                    &docks,
+                    "local",
+                    &names,
+                    &composes,
+                    interactive,
+                    allow_unsigned,
+                )?;
+                let dependencies = plan::dependencies(&docks, &names, &composes);
+                let stages = plan::stages(&names, &dependencies)?;
+                return deploy_stages(
+                    "local",
+                    plan::run(&stages, &dependencies, usize::MAX, |container| {
+                        if let Some(reason) = refused.get(container) {
+                            return Err(Error::new(ErrorKind::PermissionDenied, reason.as_str()));
+                        }
+                        compose_local(container, secrets.get(container))
+                    }),
+                );
             }
             return Err(Error::new(ErrorKind::NotFound, "missing local id"));
         }
@@ -379,12 +796,47 @@ fn server_founded(servers: usize) {
     }
 }
 
-fn manage_remote_container(image: &str, server: &str, ip: &str, port: &str, username: &str) {
+fn manage_remote_container(
+    image: &str,
+    server: &str,
+    ip: &str,
+    port: &str,
+    username: &str,
+    secrets: Option<&BTreeMap<String, String>>,
+) -> Result<(), Error> {
     log(
         format!("Deploying {image} docker container on {server} server").as_str(),
         LOG_WITH_SPACE,
     );
-    assert!(upload_image(username, ip, image, port).is_ok());
+    upload_image(username, ip, image, port)?;
+    let public = var("DOCKS_PUBLIC_DIR").unwrap_or_default();
+    let files = compose::files(Path::new(format!("{public}/{image}").as_str()))
+        .into_iter()
+        .flat_map(|file| [String::from("-f"), format!("{image}/{file}")])
+        .collect::<Vec<String>>();
+    let files = files.iter().map(String::as_str).collect::<Vec<&str>>();
+    let env = format!("{image}/{}", secrets::ENV_FILE);
+    let mut prefix: Vec<&str> = Vec::new();
+    if let Some(secrets) = secrets.filter(|s| s.is_empty().eq(&false)) {
+        ssh_write(
+            secrets::env_file(secrets).as_str(),
+            env.as_str(),
+            username,
+            ip,
+        )
+        .map_err(|_| Error::other(format!("Failed to send the secrets of {image} to {server}")))?;
+        prefix.extend([
+            "set",
+            "-a",
+            "&&",
+            ".",
+            env.as_str(),
+            "&&",
+            "set",
+            "+a",
+            "&&",
+        ]);
+    }
     log(
         format!("The {image} has been deployed successfully on the {server} server").as_str(),
         LOG_WITH_SPACE,
@@ -393,28 +845,34 @@ fn manage_remote_container(image: &str, server: &str, ip: &str, port: &str, user
         format!("Stopping {image} before update on the {server} server").as_str(),
         LOG_WITH_SPACE,
     );
-    assert!(
-        ssh_run(
-            &["docker", "compose", "--project-directory", image, "down",],
-            username,
-            ip,
-        )
-        .is_ok(),
-        "Failed to stop container"
-    );
+    ssh_run(
+        &[
+            prefix.as_slice(),
+            &["docker", "compose", "--project-directory", image],
+            files.as_slice(),
+            &["down"],
+        ]
+        .concat(),
+        username,
+        ip,
+    )
+    .map_err(|_| Error::other(format!("Failed to stop {image} on {server}")))?;
     log(
         format!("Updating the {image} container on the {server} server").as_str(),
         LOG_WITH_SPACE,
     );
-    assert!(
-        ssh_run(
-            &["docker", "compose", "--project-directory", image, "pull"],
-            username,
-            ip
-        )
-        .is_ok(),
-        "Failed to update container"
-    );
+    ssh_run(
+        &[
+            prefix.as_slice(),
+            &["docker", "compose", "--project-directory", image],
+            files.as_slice(),
+            &["pull"],
+        ]
+        .concat(),
+        username,
+        ip,
+    )
+    .map_err(|_| Error::other(format!("Failed to update {image} on {server}")))?;
     log(
         format!("The {image} container has been updated successfully on the {server} server")
             .as_str(),
@@ -424,30 +882,45 @@ fn manage_remote_container(image: &str, server: &str, ip: &str, port: &str, user
         format!("Restarting the {image} after upgrade on the {server} server").as_str(),
         LOG_WITH_SPACE,
     );
-    assert!(
-        ssh_run(
-            &[
-                "docker",
-                "compose",
-                "--project-directory",
-                image,
-                "--remove-orphans",
-                "up",
-                "-d",
-            ],
-            username,
-            ip
-        )
-        .is_ok(),
-        "Failed to start the container"
-    );
+    ssh_run(
+        &[
+            prefix.as_slice(),
+            &["docker", "compose", "--project-directory", image],
+            files.as_slice(),
+            &["up", "--remove-orphans", "-d"],
+        ]
+        .concat(),
+        username,
+        ip,
+    )
+    .map_err(|_| Error::other(format!("Failed to start {image} on {server}")))?;
     log(
         format!("The {image} has been restarted successfully on the {server} server").as_str(),
         LOG_WITH_SPACE,
     );
+    Ok(())
+}
+
+fn deploy_stages(target: &str, failed: Vec<(String, Error)>) -> Result<(), Error> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    for (container, e) in &failed {
+        log(
+            format!("{container} on {target}: {e}").as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    Err(Error::other(format!(
+        "{} containers not deployed on {target}",
+        failed.len()
+    )))
 }
-fn deploy_to_remote() -> Result<(), Error> {
+
+fn deploy_to_remote(interactive: bool, allow_unsigned: bool) -> Result<(), Error> {
     if let Ok(docks) = configuration() {
+        let rules = Rules::from_config(&docks);
+        let secrets = load_secrets(&docks)?;
         if let Ok(servers) = servers() {
             server_founded(servers.len());
             for server in &servers {
@@ -480,10 +953,42 @@ fn deploy_to_remote() -> Result<(), Error> {
                             );
                             continue;
                         }
-                        for container in containers {
-                            let image = container.as_str().unwrap();
-                            manage_remote_container(image, server, ip, port, username);
-                        }
+                        let names = containers
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(String::from)
+                            .collect::<Vec<String>>();
+                        let composes = lint_remote(&names, &rules, &secrets, username, ip, port)?;
+                        lint_report(server, &composes, interactive)?;
+                        let refused = signatures(
+                            &docks,
+                            server,
+                            &names,
+                            &composes,
+                            interactive,
+                            allow_unsigned,
+                        )?;
+                        let dependencies = plan::dependencies(&docks, &names, &composes);
+                        let stages = plan::stages(&names, &dependencies)?;
+                        deploy_stages(
+                            server,
+                            plan::run(&stages, &dependencies, usize::MAX, |image| {
+                                if let Some(reason) = refused.get(image) {
+                                    return Err(Error::new(
+                                        ErrorKind::PermissionDenied,
+                                        reason.as_str(),
+                                    ));
+                                }
+                                manage_remote_container(
+                                    image,
+                                    server,
+                                    ip,
+                                    port,
+                                    username,
+                                    secrets.get(image),
+                                )
+                            }),
+                        )?;
                     }
                 }
             }
@@ -493,19 +998,21 @@ fn deploy_to_remote() -> Result<(), Error> {
     Err(Error::new(ErrorKind::NotFound, "docks.toml not found"))
 }
 
-fn deploy() {
+fn deploy(interactive: bool, allow_unsigned: bool) -> Result<(), Error> {
     let now = Instant::now();
     let date = Local::now();
     log(
         format!("Starting deployment at {date}").as_str(),
         LOG_WITH_SPACE,
     );
-    assert!(deploy_local().is_ok());
-    assert!(deploy_to_remote().is_ok());
+    configuration()?;
+    deploy_local(interactive, allow_unsigned)?;
+    deploy_to_remote(interactive, allow_unsigned)?;
     log(
         format!("The deployment take {} secs", now.elapsed().as_secs()).as_str(),
         LOG_WITH_SPACE,
     );
+    Ok(())
 }
 
 fn editor() -> Result<(), Error> {
@@ -766,11 +1273,24 @@ fn list_volumes() -> Result<(), Error> {
 fn list_images() -> Result<(), Error> {
     docker("images", &[], "/tmp")
 }
-fn main() {
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().collect();
+    if let Some(at) = args.iter().position(|arg| arg.eq("--profile")) {
+        let Some(profile) = args.get(at + 1).cloned() else {
+            log("--profile needs a name", LOG_WITHOUT_SPACE);
+            return ExitCode::FAILURE;
+        };
+        std::env::set_var(config::PROFILE_VAR, profile);
+        args.drain(at..=at + 1);
+    }
+    if args.len().gt(&1) {
+        return run(&args[1..]);
+    }
     assert!(clear().is_ok());
     assert!(Path::new("/usr/bin/ranger").is_executable());
-    if let Ok(dir) = var("DOCKS_WORKING_DIR") {
+    if let Some(dir) = project_root() {
         assert!(set_current_dir(dir).is_ok());
+        warn_outdated();
         loop {
             let project = current_dir().map_or_else(
                 |_| String::from("."),
@@ -796,10 +1316,22 @@ fn main() {
                 "login" => assert!(login().is_ok()),
                 "logout" => assert!(logout().is_ok()),
                 "clear" => assert!(clear().is_ok()),
-                "deploy" => deploy(),
+                "deploy" => assert!(deploy(true, false).is_ok()),
+                "cache usage" => assert!(cache_usage().is_ok()),
+                "cache prune" => assert!(cache_prune().is_ok()),
+                "lint" => {
+                    if let Err(e) = lint() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
                 "check" => assert!(dock_running().is_ok()),
                 "cd" => jump(),
                 "edit" => assert!(edit().is_ok()),
+                "configure" => {
+                    if let Err(e) = configure() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
                 "enter" => enter(),
                 "ssh" => assert!(ssh().is_ok()),
                 "stop" => assert!(stop().is_ok()),
@@ -809,6 +1341,26 @@ fn main() {
                 "show containers" => assert!(list_container().is_ok()),
                 "show volumes" => assert!(list_volumes().is_ok()),
                 "show networks" => assert!(list_networks().is_ok()),
+                "validate" => {
+                    if let Err(e) = validate() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "export schemas" => {
+                    if let Err(e) = export_schemas() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "migrate config" => {
+                    if let Err(e) = migrate_config(false, true) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "show config" => {
+                    if let Err(e) = show_config() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
                 "ls" => ls(),
                 "os" => os(),
                 "start" => assert!(start().is_ok()),
@@ -818,36 +1370,384 @@ fn main() {
                 "ps" => assert!(ps().is_ok()),
                 "pull" => pull(),
                 "build" => assert!(build().is_ok()),
-                "publish" => publish(),
+                "publish" => {
+                    let username = Text::new("username : ")
+                        .with_default(
+                            configuration()
+                                .ok()
+                                .and_then(|c| Hub::from_config(&c, Path::new(".")).ok())
+                                .map(|hub| hub.username)
+                                .unwrap_or_default()
+                                .as_str(),
+                        )
+                        .prompt()
+                        .unwrap();
+                    let force = Confirm::new("rebuild the unchanged images too ?")
+                        .with_default(false)
+                        .prompt()
+                        .unwrap_or_default();
+                    assert!(clear().is_ok());
+                    if let Err(e) = publish(Some(username), force, None) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "sbom" => {
+                    let reference = Text::new("image (user/image:tag) : ")
+                        .prompt()
+                        .unwrap_or_default();
+                    if let Err(e) = sbom::print(Path::new("."), reference.as_str()) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "hub prune" => {
+                    if let Err(e) = hub_prune(false) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "migrate" => {
+                    if let Err(e) = migrate_task() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "backup" => {
+                    let container = Text::new("container to backup : ")
+                        .prompt()
+                        .unwrap_or_default();
+                    let host =
+                        configuration().and_then(|docks| ask_host(&docks, container.as_str()));
+                    if let Err(e) =
+                        host.and_then(|host| backup(container.as_str(), host.as_deref(), None))
+                    {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "restore" => {
+                    if let Err(e) = restore(None, None) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "cron" => {
+                    if let Err(e) = cron_task() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "prune" => {
+                    let days = Text::new("remove unused images older than (days) : ")
+                        .with_default("30")
+                        .prompt()
+                        .ok()
+                        .and_then(|days| days.parse::<i64>().ok())
+                        .unwrap_or(30);
+                    if let Err(e) = prune(days, false) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "scan image" => {
+                    if let Err(e) = scan_image(None) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "import vulnerabilities" => {
+                    let source = Text::new("OSV json file or directory : ")
+                        .prompt()
+                        .unwrap_or_default();
+                    if let Err(e) = import_vulnerabilities(source.as_str()) {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "secrets set" | "secrets get" => {
+                    let scope = Text::new("container, or registry : ")
+                        .prompt()
+                        .unwrap_or_default();
+                    let key = Text::new("variable : ").prompt().unwrap_or_default();
+                    let result = if selected.eq("secrets set") {
+                        secrets_set(scope.as_str(), key.as_str(), None)
+                    } else {
+                        secrets_get(scope.as_str(), key.as_str())
+                    };
+                    if let Err(e) = result {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "secrets list" => {
+                    if let Err(e) = secrets_list() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
+                "signing key" => {
+                    if let Err(e) = signing_key() {
+                        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+                    }
+                }
                 "editor" => assert!(editor().is_ok()),
                 "exit" => break,
                 _ => continue,
             }
         }
     } else {
-        log("$DOCKS_WORKING_DIR not founded", LOG_WITHOUT_SPACE);
+        log(
+            "no docks.toml in the current directory or its parents, and $DOCKS_WORKING_DIR not founded",
+            LOG_WITHOUT_SPACE,
+        );
     }
     log("Bye", LOG_WITHOUT_SPACE);
+    ExitCode::SUCCESS
 }
 
-fn init() -> io::Result<()> {
-    let mut f = File::create("docks.toml")?;
-    writeln!(f, "# Docker user information (optional)\n[docker]\nusername = \"\"\nemail = \"\"\n\n# Private registry information (if you are using one)\n[registry]\n# url = \"your_registry_url\"  # Uncomment and fill in if necessary\n# username = \"your_username\"  # Uncomment and fill in if necessary\n# password = \"your_password\"  # Uncomment and fill in if necessary\n\n# SSH settings for remote deployment\n[ssh]\nport = 22\nuser = \"root\"\n\n# Docker image tag configuration\n[hub]\ntags = [[\"version\", [\"stable\", \"beta\", \"nightly\", \"latest\"]],[\"env\", [\"staging\", \"dev\", \"prod\"]],[\"schedule\", [\"hourly\", \"daily\", \"weekly\", \"monthly\"]]]\n\n# List of Docker images to build\n[[hub.images]]\nname = \"\"\ntags = []\npath = \"./rlang\" # Path relative to the configuration file directory\npath = \"\"\n\n[deploy]\nlocal = []\n\n[deploy.remotes]\n")
-}
-
-fn enter() {
-    assert!(list_container().is_ok());
-    let image = Text::new("please enter the image to enter :")
-        .prompt()
-        .unwrap();
-    let _ = docker("run", &["-i", "-t", image.as_str()], "/tmp");
+/// Warn when docks.toml uses the layout of an older release.
+fn warn_outdated() {
+    if let Some(version) = schema::outdated(Path::new(config::CONFIG_FILE)) {
+        log(
+            format!(
+                "docks.toml uses the version {version} layout, run docks migrate-config to upgrade it to version {}",
+                schema::VERSION
+            )
+            .as_str(),
+            LOG_WITHOUT_SPACE,
+        );
+    }
 }
 
-fn commit() -> Result<(), Error> {
-    assert!(list_container().is_ok());
-    let id = Text::new("please enter the id of the container to commit :")
-        .prompt()
-        .unwrap();
+/// Rewrite docks.toml and its included files to the current layout, asking first when
+/// `confirm` is set.
+fn migrate_config(dry_run: bool, confirm: bool) -> Result<(), Error> {
+    let path = Path::new(config::CONFIG_FILE);
+    let changes = schema::migrate(path, true)?;
+    if changes.is_empty() {
+        log(
+            format!(
+                "docks.toml already uses the version {} layout",
+                schema::VERSION
+            )
+            .as_str(),
+            LOG_WITH_SPACE,
+        );
+        return Ok(());
+    }
+    for (file, changes) in &changes {
+        for change in changes {
+            log(
+                format!("{}: {change}", file.display()).as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+    }
+    if dry_run
+        || (confirm
+            && Confirm::new("rewrite these files ?")
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false)
+                .eq(&false))
+    {
+        return Ok(());
+    }
+    schema::migrate(path, false)?;
+    log(
+        format!("docks.toml uses the version {} layout", schema::VERSION).as_str(),
+        LOG_WITH_SPACE,
+    );
+    Ok(())
+}
+
+/// Check the config files of the project against their schemas, docks.toml merged with its
+/// includes and the overrides of the active profile, the other files as written.
+fn validate() -> Result<(), Error> {
+    let path = Path::new(config::CONFIG_FILE);
+    let layered = config::Layered::load(path, config::profile().as_deref())?;
+    let mut errors: Vec<String> = Vec::new();
+    if let Some(schema) = schema::schema(config::CONFIG_FILE) {
+        for (key, message) in schema::validate(&layered.value, &schema) {
+            let file = layered.origin(key.as_str()).unwrap_or(config::CONFIG_FILE);
+            errors.push(format!("{file}: {message}"));
+        }
+    }
+    let included = config::files(path)?;
+    let mut checked = included.len();
+    for file in schema::FILES {
+        let file = Path::new(file);
+        if file.is_file().eq(&false) || included.iter().any(|i| i.eq(file)) {
+            continue;
+        }
+        let Some(schema) = file.to_str().and_then(schema::schema) else {
+            continue;
+        };
+        let (value, unresolved) = config::raw(file)?;
+        for variable in unresolved {
+            log(
+                format!(
+                    "{}: {} uses ${{{}}}, which is not set",
+                    variable.file, variable.key, variable.name
+                )
+                .as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+        for (_, message) in schema::validate(&value, &schema) {
+            errors.push(format!("{}: {message}", file.display()));
+        }
+        checked += 1;
+    }
+    for error in &errors {
+        log(error.as_str(), LOG_WITH_SPACE);
+    }
+    log(
+        format!("{checked} config files checked, {} errors", errors.len()).as_str(),
+        LOG_WITH_SPACE,
+    );
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        "the config files have errors",
+    ))
+}
+
+/// Write the JSON Schemas of the config files and, when the project has none, a .taplo.toml
+/// associating them with the files for editor completion.
+fn export_schemas() -> Result<(), Error> {
+    for path in schema::export(Path::new(schema::SCHEMA_DIR))? {
+        log(
+            format!("{} written", path.display()).as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    let includes = config::load(Path::new(config::CONFIG_FILE))
+        .ok()
+        .and_then(|docks| {
+            docks
+                .get(config::INCLUDE)
+                .and_then(Value::as_array)
+                .map(|patterns| {
+                    patterns
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect::<Vec<String>>()
+                })
+        })
+        .unwrap_or_default();
+    let taplo = schema::taplo(schema::SCHEMA_DIR, &includes);
+    if Path::new(".taplo.toml").exists() {
+        log(
+            "a .taplo.toml exists, add these rules to it to use the schemas :",
+            LOG_WITH_SPACE,
+        );
+        println!("{taplo}");
+        return Ok(());
+    }
+    std::fs::write(".taplo.toml", taplo)?;
+    log(".taplo.toml written", LOG_WITH_SPACE);
+    Ok(())
+}
+
+/// The project root, found upward from the current directory, `$DOCKS_WORKING_DIR` otherwise.
+fn project_root() -> Option<PathBuf> {
+    current_dir()
+        .ok()
+        .and_then(|dir| config::root(dir.as_path()))
+        .or_else(|| var("DOCKS_WORKING_DIR").ok().map(PathBuf::from))
+}
+
+/// The value following the `name` flag in `args`.
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg.eq(name))
+        .and_then(|at| args.get(at + 1))
+        .map(String::as_str)
+}
+
+fn run(args: &[String]) -> ExitCode {
+    let Some(dir) = project_root() else {
+        log(
+            "no docks.toml in the current directory or its parents, and $DOCKS_WORKING_DIR not founded",
+            LOG_WITHOUT_SPACE,
+        );
+        return ExitCode::FAILURE;
+    };
+    assert!(set_current_dir(dir).is_ok());
+    if args[0].ne("migrate-config") {
+        warn_outdated();
+    }
+    let result = match args[0].as_str() {
+        "deploy" => deploy(false, args.iter().any(|arg| arg.eq("--allow-unsigned"))),
+        "lint" => lint(),
+        "show-config" => show_config(),
+        "validate" => validate(),
+        "schema" => export_schemas(),
+        "migrate-config" => migrate_config(args.iter().any(|arg| arg.eq("--dry-run")), false),
+        "publish" => publish(
+            None,
+            args.iter().any(|arg| arg.eq("--force")),
+            hub::jobs(args),
+        ),
+        "sbom" if args.len().eq(&2) => sbom::print(Path::new("."), args[1].as_str()),
+        "scan" if args.len().eq(&2) => scan_image(Some(args[1].clone())),
+        "hub-prune" => hub_prune(args.iter().any(|arg| arg.eq("--yes"))),
+        "migrate" if args.len().ge(&3) => migrate(
+            args[1].as_str(),
+            option(args, "--from"),
+            args[2].as_str(),
+            args.iter().any(|arg| arg.eq("--yes")),
+        ),
+        "backup" if args.len().ge(&2) => backup(
+            args[1].as_str(),
+            option(args, "--host"),
+            args.iter().any(|arg| arg.eq("--stop")).then_some(true),
+        ),
+        "restore" if args.len().ge(&2) => restore(
+            Some(PathBuf::from(args[1].as_str())),
+            option(args, "--host").map(String::from),
+        ),
+        "secrets" => secrets_command(args),
+        "cron" => cron(args.get(1).map(String::as_str)),
+        "prune" => prune(
+            args.iter()
+                .position(|arg| arg.eq("--days"))
+                .and_then(|at| args.get(at + 1))
+                .and_then(|days| days.parse::<i64>().ok())
+                .unwrap_or(30),
+            args.iter().any(|arg| arg.eq("--yes")),
+        ),
+        "import-vulnerabilities" if args.len().eq(&2) => import_vulnerabilities(args[1].as_str()),
+        _ => {
+            log(
+                format!(
+                    "unknown command {}, available: [--profile <name>] deploy, lint, show-config, migrate-config, validate, schema, publish, hub-prune, prune, migrate <container> <host>, backup <container>, restore <snapshot>, cron [job], secrets, sbom <user/image:tag>, scan <image>, import-vulnerabilities <path>",
+                    args[0]
+                )
+                .as_str(),
+                LOG_WITHOUT_SPACE,
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = result {
+        log(e.to_string().as_str(), LOG_WITHOUT_SPACE);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn init() -> io::Result<()> {
+    let mut f = File::create("docks.toml")?;
+    writeln!(f, "version = {}\n\n# Docker user information (optional)\n[docker]\nusername = \"\"\nemail = \"\"\n\n# Private registry information (if you are using one)\n[registry]\n# url = \"your_registry_url\"  # Uncomment and fill in if necessary\n# username = \"your_username\"  # Uncomment and fill in if necessary\n# password = \"your_password\"  # Uncomment and fill in if necessary\n\n# SSH settings for remote deployment\n[ssh]\nport = 22\nuser = \"root\"\n\n# Docker image tag configuration\n[hub]\ntags = [[\"version\", [\"stable\", \"beta\", \"nightly\", \"latest\"]],[\"env\", [\"staging\", \"dev\", \"prod\"]],[\"schedule\", [\"hourly\", \"daily\", \"weekly\", \"monthly\"]]]\n\n# List of Docker images to build\n[[hub.images]]\nname = \"\"\ntags = []\npath = \"\" # Path relative to the configuration file directory\n\n# Containers deployed locally, each server is a table with username, ip, port and containers\n[local]\ncontainers = []\n", schema::VERSION)
+}
+
+fn enter() {
+    assert!(list_container().is_ok());
+    let image = Text::new("please enter the image to enter :")
+        .prompt()
+        .unwrap();
+    let _ = docker("run", &["-i", "-t", image.as_str()], "/tmp");
+}
+
+fn commit() -> Result<(), Error> {
+    assert!(list_container().is_ok());
+    let id = Text::new("please enter the id of the container to commit :")
+        .prompt()
+        .unwrap();
     let image = Text::new("please enter the name of the new image :")
         .prompt()
         .unwrap();
@@ -863,66 +1763,879 @@ fn commit() -> Result<(), Error> {
         "Failed to commit image",
     ))
 }
-fn publish() {
-    let username = Text::new("username : ")
-        .with_default(var("USER").unwrap().as_str())
+fn publish(username: Option<String>, force: bool, jobs: Option<usize>) -> Result<(), Error> {
+    let mut hub = Hub::from_config(&configuration()?, Path::new("."))?;
+    if let Some(username) = username {
+        hub.username = username;
+    }
+    if let Some(jobs) = jobs {
+        hub.jobs = jobs;
+    }
+    hub::publish(&hub, force)?;
+    Ok(())
+}
+/// List the registry tags outside the retention policy, then delete them once confirmed or
+/// when `yes` is set.
+fn hub_prune(yes: bool) -> Result<(), Error> {
+    let docks = configuration()?;
+    let mut hub = Hub::from_config(&docks, Path::new("."))?;
+    if hub.account.password.is_empty() {
+        if let Some(password) = load_secrets(&docks)?
+            .get("registry")
+            .and_then(|registry| registry.get("password"))
+        {
+            hub.account.password.clone_from(password);
+        }
+    }
+    let protected = hub::deployed(&docks)?;
+    let pruned = hub::prune(&hub, &protected, false)?;
+    if pruned.is_empty() {
+        log("No tag to prune", LOG_WITHOUT_SPACE);
+        return Ok(());
+    }
+    let confirmed = yes
+        || Confirm::new(format!("delete these {} tags ?", pruned.len()).as_str())
+            .with_default(false)
+            .prompt()
+            .unwrap_or_default();
+    if confirmed.eq(&false) {
+        return Ok(());
+    }
+    let pruned = hub::prune(&hub, &protected, true)?;
+    log(
+        format!("{} tags deleted", pruned.len()).as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    Ok(())
+}
+
+/// Report what docker can reclaim locally and on every server, keeping what their deployed
+/// compose files use, then remove it host by host once confirmed or when `yes` is set.
+fn prune(days: i64, yes: bool) -> Result<(), Error> {
+    let docks = configuration()?;
+    let mut hosts: Vec<(Host, Vec<Compose>)> = Vec::new();
+    if docks.get("local").is_some() {
+        match compose::deployed(&docks, "local") {
+            Ok(composes) => hosts.push((Host::local(), composes)),
+            Err(e) => log(format!("local skipped, {e}").as_str(), LOG_WITH_SPACE),
+        }
+    }
+    for server in servers()? {
+        let Some(host) = Host::from_config(&docks, server.as_str()) else {
+            continue;
+        };
+        let ip = host.ssh.as_deref().and_then(|ssh| ssh.split('@').nth(1));
+        if check_connexion(ip.unwrap_or_default(), host.port.as_str()).is_err() {
+            log(
+                format!("Cannot communicate to the {server} server").as_str(),
+                LOG_WITH_SPACE,
+            );
+            continue;
+        }
+        match compose::deployed(&docks, server.as_str()) {
+            Ok(composes) => hosts.push((host, composes)),
+            Err(e) => log(format!("{server} skipped, {e}").as_str(), LOG_WITH_SPACE),
+        }
+    }
+    let mut summary: Vec<(String, u64, usize)> = Vec::new();
+    for (host, composes) in &hosts {
+        let report = match host.report(days, &Protected::from_composes(composes)) {
+            Ok(report) => report,
+            Err(e) => {
+                log(e.to_string().as_str(), LOG_WITH_SPACE);
+                continue;
+            }
+        };
+        if report.items.is_empty() {
+            log(
+                format!("Nothing to reclaim on {}", host.name).as_str(),
+                LOG_WITH_SPACE,
+            );
+            continue;
+        }
+        println!("{}", report.summary(host.name.as_str()));
+        let confirmed = yes
+            || Confirm::new(format!("remove them from {} ?", host.name).as_str())
+                .with_default(false)
+                .prompt()
+                .unwrap_or_default();
+        if confirmed.eq(&false) {
+            continue;
+        }
+        let (freed, failed) = host.remove(&report);
+        for (item, e) in &failed {
+            log(
+                format!("{} {} kept: {e}", item.kind, item.name).as_str(),
+                LOG_WITH_SPACE,
+            );
+        }
+        summary.push((host.name.clone(), freed, failed.len()));
+    }
+    for (host, freed, failed) in summary {
+        log(
+            format!("{host}: {} freed, {failed} failures", gc::human(freed)).as_str(),
+            LOG_WITHOUT_SPACE,
+        );
+    }
+    Ok(())
+}
+
+/// Choose a job of crontab.toml and run it now.
+fn cron_task() -> Result<(), Error> {
+    let crontab = Crontab::load(Path::new("."))?;
+    let names = crontab
+        .jobs
+        .iter()
+        .map(|job| job.name.clone())
+        .collect::<Vec<String>>();
+    let name = Select::new("job to run :", names)
         .prompt()
-        .unwrap();
-    if let Ok(public) = var("DOCKS_PUBLIC_DIR") {
-        for (image, tags) in &to_publish() {
-            for tag in tags {
-                assert!(docker(
-                    "buildx",
-                    &[
-                        "build",
-                        "-t",
-                        format!("{username}/{image}:{tag}").as_str(),
-                        format!("{public}/{image}/{tag}").as_str()
-                    ],
-                    "."
-                )
-                .is_ok());
-                assert!(
-                    docker("push", &[format!("{username}/{image}:{tag}").as_str()], ".").is_ok()
-                );
+        .map_err(|e| Error::other(e.to_string()))?;
+    cron(Some(name.as_str()))
+}
+
+/// Run the job `name`, or every job of crontab.toml due at this minute, meant to be called
+/// each minute by the system cron.
+fn cron(name: Option<&str>) -> Result<(), Error> {
+    let crontab = Crontab::load(Path::new("."))?;
+    let docks = configuration().unwrap_or_else(|_| Value::Table(toml::Table::new()));
+    let jobs = match name {
+        Some(name) => vec![crontab.job(name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no {name} job in crontab.toml"),
+            )
+        })?],
+        None => crontab.due(&Local::now()),
+    };
+    let mut failed: Vec<String> = Vec::new();
+    for job in jobs {
+        match crontab.run(job, &docks) {
+            Ok(()) => log(format!("{} succeeded", job.name).as_str(), LOG_WITH_SPACE),
+            Err(e) => {
+                log(format!("{} failed: {e}", job.name).as_str(), LOG_WITH_SPACE);
+                failed.push(job.name.clone());
             }
         }
     }
-    assert!(clear().is_ok());
-    log("all images are published successfully", LOG_WITHOUT_SPACE);
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(Error::other(format!("jobs failed : {}", failed.join(", "))))
 }
-fn to_publish() -> HashMap<String, Vec<String>> {
-    let mut data: HashMap<String, Vec<String>> = HashMap::new();
-    if let Ok(docks) = configuration() {
-        if let Some(repositories) = docks.get("hub") {
-            if let Some(repos) = repositories.as_table() {
-                if let Some(i) = repos.get("images") {
-                    if let Some(images) = i.as_array() {
-                        for image in images {
-                            if let Some(x) = image.as_array() {
-                                if let Some(name) = x.first() {
-                                    if let Some(tag) = x.get(1) {
-                                        if let Some(tags) = tag.as_array() {
-                                            let mut x: Vec<String> = Vec::new();
-                                            for tag in tags {
-                                                if let Some(name) = tag.as_str() {
-                                                    x.push(name.to_string());
-                                                }
-                                            }
-                                            if let Some(image) = name.as_str() {
-                                                data.insert(image.to_string(), x);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+
+/// Ask for a text answer, `default` being used when the prompt is left empty.
+fn ask(message: &str, default: &str) -> Result<String, Error> {
+    Text::new(message)
+        .with_default(default)
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))
+}
+
+/// Ask for an integer answer, `default` being used when the prompt is left empty.
+fn ask_number(message: &str, default: &str) -> Result<i64, Error> {
+    let answer = ask(message, default)?;
+    answer
+        .trim()
+        .parse::<i64>()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{answer} is not a number")))
+}
+
+/// The containers docks knows: the directories of ./containers and of $DOCKS_PUBLIC_DIR, and
+/// the ones listed on a host of `docks`.
+fn known_containers(docks: &Value) -> Vec<String> {
+    let mut containers: Vec<String> = Vec::new();
+    let public = var("DOCKS_PUBLIC_DIR").ok().map(PathBuf::from);
+    for dir in [Some(PathBuf::from("./containers")), public]
+        .into_iter()
+        .flatten()
+    {
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            if entry.path().join(compose::COMPOSE_FILE).is_file() {
+                containers.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    for (_, table) in docks.as_table().into_iter().flatten() {
+        containers.extend(
+            table
+                .get("containers")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(String::from),
+        );
+    }
+    containers.sort();
+    containers.dedup();
+    containers
+}
+
+fn configure_host(docks: &config::Layered, editor: &mut Editor) -> Result<(), Error> {
+    let name = ask("host name :", "")?;
+    if name.is_empty() || docks.value.get(name.as_str()).is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{name} is empty or already exists"),
+        ));
+    }
+    let ssh = |key: &str, default: &str| {
+        docks
+            .value
+            .get("ssh")
+            .and_then(|ssh| ssh.get(key))
+            .map_or_else(
+                || default.to_string(),
+                |value| {
+                    value
+                        .as_str()
+                        .map_or_else(|| value.to_string(), String::from)
+                },
+            )
+    };
+    let username = ask("ssh user :", ssh("user", "root").as_str())?;
+    let ip = ask("ip or hostname :", "")?;
+    let port = ask("ssh port :", ssh("port", "22").as_str())?;
+    editor::add_host(
+        editor.document(Path::new(config::CONFIG_FILE))?,
+        name.as_str(),
+        username.as_str(),
+        ip.as_str(),
+        port.as_str(),
+    )
+}
+
+fn configure_remove_host(docks: &config::Layered, editor: &mut Editor) -> Result<(), Error> {
+    let host = Select::new("host to remove :", servers()?)
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    if Confirm::new(format!("remove {host} from the config ?").as_str())
+        .with_default(false)
+        .prompt()
+        .unwrap_or(false)
+        .eq(&false)
+    {
+        return Ok(());
+    }
+    let file = docks
+        .file(host.as_str(), "ip")
+        .unwrap_or_else(|| PathBuf::from(config::CONFIG_FILE));
+    let orphans = editor::remove_host(editor.document(file.as_path())?, host.as_str())?;
+    if orphans.is_empty().eq(&false) {
+        log(
+            format!("{} are no longer deployed anywhere", orphans.join(", ")).as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    Ok(())
+}
+
+fn configure_containers(docks: &config::Layered, editor: &mut Editor) -> Result<(), Error> {
+    let mut hosts = vec![String::from("local")];
+    hosts.extend(servers()?);
+    let host = Select::new("host :", hosts.clone())
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    let assigned = |host: &str| {
+        docks
+            .value
+            .get(host)
+            .and_then(|table| table.get("containers"))
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect::<Vec<String>>()
+    };
+    let current = assigned(host.as_str());
+    let known = known_containers(&docks.value);
+    let defaults = known
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| current.contains(c))
+        .map(|(at, _)| at)
+        .collect::<Vec<usize>>();
+    let selected = MultiSelect::new(format!("containers deployed on {host} :").as_str(), known)
+        .with_default(&defaults)
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    let file = |host: &str| {
+        docks
+            .file(host, "containers")
+            .unwrap_or_else(|| PathBuf::from(config::CONFIG_FILE))
+    };
+    for other in hosts.iter().filter(|h| h.ne(&&host)) {
+        for container in assigned(other).iter().filter(|c| selected.contains(c)) {
+            log(
+                format!("{container} moves from {other} to {host}").as_str(),
+                LOG_WITH_SPACE,
+            );
+            editor::unassign(editor.document(file(other).as_path())?, container);
+        }
+    }
+    editor::set_containers(
+        editor.document(file(host.as_str()).as_path())?,
+        host.as_str(),
+        &selected,
+    )
+}
+
+fn configure_image(docks: &config::Layered, editor: &mut Editor) -> Result<(), Error> {
+    let name = ask("image name :", "")?;
+    let path = ask("build context :", format!("./{name}").as_str())?;
+    let tags = MultiSelect::new("tag groups :", editor::tag_groups(&docks.value))
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    let images = docks
+        .value
+        .get("hub")
+        .and_then(|hub| hub.get("images"))
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|image| image.get("name").and_then(Value::as_str).map(String::from))
+        .collect::<Vec<String>>();
+    let after = if images.is_empty() {
+        Vec::new()
+    } else {
+        MultiSelect::new("built after :", images)
+            .prompt()
+            .map_err(|e| Error::other(e.to_string()))?
+    };
+    let file = docks
+        .file("hub", "images")
+        .unwrap_or_else(|| PathBuf::from(config::CONFIG_FILE));
+    editor::add_image(
+        editor.document(file.as_path())?,
+        name.as_str(),
+        path.as_str(),
+        &tags,
+        &after,
+    )
+}
+
+fn configure_job(docks: &config::Layered, editor: &mut Editor) -> Result<(), Error> {
+    let mut job = editor::NewJob {
+        name: ask("job name :", "")?,
+        schedule: ask("schedule (minute hour day month weekday) :", "0 3 * * *")?,
+        ..editor::NewJob::default()
+    };
+    let kind = Select::new("the job runs :", vec!["a backup", "commands"])
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    if kind.eq("commands") {
+        job.commands = ask("commands, separated by ; :", "")?
+            .split(';')
+            .map(str::trim)
+            .filter(|command| command.is_empty().eq(&false))
+            .map(String::from)
+            .collect();
+    } else {
+        job.container = Some(
+            Select::new("container :", known_containers(&docks.value))
+                .prompt()
+                .map_err(|e| Error::other(e.to_string()))?,
+        );
+        job.stop = Confirm::new("stop it while archiving ?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        let counts = ask(
+            "snapshots kept for the latest days, weeks and months, empty keeps them all :",
+            "7 4 6",
+        )?;
+        for (period, count) in cron::PERIODS.iter().zip(counts.split_whitespace()) {
+            let count = count.parse::<i64>().map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, format!("{count} is not a number"))
+            })?;
+            if count.gt(&0) {
+                job.retention.push(((*period).to_string(), count));
+            }
+        }
+    }
+    job.log = Some(ask("log file, empty for none :", "")?).filter(|log| log.is_empty().eq(&false));
+    job.retries = ask_number("retries :", "0")?;
+    if job.retries.gt(&0) {
+        job.retry_delay = ask_number("seconds between retries :", "60")?;
+    }
+    editor::add_job(editor.document(Path::new(cron::CRONTAB))?, &job)
+}
+
+/// Guided changes of the config files, validated then written back with their comments and
+/// formatting kept.
+fn configure() -> Result<(), Error> {
+    let docks = config::Layered::load(Path::new(config::CONFIG_FILE), None)?;
+    let change = Select::new(
+        "change :",
+        vec![
+            "add a host",
+            "remove a host",
+            "assign containers",
+            "add a hub image",
+            "add a cron job",
+        ],
+    )
+    .prompt()
+    .map_err(|e| Error::other(e.to_string()))?;
+    let mut editor = Editor::new();
+    match change {
+        "add a host" => configure_host(&docks, &mut editor)?,
+        "remove a host" => configure_remove_host(&docks, &mut editor)?,
+        "assign containers" => configure_containers(&docks, &mut editor)?,
+        "add a hub image" => configure_image(&docks, &mut editor)?,
+        _ => configure_job(&docks, &mut editor)?,
+    }
+    for path in editor.save()? {
+        log(format!("{} saved", path.display()).as_str(), LOG_WITH_SPACE);
+    }
+    Ok(())
+}
+
+/// Choose a deployed container and the host to move it to, then migrate it.
+fn migrate_task() -> Result<(), Error> {
+    let docks = configuration()?;
+    let mut hosts = vec![String::from("local")];
+    hosts.extend(servers()?);
+    let mut containers: Vec<String> = Vec::new();
+    for host in &hosts {
+        containers.extend(
+            docks
+                .get(host.as_str())
+                .and_then(|table| table.get("containers"))
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(String::from),
+        );
+    }
+    containers.sort();
+    containers.dedup();
+    let container = Select::new("container to migrate :", containers)
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    let source = ask_host(&docks, container.as_str())?;
+    let destination = Select::new("to host :", hosts)
+        .prompt()
+        .map_err(|e| Error::other(e.to_string()))?;
+    migrate(
+        container.as_str(),
+        source.as_deref(),
+        destination.as_str(),
+        false,
+    )
+}
+
+/// The host to take `container` from, asked when docks.toml lists it on several hosts.
+fn ask_host(docks: &Value, container: &str) -> Result<Option<String>, Error> {
+    let hosts = gc::hosts(docks, container);
+    if hosts.len().lt(&2) {
+        return Ok(None);
+    }
+    Select::new(
+        format!("{container} is listed on several hosts, from host :").as_str(),
+        hosts,
+    )
+    .prompt()
+    .map(Some)
+    .map_err(|e| Error::other(e.to_string()))
+}
+
+/// Stop `container` on `source`, the only host listing it when `None`, copy its volumes to
+/// `destination`, deploy it there and wait until it is healthy, then move it to `destination`
+/// in docks.toml. The source keeps its stopped containers and volumes, a failure starts it
+/// again.
+fn migrate(
+    container: &str,
+    source: Option<&str>,
+    destination: &str,
+    yes: bool,
+) -> Result<(), Error> {
+    let docks = configuration()?;
+    let image = Backup::from_config(&docks, Path::new("."))?.image;
+    let source = Host::deploying(&docks, container, source)?;
+    let target = Host::named(&docks, destination)?;
+    if source.name.eq(&target.name) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{container} already runs on {destination}"),
+        ));
+    }
+    let dir = PathBuf::from(format!("./containers/{container}"));
+    let compose = compose::lint(dir.as_path(), &Rules::default(), &|_| true)?;
+    let secrets = load_secrets(&docks)?;
+    let existing = source.docker(&["volume", "ls", "-q"])?;
+    let volumes = compose
+        .volumes()
+        .into_iter()
+        .filter(|volume| existing.lines().any(|line| line.eq(volume)))
+        .collect::<Vec<String>>();
+    let confirmed = yes
+        || Confirm::new(
+            format!(
+                "stop {container} on {} and move it with {} volumes to {destination} ?",
+                source.name,
+                volumes.len()
+            )
+            .as_str(),
+        )
+        .with_default(false)
+        .prompt()
+        .unwrap_or_default();
+    if confirmed.eq(&false) {
+        return Ok(());
+    }
+    let project = source.project(container);
+    log(
+        format!("Stopping {container} on {}", source.name).as_str(),
+        LOG_WITH_SPACE,
+    );
+    source.docker(&["compose", "--project-directory", project.as_str(), "stop"])?;
+    let mut copied: Vec<&str> = Vec::new();
+    let moved = volumes
+        .iter()
+        .try_for_each(|volume| {
+            log(
+                format!("Copying {volume} to {destination}").as_str(),
+                LOG_WITH_SPACE,
+            );
+            let copy = migrate::transfer(&source, &target, volume.as_str(), image.as_str());
+            if copy
+                .as_ref()
+                .is_err_and(|e| e.kind().eq(&ErrorKind::AlreadyExists))
+                .eq(&false)
+            {
+                copied.push(volume.as_str());
+            }
+            copy
+        })
+        .and_then(|()| match docks.get(destination) {
+            Some(config) if target.ssh.is_some() => {
+                let field =
+                    |name: &str| config.get(name).and_then(Value::as_str).unwrap_or_default();
+                manage_remote_container(
+                    container,
+                    destination,
+                    field("ip"),
+                    target.port.as_str(),
+                    field("username"),
+                    secrets.get(container),
+                )
             }
+            _ => compose_local(container, secrets.get(container)),
+        })
+        .and_then(|()| migrate::wait_healthy(&target, container, Duration::from_secs(60)));
+    if let Err(e) = moved {
+        log(
+            format!("Removing {container} from {destination}").as_str(),
+            LOG_WITH_SPACE,
+        );
+        let remote = target.project(container);
+        let _ = target.docker(&["compose", "--project-directory", remote.as_str(), "down"]);
+        for volume in copied {
+            let _ = target.docker(&["volume", "rm", volume]);
+        }
+        log(
+            format!("Starting {container} again on {}", source.name).as_str(),
+            LOG_WITH_SPACE,
+        );
+        source.docker(&["compose", "--project-directory", project.as_str(), "start"])?;
+        return Err(e);
+    }
+    let layered =
+        config::Layered::load(Path::new(config::CONFIG_FILE), config::profile().as_deref())?;
+    let file = |table: &str| {
+        layered
+            .file(table, "containers")
+            .unwrap_or_else(|| PathBuf::from(config::CONFIG_FILE))
+    };
+    migrate::reassign(
+        container,
+        (file(source.name.as_str()).as_path(), source.name.as_str()),
+        (file(destination).as_path(), destination),
+    )?;
+    log(
+        format!(
+            "{container} runs on {destination}, it stays stopped on {} with its volumes",
+            source.name
+        )
+        .as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    Ok(())
+}
+
+/// Snapshot the volumes of `container` on `host`, the only host listing it when `None`,
+/// stopping the stack when `stop` is set or, without it, when `[backup].stop` asks for it.
+fn backup(container: &str, host: Option<&str>, stop: Option<bool>) -> Result<(), Error> {
+    let docks = configuration()?;
+    let backup = Backup::from_config(&docks, Path::new("."))?;
+    let host = Host::deploying(&docks, container, host)?;
+    let dir = PathBuf::from(format!("./containers/{container}"));
+    let compose = compose::lint(dir.as_path(), &Rules::default(), &|_| true)?;
+    let snapshot = backup.snapshot(&host, container, &compose, &[], stop.unwrap_or(backup.stop))?;
+    for archive in &snapshot.archives {
+        log(
+            format!("{} archived, {}", archive.volume, gc::human(archive.size)).as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    log(
+        format!("Snapshot saved in {}", snapshot.dir.display()).as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    Ok(())
+}
+
+/// Restore a snapshot, chosen in the menu when `dir` is `None`, into volumes of `host`, the
+/// host it was taken on by default. The menu can restore one volume under another name.
+fn restore(dir: Option<PathBuf>, host: Option<String>) -> Result<(), Error> {
+    let docks = configuration()?;
+    let backup = Backup::from_config(&docks, Path::new("."))?;
+    let interactive = dir.is_none();
+    let snapshot = if let Some(dir) = dir {
+        Snapshot::load(dir.as_path())?
+    } else {
+        let mut snapshots = backup.snapshots()?;
+        snapshots.reverse();
+        if snapshots.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no snapshot in {}", backup.dir.display()),
+            ));
+        }
+        let labels = snapshots
+            .iter()
+            .map(Snapshot::label)
+            .collect::<Vec<String>>();
+        let label = Select::new("snapshot to restore :", labels.clone())
+            .prompt()
+            .map_err(|e| Error::other(e.to_string()))?;
+        let at = labels.iter().position(|l| l.eq(&label)).unwrap_or_default();
+        snapshots.swap_remove(at)
+    };
+    let host = match host {
+        Some(host) => host,
+        None if interactive => Text::new("restore on host : ")
+            .with_default(snapshot.host.as_str())
+            .prompt()
+            .unwrap_or_else(|_| snapshot.host.clone()),
+        None => snapshot.host.clone(),
+    };
+    let host = Host::named(&docks, host.as_str())?;
+    let mut targets: Vec<(usize, String)> = snapshot
+        .archives
+        .iter()
+        .enumerate()
+        .map(|(at, archive)| (at, archive.volume.clone()))
+        .collect();
+    if interactive {
+        let mut volumes = vec![String::from("every volume")];
+        volumes.extend(snapshot.archives.iter().map(|a| a.volume.clone()));
+        let volume = Select::new("volume to restore :", volumes)
+            .prompt()
+            .map_err(|e| Error::other(e.to_string()))?;
+        if let Some(at) = snapshot.archives.iter().position(|a| a.volume.eq(&volume)) {
+            let target = Text::new("into volume : ")
+                .with_default(volume.as_str())
+                .prompt()
+                .unwrap_or(volume);
+            targets = vec![(at, target)];
+        }
+        let confirmed = Confirm::new(
+            format!(
+                "replace the content of {} volumes on {} ?",
+                targets.len(),
+                host.name
+            )
+            .as_str(),
+        )
+        .with_default(false)
+        .prompt()
+        .unwrap_or_default();
+        if confirmed.eq(&false) {
+            return Ok(());
+        }
+    }
+    for (at, target) in targets {
+        backup.restore(&snapshot, &snapshot.archives[at], &host, target.as_str())?;
+        log(
+            format!(
+                "{} restored into {target} on {}",
+                snapshot.archives[at].volume, host.name
+            )
+            .as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    Ok(())
+}
+
+/// Decrypt the secrets store of docks.toml, none when the project has no store.
+fn load_secrets(docks: &Value) -> Result<Secrets, Error> {
+    Store::from_config(docks, Path::new("."))?.load()
+}
+
+/// Encrypt `value` as `key` of `scope`, asked without echo when `None`. The age key is
+/// created on the first secret when the store uses a key file.
+fn secrets_set(scope: &str, key: &str, value: Option<String>) -> Result<(), Error> {
+    let store = Store::from_config(&configuration()?, Path::new("."))?;
+    if store
+        .identity
+        .as_ref()
+        .is_some_and(|identity| identity.exists().eq(&false))
+    {
+        store.generate()?;
+        log(
+            format!(
+                "Age key created in {}, keep a copy of it",
+                store
+                    .identity
+                    .as_ref()
+                    .map(|i| i.display().to_string())
+                    .unwrap_or_default()
+            )
+            .as_str(),
+            LOG_WITH_SPACE,
+        );
+    }
+    let value = match value {
+        Some(value) => value,
+        None => Password::new(format!("{key} : ").as_str())
+            .without_confirmation()
+            .prompt()
+            .map_err(|e| Error::other(e.to_string()))?,
+    };
+    store.set(scope, key, value.as_str())?;
+    log(
+        format!("{scope} {key} saved in {}", store.path.display()).as_str(),
+        LOG_WITH_SPACE,
+    );
+    Ok(())
+}
+
+fn secrets_get(scope: &str, key: &str) -> Result<(), Error> {
+    let secrets = load_secrets(&configuration()?)?;
+    let value = secrets
+        .get(scope)
+        .and_then(|values| values.get(key))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no {key} secret for {scope}")))?;
+    println!("{value}");
+    Ok(())
+}
+
+fn secrets_list() -> Result<(), Error> {
+    let secrets = load_secrets(&configuration()?)?;
+    if secrets.is_empty() {
+        log("No secret stored", LOG_WITHOUT_SPACE);
+    }
+    for (scope, values) in secrets {
+        println!("{scope}");
+        for key in values.keys() {
+            println!("  {key}");
         }
     }
-    data
+    Ok(())
+}
+
+fn secrets_remove(scope: &str, key: &str) -> Result<(), Error> {
+    let store = Store::from_config(&configuration()?, Path::new("."))?;
+    if store.remove(scope, key)? {
+        log(format!("{scope} {key} removed").as_str(), LOG_WITH_SPACE);
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("no {key} secret for {scope}"),
+    ))
+}
+
+/// The `secrets set|get|list|rm` command line.
+fn secrets_command(args: &[String]) -> Result<(), Error> {
+    let arg = |at: usize| args.get(at).map(String::as_str).unwrap_or_default();
+    match (arg(1), args.len()) {
+        ("set", 4 | 5) => {
+            let value = if args.iter().any(|arg| arg.eq("--stdin")) {
+                let mut value = String::new();
+                io::stdin().read_line(&mut value)?;
+                Some(value.trim_end_matches(['\n', '\r']).to_string())
+            } else {
+                None
+            };
+            secrets_set(arg(2), arg(3), value)
+        }
+        ("get", 4) => secrets_get(arg(2), arg(3)),
+        ("rm", 4) => secrets_remove(arg(2), arg(3)),
+        ("list", 2) => secrets_list(),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "usage: secrets set <scope> <KEY> [--stdin] | get <scope> <KEY> | rm <scope> <KEY> | list",
+        )),
+    }
+}
+
+fn vulnerability_database() -> PathBuf {
+    configuration().map_or_else(
+        |_| vuln::database(&Value::Table(toml::Table::new()), Path::new(".")),
+        |config| vuln::database(&config, Path::new(".")),
+    )
+}
+
+fn import_vulnerabilities(source: &str) -> Result<(), Error> {
+    let database = vulnerability_database();
+    let count = Database::import(Path::new(source), database.as_path())?;
+    log(
+        format!("{count} advisories imported in {}", database.display()).as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    Ok(())
+}
+
+fn scan_image(image: Option<String>) -> Result<(), Error> {
+    let database = Database::load(vulnerability_database().as_path())?;
+    let image = match image {
+        Some(image) => image,
+        None => {
+            assert!(list_images().is_ok());
+            let images = output(
+                "docker",
+                &["images", "--format", "{{.Repository}}:{{.Tag}}"],
+            )?
+            .lines()
+            .filter(|image| image.contains("<none>").eq(&false))
+            .map(String::from)
+            .collect::<Vec<String>>();
+            Select::new("image to scan :", images)
+                .prompt()
+                .map_err(|e| Error::new(ErrorKind::Interrupted, e.to_string()))?
+        }
+    };
+    let contents = sbom::inspect(image.as_str(), false)?;
+    let findings = database.scan(&contents);
+    println!("{}", vuln::report(image.as_str(), &findings));
+    Ok(())
+}
+
+fn signing_key() -> Result<(), Error> {
+    let signing = Signing::from_config(&configuration()?, Path::new("."))?.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "add a [hub.signing] section with the key to create",
+        )
+    })?;
+    signing.generate()?;
+    log(
+        format!(
+            "Images will be signed with {}, share {} with the deploy hosts",
+            signing.key.display(),
+            signing.public_key.display()
+        )
+        .as_str(),
+        LOG_WITHOUT_SPACE,
+    );
+    Ok(())
 }
 fn os() {
     loop {
@@ -0,0 +1,199 @@
+use crate::gc::Host;
+use std::fs::{read_to_string, write};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use toml_edit::{value, Array, DocumentMut, Item};
+
+/// Copy the content of `volume` from one host to the same volume on another, streamed through
+/// tar in `image`. The destination volume must not exist yet.
+///
+/// # Errors
+///
+/// When the destination volume exists or one side of the copy fails.
+pub fn transfer(from: &Host, to: &Host, volume: &str, image: &str) -> Result<(), Error> {
+    let existing = to.docker(&["volume", "ls", "-q"])?;
+    if existing.lines().any(|line| line.eq(volume)) {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{volume} already exists on {}, remove it first", to.name),
+        ));
+    }
+    to.docker(&["volume", "create", volume])?;
+    let source = format!("{volume}:/volume:ro");
+    let mut reader = from
+        .command(&[
+            "run",
+            "--rm",
+            "-v",
+            source.as_str(),
+            image,
+            "tar",
+            "-czf",
+            "-",
+            "-C",
+            "/volume",
+            ".",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "docker or ssh not found"))?;
+    let target = format!("{volume}:/volume");
+    let written = reader.stdout.take().map(|stdout| {
+        to.command(&[
+            "run",
+            "--rm",
+            "-i",
+            "-v",
+            target.as_str(),
+            image,
+            "tar",
+            "-xzf",
+            "-",
+            "-C",
+            "/volume",
+        ])
+        .stdin(stdout)
+        .stdout(Stdio::null())
+        .status()
+    });
+    let read = reader.wait()?;
+    match written {
+        Some(Ok(status)) if status.success() && read.success() => Ok(()),
+        _ => Err(Error::other(format!(
+            "cannot copy {volume} from {} to {}",
+            from.name, to.name
+        ))),
+    }
+}
+
+/// The state and health of every service of `container` on `host`.
+///
+/// # Errors
+///
+/// When docker compose fails on the host.
+pub fn services(host: &Host, container: &str) -> Result<Vec<(String, String, String)>, Error> {
+    let project = host.project(container);
+    let output = host.docker(&[
+        "compose",
+        "--project-directory",
+        project.as_str(),
+        "ps",
+        "-a",
+        "--format",
+        "{{.Service}}|{{.State}}|{{.Health}}",
+    ])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('|');
+            Some((
+                fields.next()?.to_string(),
+                fields.next()?.to_string(),
+                fields.next().unwrap_or_default().to_string(),
+            ))
+        })
+        .collect())
+}
+
+/// Wait until every service of `container` runs, and is healthy when it has a healthcheck.
+///
+/// # Errors
+///
+/// When a service exits, becomes unhealthy, or `timeout` elapses first.
+pub fn wait_healthy(host: &Host, container: &str, timeout: Duration) -> Result<(), Error> {
+    let start = Instant::now();
+    loop {
+        let services = services(host, container)?;
+        if let Some((service, state, health)) = services.iter().find(|(_, state, health)| {
+            ["exited", "dead"].contains(&state.as_str()) || health.eq("unhealthy")
+        }) {
+            return Err(Error::other(format!(
+                "{service} of {container} is {} on {}",
+                if health.is_empty() { state } else { health },
+                host.name
+            )));
+        }
+        if services.is_empty().eq(&false)
+            && services.iter().all(|(_, state, health)| {
+                state.eq("running") && ["", "healthy"].contains(&health.as_str())
+            })
+        {
+            return Ok(());
+        }
+        if start.elapsed().gt(&timeout) {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{container} is not healthy on {} after {}s",
+                    host.name,
+                    timeout.as_secs()
+                ),
+            ));
+        }
+        sleep(Duration::from_secs(2));
+    }
+}
+
+fn edit(
+    path: &Path,
+    change: impl FnOnce(&mut DocumentMut) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut document = read_to_string(path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+    change(&mut document)?;
+    write(path, document.to_string())
+}
+
+/// Move `container` from the `containers` list of the `from` table to the one of `to`, each
+/// table being edited in the config file defining it, keeping the comments and the layout of
+/// the files.
+///
+/// # Errors
+///
+/// When a file cannot be read, parsed or written, or `to` is not a table of its file.
+pub fn reassign(container: &str, from: (&Path, &str), to: (&Path, &str)) -> Result<(), Error> {
+    edit(from.0, |document| {
+        if let Some(containers) = document
+            .get_mut(from.1)
+            .and_then(|table| table.get_mut("containers"))
+            .and_then(Item::as_array_mut)
+        {
+            containers.retain(|c| c.as_str().ne(&Some(container)));
+            containers.fmt();
+        }
+        Ok(())
+    })?;
+    edit(to.0, |document| {
+        let table = document
+            .get_mut(to.1)
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not in {}", to.1, to.0.display()),
+                )
+            })?;
+        match table.get_mut("containers").and_then(Item::as_array_mut) {
+            Some(containers) => {
+                if containers
+                    .iter()
+                    .any(|c| c.as_str().eq(&Some(container)))
+                    .eq(&false)
+                {
+                    containers.push(container);
+                }
+            }
+            None => {
+                let mut containers = Array::new();
+                containers.push(container);
+                table.insert("containers", value(containers));
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,163 @@
+use crate::compose::Compose;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::thread;
+use toml::Value;
+
+/// Containers each container needs running before it, keyed by container name.
+pub type Dependencies = HashMap<String, Vec<String>>;
+
+/// Collect the dependencies between `containers` deployed on the same host.
+///
+/// They come from the `[depends_on]` table of docks.toml, from compose `depends_on` naming a
+/// service defined by another container, and from an `external` network created by another
+/// container. Dependencies on containers that are not part of `containers` are ignored.
+#[must_use]
+pub fn dependencies(config: &Value, containers: &[String], composes: &[Compose]) -> Dependencies {
+    let mut dependencies: Dependencies = HashMap::new();
+    let mut add = |container: &str, dependency: &str| {
+        if container.ne(dependency) && containers.iter().any(|c| c.eq(dependency)) {
+            let entry = dependencies.entry(container.to_string()).or_default();
+            if entry.iter().any(|d| d.eq(dependency)).eq(&false) {
+                entry.push(dependency.to_string());
+            }
+        }
+    };
+    if let Some(table) = config.get("depends_on").and_then(Value::as_table) {
+        for (container, needs) in table {
+            if containers.contains(container).eq(&false) {
+                continue;
+            }
+            for need in needs
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                add(container, need);
+            }
+        }
+    }
+    for (container, compose) in containers.iter().zip(composes) {
+        for service in compose.external_services() {
+            for (other, candidate) in containers.iter().zip(composes) {
+                if candidate.services().contains(&service) {
+                    add(container, other);
+                }
+            }
+        }
+        let (_, external) = compose.networks();
+        for network in external {
+            for (other, candidate) in containers.iter().zip(composes) {
+                if candidate.networks().0.contains(&network) {
+                    add(container, other);
+                }
+            }
+        }
+    }
+    dependencies
+}
+
+/// Group `containers` in stages, every container of a stage only depends on containers of
+/// the previous stages, the order of `containers` is kept inside a stage.
+///
+/// # Errors
+///
+/// When the dependencies contain a cycle.
+pub fn stages(
+    containers: &[String],
+    dependencies: &Dependencies,
+) -> Result<Vec<Vec<String>>, Error> {
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut stages: Vec<Vec<String>> = Vec::new();
+    while done.len().lt(&containers.len()) {
+        let stage: Vec<String> = containers
+            .iter()
+            .filter(|c| done.contains(c.as_str()).eq(&false))
+            .filter(|c| {
+                dependencies
+                    .get(c.as_str())
+                    .is_none_or(|needs| needs.iter().all(|n| done.contains(n.as_str())))
+            })
+            .cloned()
+            .collect();
+        if stage.is_empty() {
+            let cycle = containers
+                .iter()
+                .filter(|c| done.contains(c.as_str()).eq(&false))
+                .map(String::as_str)
+                .collect::<Vec<&str>>()
+                .join(", ");
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("dependency cycle between {cycle}"),
+            ));
+        }
+        for container in containers {
+            if stage.contains(container) {
+                done.insert(container.as_str());
+            }
+        }
+        stages.push(stage);
+    }
+    Ok(stages)
+}
+
+/// Run `task` on every container stage by stage, the containers of a stage in parallel with
+/// at most `jobs` of them at once.
+///
+/// A container whose dependency failed or was skipped is skipped too, the returned errors
+/// are keyed by container name.
+pub fn run<F>(
+    stages: &[Vec<String>],
+    dependencies: &Dependencies,
+    jobs: usize,
+    task: F,
+) -> Vec<(String, Error)>
+where
+    F: Fn(&str) -> Result<(), Error> + Sync,
+{
+    let mut failed: Vec<(String, Error)> = Vec::new();
+    for stage in stages {
+        let mut ready: Vec<&String> = Vec::new();
+        for container in stage {
+            let blocked = dependencies
+                .get(container)
+                .and_then(|needs| needs.iter().find(|n| failed.iter().any(|(f, _)| f.eq(*n))));
+            if let Some(need) = blocked {
+                failed.push((
+                    container.to_string(),
+                    Error::new(
+                        ErrorKind::Interrupted,
+                        format!("skipped because {need} failed"),
+                    ),
+                ));
+            } else {
+                ready.push(container);
+            }
+        }
+        for batch in ready.chunks(jobs.max(1)) {
+            let results: Vec<(String, Result<(), Error>)> = thread::scope(|scope| {
+                let handles = batch
+                    .iter()
+                    .map(|container| (container.to_string(), scope.spawn(|| task(container))))
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|(container, handle)| {
+                        let result = handle
+                            .join()
+                            .unwrap_or_else(|_| Err(Error::other("task thread panicked")));
+                        (container, result)
+                    })
+                    .collect()
+            });
+            for (container, result) in results {
+                if let Err(e) = result {
+                    failed.push((container, e));
+                }
+            }
+        }
+    }
+    failed
+}
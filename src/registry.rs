@@ -0,0 +1,281 @@
+use crate::hub;
+use serde_json::Value;
+use std::env::var;
+use std::io::{Error, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+const DOCKER_HUB: &str = "https://hub.docker.com/v2";
+
+const MANIFESTS: [&str; 4] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// The registry images are pushed to and the account used for its API, docker hub when
+/// `url` is empty. The password comes from `DOCKS_REGISTRY_PASSWORD` or `[registry].password`.
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// A tag of a repository, `updated` is only known on docker hub.
+#[derive(Clone, Debug, Default)]
+pub struct Tag {
+    pub name: String,
+    pub updated: Option<String>,
+    pub digest: Option<String>,
+}
+
+/// Run curl with `input` on its stdin, how credentials reach it through `-K -` or
+/// `--data @-` without showing in the process list.
+fn curl(args: &[&str], input: &str) -> Result<String, Error> {
+    let mut child = Command::new("curl")
+        .args(["-sS", "--fail-with-body"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "curl not found"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let body = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() {
+        return Ok(body);
+    }
+    Err(Error::other(format!(
+        "{} failed: {}",
+        args.last().copied().unwrap_or_default(),
+        body.trim()
+    )))
+}
+
+/// A curl config line setting `option` to `value`, for curl to read with `-K -`.
+pub(crate) fn option(option: &str, value: &str) -> String {
+    format!(
+        "{option} = \"{}\"\n",
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+fn json(body: &str) -> Result<Value, Error> {
+    serde_json::from_str::<Value>(body)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+impl Registry {
+    #[must_use]
+    pub fn from_config(config: &toml::Value) -> Self {
+        let field = |name: &str| {
+            config
+                .get("registry")
+                .and_then(|registry| registry.get(name))
+                .and_then(toml::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let username = field("username");
+        Self {
+            url: field("url").trim_end_matches('/').to_string(),
+            username: if username.is_empty() {
+                hub::username(config)
+            } else {
+                username
+            },
+            password: var("DOCKS_REGISTRY_PASSWORD").unwrap_or_else(|_| field("password")),
+        }
+    }
+
+    fn base(&self) -> String {
+        if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            return format!("{}/v2", self.url);
+        }
+        format!("https://{}/v2", self.url)
+    }
+
+    fn basic(&self) -> String {
+        option(
+            "user",
+            format!("{}:{}", self.username, self.password).as_str(),
+        )
+    }
+
+    /// The curl config authenticating on `repository`, read by curl from stdin: a docker
+    /// hub JWT, a bearer token when the registry answers with a token challenge, basic
+    /// credentials otherwise.
+    fn credentials(&self, repository: &str) -> Result<String, Error> {
+        if self.password.is_empty() {
+            return Ok(String::new());
+        }
+        if self.url.is_empty() {
+            let body = serde_json::json!({ "username": self.username, "password": self.password });
+            let login = curl(
+                &[
+                    "-X",
+                    "POST",
+                    "-H",
+                    "Content-Type: application/json",
+                    "--data",
+                    "@-",
+                    format!("{DOCKER_HUB}/users/login").as_str(),
+                ],
+                body.to_string().as_str(),
+            )?;
+            let token = json(login.as_str())?
+                .get("token")
+                .and_then(Value::as_str)
+                .map(String::from)
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::PermissionDenied, "docker hub login failed")
+                })?;
+            return Ok(option(
+                "header",
+                format!("Authorization: JWT {token}").as_str(),
+            ));
+        }
+        let headers = Command::new("curl")
+            .args(["-sS", "-o", "/dev/null", "-D", "-"])
+            .arg(format!("{}/", self.base()))
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+            .unwrap_or_default();
+        let challenge = headers.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("www-authenticate") {
+                value.trim().strip_prefix("Bearer ").map(String::from)
+            } else {
+                None
+            }
+        });
+        let Some(challenge) = challenge else {
+            return Ok(self.basic());
+        };
+        let param = |key: &str| {
+            challenge.split(',').find_map(|part| {
+                let (k, v) = part.trim().split_once('=')?;
+                k.eq(key).then(|| v.trim_matches('"').to_string())
+            })
+        };
+        let realm = param("realm").unwrap_or_default();
+        let service = param("service").unwrap_or_default();
+        let body = curl(
+            &[
+                "-K",
+                "-",
+                format!("{realm}?service={service}&scope=repository:{repository}:pull,push,delete")
+                    .as_str(),
+            ],
+            self.basic().as_str(),
+        )?;
+        let value = json(body.as_str())?;
+        let token = value
+            .get("token")
+            .or_else(|| value.get("access_token"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("no token for {repository} from {realm}"),
+                )
+            })?;
+        Ok(option(
+            "header",
+            format!("Authorization: Bearer {token}").as_str(),
+        ))
+    }
+
+    /// Every tag of `repository`, a `user/image` path.
+    ///
+    /// # Errors
+    ///
+    /// When curl is missing or the registry refuses the request.
+    pub fn tags(&self, repository: &str) -> Result<Vec<Tag>, Error> {
+        let credentials = self.credentials(repository)?;
+        let authenticated: Vec<&str> = vec!["-K", "-"];
+        let mut tags: Vec<Tag> = Vec::new();
+        if self.url.is_empty() {
+            let mut next = Some(format!(
+                "{DOCKER_HUB}/repositories/{repository}/tags?page_size=100"
+            ));
+            while let Some(url) = next {
+                let mut args = authenticated.clone();
+                args.push(url.as_str());
+                let page = json(curl(&args, credentials.as_str())?.as_str())?;
+                for result in page
+                    .get("results")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    let field =
+                        |name: &str| result.get(name).and_then(Value::as_str).map(String::from);
+                    tags.push(Tag {
+                        name: field("name").unwrap_or_default(),
+                        updated: field("last_updated"),
+                        digest: field("digest"),
+                    });
+                }
+                next = page.get("next").and_then(Value::as_str).map(String::from);
+            }
+            return Ok(tags);
+        }
+        let mut args = authenticated.clone();
+        let url = format!("{}/{repository}/tags/list", self.base());
+        args.push(url.as_str());
+        let list = json(curl(&args, credentials.as_str())?.as_str())?;
+        for name in list
+            .get("tags")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            let mut args = authenticated.clone();
+            let accept = format!("Accept: {}", MANIFESTS.join(", "));
+            let url = format!("{}/{repository}/manifests/{name}", self.base());
+            args.extend(["-I", "-H", accept.as_str(), url.as_str()]);
+            let digest = curl(&args, credentials.as_str()).ok().and_then(|head| {
+                head.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.eq_ignore_ascii_case("docker-content-digest")
+                        .then(|| value.trim().to_string())
+                })
+            });
+            tags.push(Tag {
+                name: name.to_string(),
+                updated: None,
+                digest,
+            });
+        }
+        Ok(tags)
+    }
+
+    /// Delete `tag` from `repository`. Outside docker hub the manifest is deleted by digest,
+    /// which removes every tag pointing to it.
+    ///
+    /// # Errors
+    ///
+    /// When the registry refuses the deletion.
+    pub fn delete(&self, repository: &str, tag: &Tag) -> Result<(), Error> {
+        let credentials = self.credentials(repository)?;
+        let mut args: Vec<&str> = vec!["-X", "DELETE", "-K", "-"];
+        let url = if self.url.is_empty() {
+            format!("{DOCKER_HUB}/repositories/{repository}/tags/{}/", tag.name)
+        } else {
+            let digest = tag.digest.as_ref().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("no digest known for {repository}:{}", tag.name),
+                )
+            })?;
+            format!("{}/{repository}/manifests/{digest}", self.base())
+        };
+        args.push(url.as_str());
+        curl(&args, credentials.as_str()).map(|_| ())
+    }
+}
@@ -0,0 +1,451 @@
+use crate::lock::Lock;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::env::temp_dir;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where the SBOM of each published digest is kept, relative to the hub root.
+pub const SBOM_DIR: &str = ".docks/sbom";
+
+/// How the SBOM of an image is obtained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// The SPDX attestation generated by buildx and pushed with the image.
+    Buildx,
+    /// The packages read by docks from the package databases of the pushed image.
+    Scan,
+}
+
+fn docker(args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("docker")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "docker not found"))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+    Err(Error::other(format!("docker {} failed", args.join(" "))))
+}
+
+fn spdx(value: &Value) -> Option<&Value> {
+    if value.get("spdxVersion").is_some() {
+        return Some(value);
+    }
+    match value {
+        Value::Object(map) => map.values().find_map(spdx),
+        Value::Array(values) => values.iter().find_map(spdx),
+        _ => None,
+    }
+}
+
+/// Read the SPDX document buildx attached to the pushed `reference`, the one of the first
+/// platform for a multi-platform image.
+///
+/// # Errors
+///
+/// When docker fails or the image has no SBOM attestation.
+pub fn fetch(reference: &str) -> Result<Value, Error> {
+    let raw = docker(&[
+        "buildx",
+        "imagetools",
+        "inspect",
+        reference,
+        "--format",
+        "{{ json .SBOM }}",
+    ])?;
+    let value = serde_json::from_str::<Value>(raw.as_str())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    spdx(&value).cloned().ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{reference} has no SBOM attestation"),
+        )
+    })
+}
+
+/// A package installed in an image or pinned by a lockfile, `kind` is its purl type like
+/// `deb`, `apk`, `cargo`, `npm` or `pypi`.
+#[derive(Clone, Debug, Default)]
+pub struct Package {
+    pub kind: String,
+    pub name: String,
+    pub source: String,
+    pub version: String,
+    pub arch: String,
+}
+
+impl Package {
+    fn new(kind: &str, name: &str, version: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            version: version.to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// The operating system and the packages found in an image.
+#[derive(Clone, Debug, Default)]
+pub struct Contents {
+    pub distro: String,
+    pub release: String,
+    pub pretty: String,
+    pub packages: Vec<Package>,
+}
+
+/// The lockfiles read in build contexts and in the working directory of images.
+pub const LOCKFILES: [&str; 4] = [
+    "Cargo.lock",
+    "package-lock.json",
+    "poetry.lock",
+    "requirements.txt",
+];
+
+fn fields(paragraph: &str, separator: char) -> impl Iterator<Item = (&str, &str)> {
+    paragraph
+        .lines()
+        .filter(|line| line.starts_with([' ', '\t']).eq(&false))
+        .filter_map(move |line| {
+            line.split_once(separator)
+                .map(|(key, value)| (key.trim(), value.trim()))
+        })
+}
+
+/// The installed packages of a dpkg `status` file.
+#[must_use]
+pub fn dpkg(status: &str) -> Vec<Package> {
+    let mut packages: Vec<Package> = Vec::new();
+    for paragraph in status.split("\n\n") {
+        let mut package = Package::new("deb", "", "");
+        let mut installed = false;
+        for (key, value) in fields(paragraph, ':') {
+            match key {
+                "Package" => package.name = value.to_string(),
+                "Source" => {
+                    package.source = value.split(' ').next().unwrap_or(value).to_string();
+                }
+                "Version" => package.version = value.to_string(),
+                "Architecture" => package.arch = value.to_string(),
+                "Status" => installed = value.ends_with(" installed"),
+                _ => {}
+            }
+        }
+        if installed && package.name.is_empty().eq(&false) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+/// The installed packages of an apk `installed` database.
+#[must_use]
+pub fn apk(installed: &str) -> Vec<Package> {
+    let mut packages: Vec<Package> = Vec::new();
+    for paragraph in installed.split("\n\n") {
+        let mut package = Package::new("apk", "", "");
+        for (key, value) in fields(paragraph, ':') {
+            match key {
+                "P" => package.name = value.to_string(),
+                "o" => package.source = value.to_string(),
+                "V" => package.version = value.to_string(),
+                "A" => package.arch = value.to_string(),
+                _ => {}
+            }
+        }
+        if package.name.is_empty().eq(&false) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+/// The packages pinned by one of the [`LOCKFILES`].
+#[must_use]
+pub fn lockfile(name: &str, content: &str) -> Vec<Package> {
+    match name {
+        "Cargo.lock" | "poetry.lock" => {
+            let kind = if name.eq("Cargo.lock") {
+                "cargo"
+            } else {
+                "pypi"
+            };
+            toml::from_str::<toml::Value>(content)
+                .ok()
+                .and_then(|lock| lock.get("package").and_then(toml::Value::as_array).cloned())
+                .unwrap_or_default()
+                .iter()
+                .filter(|package| kind.eq("pypi") || package.get("source").is_some())
+                .filter_map(|package| {
+                    Some(Package::new(
+                        kind,
+                        package.get("name")?.as_str()?,
+                        package.get("version")?.as_str()?,
+                    ))
+                })
+                .collect()
+        }
+        "package-lock.json" => {
+            let lock = serde_json::from_str::<Value>(content).unwrap_or_default();
+            if let Some(packages) = lock.get("packages").and_then(Value::as_object) {
+                return packages
+                    .iter()
+                    .filter_map(|(path, package)| {
+                        let name = path.rsplit_once("node_modules/")?.1;
+                        Some(Package::new("npm", name, package.get("version")?.as_str()?))
+                    })
+                    .collect();
+            }
+            lock.get("dependencies")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, package)| {
+                    Some(Package::new("npm", name, package.get("version")?.as_str()?))
+                })
+                .collect()
+        }
+        "requirements.txt" => content
+            .lines()
+            .filter_map(|line| line.split('#').next()?.split_once("=="))
+            .map(|(name, version)| {
+                let name = name.split('[').next().unwrap_or(name);
+                Package::new(
+                    "pypi",
+                    name.trim(),
+                    version.split(';').next().unwrap_or(version).trim(),
+                )
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The packages of the lockfiles at the top of `dir`, a build context.
+#[must_use]
+pub fn lockfiles(dir: &Path) -> Vec<Package> {
+    LOCKFILES
+        .iter()
+        .filter_map(|name| {
+            read_to_string(dir.join(name))
+                .ok()
+                .map(|content| lockfile(name, content.as_str()))
+        })
+        .flatten()
+        .collect()
+}
+
+fn os_release(content: &str, key: &str) -> String {
+    fields(content, '=')
+        .find(|(k, _)| k.eq(&key))
+        .map(|(_, v)| v.trim_matches('"').to_string())
+        .unwrap_or_default()
+}
+
+/// Read the os, the debian and alpine packages and the lockfiles of the working directory of
+/// the image `reference`, from a container created without starting it. The image is pulled
+/// first when `pull` is set.
+///
+/// # Errors
+///
+/// When the image cannot be pulled or has no package docks can read.
+pub fn inspect(reference: &str, pull: bool) -> Result<Contents, Error> {
+    if pull {
+        docker(&["pull", "--quiet", reference])?;
+    }
+    let workdir = docker(&[
+        "image",
+        "inspect",
+        "--format",
+        "{{.Config.WorkingDir}}",
+        reference,
+    ])
+    .unwrap_or_default();
+    let container = docker(&["create", reference, "sbom"])?;
+    let dir = temp_dir().join(format!(
+        "docks-sbom-{}-{}",
+        std::process::id(),
+        reference.replace(['/', ':'], "-")
+    ));
+    create_dir_all(&dir)?;
+    let copy = |source: &str, name: &str| -> String {
+        let target = dir.join(name);
+        let copied = docker(&[
+            "cp",
+            format!("{container}:{source}").as_str(),
+            target.to_string_lossy().as_ref(),
+        ]);
+        copied
+            .ok()
+            .and_then(|_| read_to_string(&target).ok())
+            .unwrap_or_default()
+    };
+    let release = copy("/etc/os-release", "os-release");
+    let mut packages = dpkg(copy("/var/lib/dpkg/status", "dpkg").as_str());
+    packages.extend(apk(copy("/lib/apk/db/installed", "apk").as_str()));
+    if workdir.is_empty().eq(&false) {
+        for name in LOCKFILES {
+            let content = copy(
+                format!("{}/{name}", workdir.trim_end_matches('/')).as_str(),
+                name,
+            );
+            packages.extend(lockfile(name, content.as_str()));
+        }
+    }
+    let _ = docker(&["rm", container.as_str()]);
+    let _ = remove_dir_all(&dir);
+    if packages.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no package database or lockfile found in {reference}"),
+        ));
+    }
+    Ok(Contents {
+        distro: os_release(release.as_str(), "ID"),
+        release: os_release(release.as_str(), "VERSION_ID"),
+        pretty: os_release(release.as_str(), "PRETTY_NAME"),
+        packages,
+    })
+}
+
+/// Build an SPDX document of the packages installed in the pushed `reference`.
+///
+/// # Errors
+///
+/// When the image cannot be pulled or has no package docks can read.
+pub fn scan(reference: &str) -> Result<Value, Error> {
+    let contents = inspect(reference, true)?;
+    let packages = contents
+        .packages
+        .iter()
+        .enumerate()
+        .map(|(i, package)| {
+            let namespace = if package.kind.eq("deb") || package.kind.eq("apk") {
+                format!("{}/", contents.distro)
+            } else {
+                String::new()
+            };
+            let arch = if package.arch.is_empty() {
+                String::new()
+            } else {
+                format!("?arch={}", package.arch)
+            };
+            json!({
+                "name": package.name,
+                "SPDXID": format!("SPDXRef-Package-{i}"),
+                "versionInfo": package.version,
+                "downloadLocation": "NOASSERTION",
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": format!("pkg:{}/{namespace}{}@{}{arch}", package.kind, package.name, package.version),
+                }],
+            })
+        })
+        .collect::<Vec<Value>>();
+    Ok(json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": reference,
+        "documentNamespace": format!("https://github.com/otechdo/docks/spdx/{}", reference.replace(':', "/")),
+        "creationInfo": {
+            "created": Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            "creators": [format!("Tool: docks-{}", env!("CARGO_PKG_VERSION"))],
+            "comment": contents.pretty,
+        },
+        "packages": packages,
+    }))
+}
+
+/// Write the SBOM of `digest` under the hub root, the returned path is relative to it.
+///
+/// # Errors
+///
+/// When the file cannot be written.
+pub fn save(root: &Path, digest: &str, reference: &str, document: &Value) -> Result<String, Error> {
+    let name = if digest.is_empty() {
+        reference.replace(['/', ':'], "-")
+    } else {
+        digest.replace(':', "-")
+    };
+    let relative = PathBuf::from(SBOM_DIR).join(format!("{name}.spdx.json"));
+    create_dir_all(root.join(SBOM_DIR))?;
+    let content =
+        serde_json::to_string_pretty(document).map_err(|e| Error::other(e.to_string()))?;
+    write(root.join(&relative), content)?;
+    Ok(relative.to_string_lossy().to_string())
+}
+
+/// The name and version of every package of an SPDX document.
+#[must_use]
+pub fn packages(document: &Value) -> Vec<(String, String)> {
+    document
+        .get("packages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|package| {
+            let field = |name: &str| {
+                package
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            (field("name"), field("versionInfo"))
+        })
+        .collect()
+}
+
+/// Load the SBOM recorded in the lock of `root` for `reference`.
+///
+/// # Errors
+///
+/// When the reference was not published with an SBOM or the file cannot be read.
+pub fn load(root: &Path, reference: &str) -> Result<Value, Error> {
+    let lock = Lock::load(root)?;
+    let entry = lock.get(reference).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{reference} is not recorded in the lock"),
+        )
+    })?;
+    if entry.sbom.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{reference} was published without SBOM"),
+        ));
+    }
+    let content = read_to_string(root.join(entry.sbom.as_str()))?;
+    serde_json::from_str::<Value>(content.as_str())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", entry.sbom)))
+}
+
+/// Print the packages of the SBOM recorded for `reference`.
+///
+/// # Errors
+///
+/// When no SBOM is recorded for it.
+pub fn print(root: &Path, reference: &str) -> Result<(), Error> {
+    let document = load(root, reference)?;
+    let packages = packages(&document);
+    let width = packages
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max(7);
+    println!("\n{reference}\n");
+    println!("{:width$}  version", "package");
+    for (name, version) in &packages {
+        println!("{name:width$}  {version}");
+    }
+    println!("\n{} packages", packages.len());
+    Ok(())
+}
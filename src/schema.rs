@@ -0,0 +1,1017 @@
+use crate::config;
+use crate::cron::PERIODS;
+use crate::vuln::SEVERITIES;
+use serde_json::{json, Map, Value as Json};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use toml::Value;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table};
+
+/// The layout version of docks.toml written by this release.
+pub const VERSION: i64 = 2;
+
+/// The layout version of a docks.toml, 1 when it has no `version` key.
+#[must_use]
+pub fn version(document: &DocumentMut) -> i64 {
+    document
+        .get("version")
+        .and_then(Item::as_integer)
+        .unwrap_or(1)
+}
+
+/// The layout version of the docks.toml at `path`, `None` when it is the current one or the
+/// file cannot be read.
+#[must_use]
+pub fn outdated(path: &Path) -> Option<i64> {
+    let document = read_to_string(path).ok()?.parse::<DocumentMut>().ok()?;
+    Some(version(&document)).filter(|v| v.lt(&VERSION))
+}
+
+fn strings(item: Option<&Item>) -> Vec<String> {
+    item.and_then(Item::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `hub.tags` as a table of groups, the old dockx layout, becomes an array of pairs.
+fn hub_tags(document: &mut DocumentMut, changes: &mut Vec<String>) {
+    let Some(hub) = document.get_mut("hub").and_then(Item::as_table_like_mut) else {
+        return;
+    };
+    let Some(groups) = hub.get("tags").and_then(Item::as_table_like) else {
+        return;
+    };
+    let mut pairs = Array::new();
+    for (group, tags) in groups.iter() {
+        let mut values = Array::new();
+        for tag in strings(Some(tags)) {
+            values.push(tag);
+        }
+        let mut pair = Array::new();
+        pair.push(group);
+        pair.push(values);
+        pairs.push(pair);
+    }
+    hub.insert("tags", value(pairs));
+    changes.push(String::from(
+        "hub.tags is now an array of [group, [tags]] pairs",
+    ));
+}
+
+/// `hub.images` as `[name, [tags]]` arrays, the old docks layout, becomes `[[hub.images]]`
+/// tables, tags which are not groups of `hub.tags` being reported.
+fn hub_images(document: &mut DocumentMut, changes: &mut Vec<String>) {
+    let Some(hub) = document.get_mut("hub").and_then(Item::as_table_like_mut) else {
+        return;
+    };
+    let groups = hub
+        .get("tags")
+        .and_then(Item::as_array)
+        .map(|pairs| {
+            pairs
+                .iter()
+                .filter_map(|pair| pair.as_array()?.get(0)?.as_str().map(String::from))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    let Some(old) = hub.get("images").and_then(Item::as_array) else {
+        return;
+    };
+    if old.iter().all(|image| image.as_array().is_none()) {
+        return;
+    }
+    let mut images = ArrayOfTables::new();
+    for image in old {
+        let Some(image) = image.as_array() else {
+            continue;
+        };
+        let name = image.get(0).and_then(|n| n.as_str()).unwrap_or_default();
+        let tags = image
+            .get(1)
+            .and_then(|t| t.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|t| t.as_str().map(String::from))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        let (kept, dropped): (Vec<String>, Vec<String>) =
+            tags.into_iter().partition(|tag| groups.contains(tag));
+        let mut table = Table::new();
+        table.insert("name", value(name));
+        table.insert("tags", value(kept.into_iter().collect::<Array>()));
+        table.insert("path", value(format!("./{name}")));
+        images.push(table);
+        if dropped.is_empty().eq(&false) {
+            changes.push(format!(
+                "hub image {name}: the tags {} are not groups of hub.tags, set its tags by hand",
+                dropped.join(", ")
+            ));
+        }
+    }
+    hub.insert("images", Item::ArrayOfTables(images));
+    changes.push(String::from(
+        "hub.images are now [[hub.images]] tables with a name, tags and path",
+    ));
+}
+
+/// A free table name for the server at `host`: its first DNS label, the whole host for an
+/// ip, suffixed with a number when a section of docks.toml or another server has it.
+fn server_name(document: &DocumentMut, host: &str) -> String {
+    let label = host.split('.').next().unwrap_or_default();
+    let base = if label.is_empty() || label.chars().all(|c| c.is_ascii_digit()) {
+        host
+    } else {
+        label
+    };
+    let sections = docks();
+    let taken = |name: &str| {
+        name.eq("deploy")
+            || document.contains_key(name)
+            || sections
+                .get("properties")
+                .and_then(|properties| properties.get(name))
+                .is_some()
+    };
+    let mut name = base.to_string();
+    let mut suffix = 2;
+    while taken(name.as_str()) {
+        name = format!("{base}-{suffix}");
+        suffix += 1;
+    }
+    name
+}
+
+/// `[deploy]` with `local` and `remotes` lists of `container:tag`, the old docks layout,
+/// becomes the `containers` of `[local]` and of one table per server.
+fn deploy(document: &mut DocumentMut, changes: &mut Vec<String>) {
+    let Some(old) = document.get("deploy").and_then(Item::as_table_like) else {
+        return;
+    };
+    let names = |item: Option<&Item>| {
+        strings(item)
+            .iter()
+            .map(|c| c.split(':').next().unwrap_or_default().to_string())
+            .collect::<Vec<String>>()
+    };
+    let tagged = |item: Option<&Item>| {
+        strings(item)
+            .into_iter()
+            .filter(|c| c.contains(':'))
+            .collect::<Vec<String>>()
+    };
+    let mut dropped: Vec<(String, String)> = Vec::new();
+    dropped.extend(
+        strings(old.get("local"))
+            .into_iter()
+            .map(|c| (String::from("deploy.local"), c)),
+    );
+    if let Some(remotes) = old.get("remotes").and_then(Item::as_table_like) {
+        for (host, containers) in remotes.iter() {
+            dropped.extend(
+                strings(Some(containers))
+                    .into_iter()
+                    .map(|c| (format!("deploy.remotes.{host}"), c)),
+            );
+        }
+    }
+    for (key, container) in dropped {
+        if let Some((name, tag)) = container.split_once(':') {
+            changes.push(format!(
+                "{key}: the tag {tag} of {name} is dropped, pin it in the compose file of {name}"
+            ));
+        }
+    }
+    let local = names(old.get("local"));
+    let local_tags = tagged(old.get("local"));
+    let remotes = old
+        .get("remotes")
+        .and_then(Item::as_table_like)
+        .map(|remotes| {
+            remotes
+                .iter()
+                .map(|(host, containers)| {
+                    (
+                        host.to_string(),
+                        names(Some(containers)),
+                        tagged(Some(containers)),
+                    )
+                })
+                .collect::<Vec<(String, Vec<String>, Vec<String>)>>()
+        })
+        .unwrap_or_default();
+    if local.is_empty() && remotes.is_empty() && old.get("remotes").is_none() {
+        return;
+    }
+    let username = document
+        .get("ssh")
+        .and_then(|ssh| ssh.get("user"))
+        .and_then(Item::as_str)
+        .unwrap_or("root")
+        .to_string();
+    let port = document
+        .get("ssh")
+        .and_then(|ssh| ssh.get("port"))
+        .and_then(|port| {
+            port.as_integer()
+                .map(|p| p.to_string())
+                .or_else(|| port.as_str().map(String::from))
+        })
+        .unwrap_or_else(|| String::from("22"));
+    let assign = |document: &mut DocumentMut,
+                  name: &str,
+                  containers: Vec<String>,
+                  tags: &[String]| {
+        let table = document
+            .entry(name)
+            .or_insert_with(|| Item::Table(Table::new()));
+        let Some(table) = table.as_table_like_mut() else {
+            return;
+        };
+        let list = table
+            .entry("containers")
+            .or_insert_with(|| value(Array::new()));
+        if let Some(list) = list.as_array_mut() {
+            for container in containers {
+                if list
+                    .iter()
+                    .any(|c| c.as_str().eq(&Some(container.as_str())))
+                    .eq(&false)
+                {
+                    list.push(container);
+                }
+            }
+        }
+        if tags.is_empty() {
+            return;
+        }
+        if let Some(mut key) = table.key_mut("containers") {
+            let decor = key.leaf_decor_mut();
+            let prefix = decor
+                .prefix()
+                .and_then(|p| p.as_str())
+                .unwrap_or_default()
+                .to_string();
+            decor.set_prefix(format!(
+                "{prefix}# deployed as {} before the version 2 layout, pin these tags in the compose files\n",
+                tags.join(", ")
+            ));
+        }
+    };
+    if local.is_empty().eq(&false) {
+        assign(document, "local", local, &local_tags);
+        changes.push(String::from("deploy.local is now local.containers"));
+    }
+    for (host, containers, tags) in remotes {
+        let existing = document.iter().find_map(|(name, table)| {
+            table
+                .get("ip")
+                .and_then(Item::as_str)
+                .filter(|ip| ip.eq(&host))
+                .map(|_| name.to_string())
+        });
+        let name = existing.unwrap_or_else(|| server_name(document, host.as_str()));
+        if document.contains_key(name.as_str()).eq(&false) {
+            let mut table = Table::new();
+            table.insert("username", value(username.as_str()));
+            table.insert("ip", value(host.as_str()));
+            table.insert("port", value(port.as_str()));
+            document.insert(name.as_str(), Item::Table(table));
+        }
+        assign(document, name.as_str(), containers, &tags);
+        changes.push(format!("deploy.remotes.{host} is now the [{name}] server"));
+    }
+    if let Some(old) = document.get_mut("deploy").and_then(Item::as_table_like_mut) {
+        old.remove("local");
+        old.remove("remotes");
+        if old.is_empty() {
+            document.remove("deploy");
+        }
+    }
+}
+
+/// Rewrite the older layouts of `document` to the current one, keeping its comments and
+/// formatting elsewhere. The changes made are returned.
+pub fn upgrade(document: &mut DocumentMut) -> Vec<String> {
+    let mut changes: Vec<String> = Vec::new();
+    hub_tags(document, &mut changes);
+    hub_images(document, &mut changes);
+    deploy(document, &mut changes);
+    changes
+}
+
+/// Upgrade docks.toml at `path` and the files it includes, the changes of each file being
+/// returned. Nothing is written with `dry_run`.
+///
+/// # Errors
+///
+/// When a file cannot be read, parsed or written, or is newer than this release.
+pub fn migrate(path: &Path, dry_run: bool) -> Result<Vec<(PathBuf, Vec<String>)>, Error> {
+    let mut migrated: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for file in config::files(path)? {
+        let mut document = read_to_string(&file)?
+            .parse::<DocumentMut>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", file.display())))?;
+        let mut changes = upgrade(&mut document);
+        if file.eq(path) {
+            let current = version(&document);
+            if current.gt(&VERSION) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} uses the version {current} layout, this release only knows up to {VERSION}",
+                        file.display()
+                    ),
+                ));
+            }
+            if current.lt(&VERSION) {
+                document.insert("version", value(VERSION));
+                if let Some(table) = document
+                    .iter_mut()
+                    .find_map(|(_, item)| item.as_table_mut())
+                    .filter(|table| {
+                        table
+                            .decor()
+                            .prefix()
+                            .is_none_or(|p| p.as_str().eq(&Some("")))
+                    })
+                {
+                    table.decor_mut().set_prefix("\n");
+                }
+                changes.push(format!("version {current} is now {VERSION}"));
+            }
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        if dry_run.eq(&false) {
+            write(&file, document.to_string())?;
+        }
+        migrated.push((file, changes));
+    }
+    Ok(migrated)
+}
+
+/// Where `docks schema` writes the JSON Schemas, relative to the project root.
+pub const SCHEMA_DIR: &str = ".docks/schemas";
+
+/// The config files described by a schema.
+pub const FILES: [&str; 4] = ["docks.toml", "hub.toml", "containers.toml", "crontab.toml"];
+
+fn describe(mut schema: Json, description: &str) -> Json {
+    if let Some(object) = schema.as_object_mut() {
+        object.insert(String::from("description"), json!(description));
+    }
+    schema
+}
+
+fn string() -> Json {
+    json!({ "type": "string" })
+}
+
+fn boolean() -> Json {
+    json!({ "type": "boolean" })
+}
+
+fn count() -> Json {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn list(items: &Json) -> Json {
+    json!({ "type": "array", "items": items })
+}
+
+fn strings_list() -> Json {
+    list(&string())
+}
+
+fn pair(first: &Json, second: &Json) -> Json {
+    json!({
+        "type": "array",
+        "items": [first, second],
+        "additionalItems": false,
+        "minItems": 2
+    })
+}
+
+fn object(properties: &Json) -> Json {
+    json!({ "type": "object", "properties": properties, "additionalProperties": false })
+}
+
+fn map(values: &Json) -> Json {
+    json!({ "type": "object", "additionalProperties": values })
+}
+
+fn one_of(options: &[Json]) -> Json {
+    json!({ "anyOf": options })
+}
+
+fn caches() -> Json {
+    list(&one_of(&[
+        string(),
+        json!({
+            "type": "object",
+            "properties": { "type": string() },
+            "required": ["type"]
+        }),
+    ]))
+}
+
+fn thresholds(database: bool) -> Json {
+    let mut properties = Map::new();
+    if database {
+        properties.insert(
+            String::from("database"),
+            describe(string(), "OSV advisories imported by docks"),
+        );
+    }
+    for severity in SEVERITIES {
+        properties.insert(
+            severity.to_string(),
+            describe(count(), "most findings of this severity allowed"),
+        );
+    }
+    object(&Json::Object(properties))
+}
+
+fn image() -> Json {
+    let sbom = one_of(&[boolean(), json!({ "enum": ["buildx", "scan"] })]);
+    let provenance = one_of(&[boolean(), json!({ "enum": ["min", "max"] })]);
+    let args = map(&one_of(&[
+        string(),
+        json!({ "type": ["integer", "number", "boolean"] }),
+    ]));
+    let mut image = object(&json!({
+        "name": describe(string(), "image name, pushed as user/name:tag"),
+        "path": describe(string(), "build context, relative to the config file"),
+        "tags": describe(strings_list(), "tag groups of hub.tags"),
+        "platforms": strings_list(),
+        "dockerfile": describe(string(), "relative to path"),
+        "target": string(),
+        "build_args": args,
+        "tag_args": map(&args),
+        "labels": args,
+        "secrets": list(&json!({
+            "type": "object",
+            "properties": { "id": string(), "src": string(), "env": string() },
+            "required": ["id"],
+            "additionalProperties": false
+        })),
+        "ssh": strings_list(),
+        "cache_from": caches(),
+        "cache_to": caches(),
+        "after": describe(strings_list(), "hub images built before this one"),
+        "sbom": sbom,
+        "provenance": provenance,
+        "vulnerabilities": thresholds(false)
+    }));
+    image["required"] = json!(["name", "path"]);
+    image
+}
+
+fn hub() -> Json {
+    object(&json!({
+        "username": string(),
+        "email": string(),
+        "tags": describe(list(&pair(&string(), &strings_list())), "[group, [tags]] pairs"),
+        "platforms": strings_list(),
+        "jobs": describe(json!({ "type": "integer", "minimum": 1 }), "images built at once"),
+        "channels": map(&object(&json!({ "branches": strings_list(), "tags": strings_list() }))),
+        "retention": describe(list(&pair(&string(), &count())), "[tag pattern, count] pairs"),
+        "cache_from": caches(),
+        "cache_to": caches(),
+        "sbom": one_of(&[boolean(), json!({ "enum": ["buildx", "scan"] })]),
+        "provenance": one_of(&[boolean(), json!({ "enum": ["min", "max"] })]),
+        "vulnerabilities": thresholds(true),
+        "signing": object(&json!({
+            "key": string(),
+            "public_key": string(),
+            "verify": strings_list(),
+            "tlog": boolean()
+        })),
+        "images": list(&image())
+    }))
+}
+
+fn registry() -> Json {
+    object(&json!({ "url": string(), "username": string(), "password": string() }))
+}
+
+fn docker() -> Json {
+    object(&json!({ "username": string(), "email": string() }))
+}
+
+fn docks() -> Json {
+    let level = json!({ "enum": ["allow", "warn", "error", "deny"] });
+    let server = describe(
+        object(&json!({
+            "username": string(),
+            "ip": string(),
+            "port": string(),
+            "containers": strings_list()
+        })),
+        "a server and the containers deployed on it",
+    );
+    let mut docks = object(&json!({
+        "version": describe(json!({ "type": "integer", "minimum": 1 }), "layout version of docks.toml"),
+        "include": describe(strings_list(), "files merged into docks.toml, * matches in file names"),
+        "docker": docker(),
+        "registry": registry(),
+        "ssh": object(&json!({
+            "port": one_of(&[json!({ "type": "integer" }), string()]),
+            "user": string(),
+            "key_file": string()
+        })),
+        "hub": hub(),
+        "local": object(&json!({ "containers": strings_list() })),
+        "lint": object(&json!({
+            "latest": level,
+            "untagged": level,
+            "restart": level,
+            "bind": level,
+            "interpolation": level
+        })),
+        "secrets": object(&json!({ "store": string(), "identity": string() })),
+        "backup": object(&json!({ "dir": string(), "stop": boolean(), "image": string() })),
+        "depends_on": describe(map(&strings_list()), "containers deployed before each container")
+    }));
+    docks["additionalProperties"] = server;
+    docks
+}
+
+fn containers() -> Json {
+    let container = object(&json!({
+        "image": string(),
+        "ports": strings_list(),
+        "environment": map(&string()),
+        "volumes": strings_list(),
+        "restart": string(),
+        "networks": strings_list()
+    }));
+    let mut containers = object(&json!({
+        "networks": map(&json!({ "type": "object" }))
+    }));
+    containers["additionalProperties"] = container;
+    containers
+}
+
+fn crontab() -> Json {
+    let action = pair(
+        &json!({ "enum": ["badge", "notify"] }),
+        &json!({ "type": "object" }),
+    );
+    let mut job = object(&json!({
+        "name": string(),
+        "schedule": describe(string(), "minute hour day month weekday"),
+        "commands": strings_list(),
+        "backup": object(&json!({
+            "container": string(),
+            "volumes": strings_list(),
+            "host": string(),
+            "destination": string(),
+            "stop": boolean()
+        })),
+        "retention": list(&pair(
+            &json!({ "enum": PERIODS }),
+            &json!({ "type": "integer", "minimum": 1 })
+        )),
+        "log": string(),
+        "retries": count(),
+        "retry_delay": describe(count(), "seconds"),
+        "on_success": list(&action),
+        "on_failure": list(&action)
+    }));
+    job["required"] = json!(["name", "schedule"]);
+    let setting = one_of(&[string(), boolean(), json!({ "type": "integer" })]);
+    object(&json!({
+        "notifications": object(&json!({
+            "enabled": boolean(),
+            "email": map(&setting)
+        })),
+        "email": object(&json!({ "templates": strings_list(), "default": string() })),
+        "cron": object(&json!({ "jobs": list(&job) }))
+    }))
+}
+
+/// The JSON Schema of the config file named `file`.
+#[must_use]
+pub fn schema(file: &str) -> Option<Json> {
+    let mut schema = match file {
+        "docks.toml" => docks(),
+        "hub.toml" => object(&json!({ "docker": docker(), "registry": registry(), "hub": hub() })),
+        "containers.toml" => containers(),
+        "crontab.toml" => crontab(),
+        _ => return None,
+    };
+    schema["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    schema["title"] = json!(file);
+    Some(schema)
+}
+
+/// Write the schema of every config file in `dir`, as `<file>.schema.json`.
+///
+/// # Errors
+///
+/// When the directory or a file cannot be written.
+pub fn export(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    create_dir_all(dir)?;
+    let mut written: Vec<PathBuf> = Vec::new();
+    for file in FILES {
+        let Some(schema) = schema(file) else {
+            continue;
+        };
+        let path = dir.join(format!("{}.schema.json", file.trim_end_matches(".toml")));
+        let content =
+            serde_json::to_string_pretty(&schema).map_err(|e| Error::other(e.to_string()))?;
+        write(&path, content + "\n")?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// A `.taplo.toml` associating the config files of a project with the schemas of `dir`.
+#[must_use]
+pub fn taplo(dir: &str, includes: &[String]) -> String {
+    let mut rules = String::new();
+    for file in FILES {
+        let stem = file.trim_end_matches(".toml");
+        let mut patterns = vec![file.to_string()];
+        if file.eq("docks.toml") {
+            patterns.push(String::from("docks.*.toml"));
+            patterns.extend(
+                includes
+                    .iter()
+                    .filter(|include| FILES.contains(&include.as_str()).eq(&false))
+                    .cloned(),
+            );
+        }
+        rules.push_str(
+            format!(
+                "[[rule]]\ninclude = {patterns:?}\n\n[rule.schema]\npath = \"./{dir}/{stem}.schema.json\"\n\n"
+            )
+            .as_str(),
+        );
+    }
+    rules.trim_end().to_string() + "\n"
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) | Value::Datetime(_) => "string",
+        Value::Integer(_) => "integer",
+        Value::Float(_) => "number",
+        Value::Boolean(_) => "boolean",
+        Value::Array(_) => "array",
+        Value::Table(_) => "object",
+    }
+}
+
+fn article(kind: &str) -> &'static str {
+    if kind.starts_with(['a', 'i', 'o']) {
+        "an"
+    } else {
+        "a"
+    }
+}
+
+fn typed(value: &Value, expected: &Json) -> bool {
+    let matches = |name: &str| {
+        let actual = kind(value);
+        actual.eq(name) || (name.eq("number") && actual.eq("integer"))
+    };
+    match expected {
+        Json::String(name) => matches(name.as_str()),
+        Json::Array(names) => names.iter().filter_map(Json::as_str).any(matches),
+        _ => true,
+    }
+}
+
+fn same(value: &Value, expected: &Json) -> bool {
+    match (value, expected) {
+        (Value::String(s), Json::String(e)) => s.eq(e),
+        (Value::Integer(i), Json::Number(n)) => n.as_i64().eq(&Some(*i)),
+        (Value::Boolean(b), Json::Bool(e)) => b.eq(e),
+        _ => false,
+    }
+}
+
+/// The edit distance between two key names.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca.ne(cb));
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn check(value: &Value, schema: &Json, key: &str, errors: &mut Vec<(String, String)>) {
+    let name = if key.is_empty() { "the file" } else { key };
+    if let Some(options) = schema.get("anyOf").and_then(Json::as_array) {
+        if options.iter().all(|option| {
+            let mut found: Vec<(String, String)> = Vec::new();
+            check(value, option, key, &mut found);
+            found.is_empty().eq(&false)
+        }) {
+            errors.push((
+                key.to_string(),
+                format!("{name} has none of the allowed forms"),
+            ));
+        }
+        return;
+    }
+    if let Some(expected) = schema.get("type") {
+        if typed(value, expected).eq(&false) {
+            let expected = match expected {
+                Json::Array(names) => names
+                    .iter()
+                    .filter_map(Json::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(" or "),
+                other => other.as_str().unwrap_or_default().to_string(),
+            };
+            errors.push((
+                key.to_string(),
+                format!(
+                    "{name} is {} {}, expected {expected}",
+                    article(kind(value)),
+                    kind(value)
+                ),
+            ));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Json::as_array) {
+        if allowed.iter().any(|e| same(value, e)).eq(&false) {
+            errors.push((
+                key.to_string(),
+                format!(
+                    "{name} must be one of {}",
+                    allowed
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            ));
+        }
+    }
+    if let (Some(minimum), Value::Integer(n)) =
+        (schema.get("minimum").and_then(Json::as_i64), value)
+    {
+        if n.lt(&minimum) {
+            errors.push((
+                key.to_string(),
+                format!("{name} must be at least {minimum}"),
+            ));
+        }
+    }
+    match value {
+        Value::Array(items) => {
+            if let Some(minimum) = schema.get("minItems").and_then(Json::as_u64) {
+                if (items.len() as u64).lt(&minimum) {
+                    errors.push((key.to_string(), format!("{name} needs {minimum} items")));
+                }
+            }
+            for (at, item) in items.iter().enumerate() {
+                let item_schema = match schema.get("items") {
+                    Some(Json::Array(tuple)) => match tuple.get(at) {
+                        Some(schema) => schema,
+                        None if schema.get("additionalItems").eq(&Some(&Json::Bool(false))) => {
+                            errors.push((key.to_string(), format!("{name} has too many items")));
+                            break;
+                        }
+                        None => continue,
+                    },
+                    Some(schema) => schema,
+                    None => continue,
+                };
+                check(item, item_schema, format!("{key}[{at}]").as_str(), errors);
+            }
+        }
+        Value::Table(table) => {
+            let properties = schema.get("properties").and_then(Json::as_object);
+            for required in schema
+                .get("required")
+                .and_then(Json::as_array)
+                .into_iter()
+                .flatten()
+            {
+                let Some(required) = required.as_str() else {
+                    continue;
+                };
+                if table.contains_key(required).eq(&false) {
+                    errors.push((key.to_string(), format!("{name} needs {required}")));
+                }
+            }
+            for (field, item) in table {
+                let path = config::dotted(key, field.as_str());
+                if let Some(property) = properties.and_then(|p| p.get(field.as_str())) {
+                    check(item, property, path.as_str(), errors);
+                    continue;
+                }
+                let close = properties
+                    .into_iter()
+                    .flatten()
+                    .map(|(known, _)| known)
+                    .find(|known| distance(known, field).le(&(known.len() / 4).max(1)));
+                match (close, schema.get("additionalProperties")) {
+                    (Some(known), _) => errors.push((
+                        path.clone(),
+                        format!("{path} is not a known key, did you mean {known} ?"),
+                    )),
+                    (None, Some(Json::Bool(false))) => {
+                        errors.push((path.clone(), format!("{path} is not a known key")));
+                    }
+                    (None, Some(additional)) => check(item, additional, path.as_str(), errors),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check `value` against `schema`, the errors are returned with the key they concern.
+#[must_use]
+pub fn validate(value: &Value, schema: &Json) -> Vec<(String, String)> {
+    let mut errors: Vec<(String, String)> = Vec::new();
+    check(value, schema, "", &mut errors);
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{schema, upgrade, validate, FILES};
+    use toml::Value;
+    use toml_edit::DocumentMut;
+
+    fn upgraded(content: &str) -> (Value, Vec<String>) {
+        let mut document = content.parse::<DocumentMut>().expect("invalid toml");
+        let changes = upgrade(&mut document);
+        let value = toml::from_str::<Value>(document.to_string().as_str()).expect("invalid toml");
+        (value, changes)
+    }
+
+    fn errors(file: &str, content: &str) -> Vec<(String, String)> {
+        let value = toml::from_str::<Value>(content).expect("invalid toml");
+        validate(&value, &schema(file).expect("no schema"))
+    }
+
+    #[test]
+    fn shipped_files_validate() {
+        let shipped = [
+            include_str!("../docks.toml"),
+            include_str!("../hub.toml"),
+            include_str!("../containers.toml"),
+            include_str!("../crontab.toml"),
+        ];
+        for (file, content) in FILES.iter().zip(shipped) {
+            assert_eq!(errors(file, content), Vec::new(), "{file}");
+        }
+    }
+
+    #[test]
+    fn readme_examples_validate() {
+        let readme = include_str!("../README.md");
+        let mut examples = 0;
+        for block in readme.split("```toml\n").skip(1) {
+            let example = block.split("```").next().unwrap_or_default();
+            let file = if example.trim_start().starts_with("[[cron.jobs]]") {
+                "crontab.toml"
+            } else {
+                "docks.toml"
+            };
+            assert_eq!(errors(file, example), Vec::new(), "{example}");
+            examples += 1;
+        }
+        assert!(examples > 0);
+    }
+
+    #[test]
+    fn close_keys_are_suggested() {
+        let found = errors("docks.toml", "[regsitry]\nurl = \"\"\n");
+        assert_eq!(
+            found,
+            vec![(
+                String::from("regsitry"),
+                String::from("regsitry is not a known key, did you mean registry ?")
+            )]
+        );
+    }
+
+    #[test]
+    fn types_are_checked() {
+        let found = errors("docks.toml", "[lab]\nip = \"10.0.0.1\"\nport = 22\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "lab.port");
+    }
+
+    #[test]
+    fn deploy_becomes_local_and_server_tables() {
+        let (value, changes) = upgraded(
+            "[ssh]\nuser = \"deploy\"\nport = 2222\n\n[deploy]\nlocal = [\"web:1.0\", \"db\"]\n\n[deploy.remotes]\n\"lab.example.com\" = [\"api:2\"]\n",
+        );
+        assert_eq!(value["local"]["containers"], Value::from(vec!["web", "db"]));
+        assert_eq!(value["lab"]["ip"].as_str(), Some("lab.example.com"));
+        assert_eq!(value["lab"]["username"].as_str(), Some("deploy"));
+        assert_eq!(value["lab"]["port"].as_str(), Some("2222"));
+        assert_eq!(value["lab"]["containers"], Value::from(vec!["api"]));
+        assert!(value.get("deploy").is_none());
+        assert!(changes
+            .iter()
+            .any(|c| c.contains("the tag 1.0 of web is dropped")));
+        assert!(changes
+            .iter()
+            .any(|c| c.contains("the tag 2 of api is dropped")));
+        assert_eq!(
+            validate(&value, &schema("docks.toml").expect("no schema")),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn dropped_tags_are_kept_as_comments() {
+        let mut document = "[deploy]\nlocal = [\"web:dev\", \"db\"]\n\n[deploy.remotes]\n\"lab.example.com\" = [\"web:stable\"]\n"
+            .parse::<DocumentMut>()
+            .expect("invalid toml");
+        upgrade(&mut document);
+        let written = document.to_string();
+        assert!(written.contains(
+            "[local]\n# deployed as web:dev before the version 2 layout, pin these tags in the compose files\ncontainers = [\"web\", \"db\"]"
+        ));
+        assert!(written.contains(
+            "# deployed as web:stable before the version 2 layout, pin these tags in the compose files\ncontainers = [\"web\"]"
+        ));
+    }
+
+    #[test]
+    fn migrated_servers_never_take_a_section_or_another_server() {
+        let (value, _) = upgraded(
+            "[hub]\nusername = \"me\"\n\n[deploy.remotes]\n\"hub.example.com\" = [\"a\"]\n\"web.a.com\" = [\"b\"]\n\"web.b.com\" = [\"c\"]\n\"10.0.0.1\" = [\"d\"]\n",
+        );
+        assert_eq!(value["hub"]["username"].as_str(), Some("me"));
+        assert!(value["hub"].get("containers").is_none());
+        assert_eq!(value["hub-2"]["ip"].as_str(), Some("hub.example.com"));
+        assert_eq!(value["web"]["ip"].as_str(), Some("web.a.com"));
+        assert_eq!(value["web-2"]["ip"].as_str(), Some("web.b.com"));
+        assert_eq!(value["10.0.0.1"]["containers"], Value::from(vec!["d"]));
+    }
+
+    #[test]
+    fn migrated_servers_reuse_the_table_with_their_ip() {
+        let (value, _) = upgraded(
+            "[prod]\nusername = \"root\"\nip = \"web.a.com\"\nport = \"22\"\ncontainers = [\"a\"]\n\n[web]\nusername = \"root\"\nip = \"web.b.com\"\nport = \"22\"\ncontainers = []\n\n[deploy.remotes]\n\"web.a.com\" = [\"a\", \"b\"]\n",
+        );
+        assert_eq!(value["prod"]["containers"], Value::from(vec!["a", "b"]));
+        assert_eq!(
+            value["web"]["containers"],
+            Value::from(Vec::<String>::new())
+        );
+        assert!(value.get("web-2").is_none());
+    }
+
+    #[test]
+    fn hub_layouts_are_upgraded() {
+        let (value, changes) = upgraded(
+            "[hub.tags]\nversion = [\"stable\", \"latest\"]\n\n[hub]\nimages = [[\"zuu\", [\"version\", \"nightly\"]]]\n",
+        );
+        assert_eq!(
+            value["hub"]["tags"],
+            Value::from(vec![Value::from(vec![
+                Value::from("version"),
+                Value::from(vec!["stable", "latest"])
+            ])])
+        );
+        assert_eq!(value["hub"]["images"][0]["name"].as_str(), Some("zuu"));
+        assert_eq!(value["hub"]["images"][0]["path"].as_str(), Some("./zuu"));
+        assert_eq!(
+            value["hub"]["images"][0]["tags"],
+            Value::from(vec!["version"])
+        );
+        assert!(changes.iter().any(|c| c.contains("nightly")));
+    }
+
+    #[test]
+    fn current_layouts_are_left_alone() {
+        let content = include_str!("../docks.toml");
+        let mut document = content.parse::<DocumentMut>().expect("invalid toml");
+        assert!(upgrade(&mut document).is_empty());
+        assert_eq!(document.to_string(), content);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::hub::home;
+use std::collections::BTreeMap;
+use std::fs::create_dir_all;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{absolute, Path, PathBuf};
+use std::process::{Command, Stdio};
+use toml::{Table, Value};
+
+pub const STORE: &str = ".docks/secrets.age";
+pub const ENV_FILE: &str = ".docks.env";
+
+/// Secrets by scope, a container name or `registry`, then by variable name.
+pub type Secrets = BTreeMap<String, BTreeMap<String, String>>;
+
+/// The `[secrets]` section: the age encrypted file holding the secrets and the age key file
+/// decrypting it, a passphrase asked by age when `identity` is not set.
+#[derive(Clone, Debug)]
+pub struct Store {
+    pub path: PathBuf,
+    pub identity: Option<PathBuf>,
+}
+
+fn age(args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    let mut child = Command::new("age")
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "age not found"))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    Err(Error::new(
+        ErrorKind::PermissionDenied,
+        "age cannot decrypt or encrypt the secrets",
+    ))
+}
+
+impl Store {
+    /// Read `[secrets]` from a config living in `root`.
+    ///
+    /// # Errors
+    ///
+    /// When `root` cannot be made absolute.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Self, Error> {
+        let root = absolute(root)?;
+        let field = |name: &str| {
+            config
+                .get("secrets")
+                .and_then(|s| s.get(name))
+                .and_then(Value::as_str)
+        };
+        Ok(Self {
+            path: home(field("store").unwrap_or(STORE), root.as_path()),
+            identity: field("identity").map(|identity| home(identity, root.as_path())),
+        })
+    }
+
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    /// Create the age key file with age-keygen.
+    ///
+    /// # Errors
+    ///
+    /// When the store uses a passphrase, the key exists or age-keygen fails.
+    pub fn generate(&self) -> Result<(), Error> {
+        let Some(identity) = &self.identity else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the store uses a passphrase, set secrets.identity to use a key file",
+            ));
+        };
+        if identity.exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", identity.display()),
+            ));
+        }
+        if let Some(dir) = identity.parent() {
+            create_dir_all(dir)?;
+        }
+        let status = Command::new("age-keygen")
+            .args(["-o", identity.to_string_lossy().as_ref()])
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "age-keygen not found"))?;
+        if status.success() {
+            return Ok(());
+        }
+        Err(Error::other("age-keygen cannot create the key"))
+    }
+
+    /// Decrypt every secret, a missing store holds none.
+    ///
+    /// # Errors
+    ///
+    /// When age cannot decrypt the store or it is not valid toml.
+    pub fn load(&self) -> Result<Secrets, Error> {
+        if self.exists().eq(&false) {
+            return Ok(Secrets::new());
+        }
+        let path = self.path.to_string_lossy().to_string();
+        let mut args = vec!["--decrypt"];
+        if let Some(identity) = &self.identity {
+            args.extend(["--identity", identity.to_str().unwrap_or_default()]);
+        }
+        args.push(path.as_str());
+        let plain = String::from_utf8(age(&args, None)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let table = toml::from_str::<Table>(plain.as_str())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let mut secrets = Secrets::new();
+        for (scope, values) in table {
+            secrets.insert(
+                scope,
+                values
+                    .as_table()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                    .collect(),
+            );
+        }
+        Ok(secrets)
+    }
+
+    /// Encrypt `secrets` into the store, the clear text only goes through a pipe to age.
+    ///
+    /// # Errors
+    ///
+    /// When age is missing or fails.
+    pub fn save(&self, secrets: &Secrets) -> Result<(), Error> {
+        let mut table = Table::new();
+        for (scope, values) in secrets {
+            table.insert(
+                scope.clone(),
+                Value::Table(
+                    values
+                        .iter()
+                        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                        .collect(),
+                ),
+            );
+        }
+        let plain = toml::to_string(&table).map_err(|e| Error::other(e.to_string()))?;
+        if let Some(dir) = self.path.parent() {
+            create_dir_all(dir)?;
+        }
+        let path = self.path.to_string_lossy().to_string();
+        let mut args = vec!["--encrypt"];
+        match &self.identity {
+            Some(identity) => args.extend(["--identity", identity.to_str().unwrap_or_default()]),
+            None => args.push("--passphrase"),
+        }
+        args.extend(["--output", path.as_str()]);
+        age(&args, Some(plain.as_bytes())).map(|_| ())
+    }
+
+    /// Store `value` as `key` of `scope`.
+    ///
+    /// # Errors
+    ///
+    /// When the store cannot be decrypted or written.
+    pub fn set(&self, scope: &str, key: &str, value: &str) -> Result<(), Error> {
+        let mut secrets = self.load()?;
+        secrets
+            .entry(scope.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+        self.save(&secrets)
+    }
+
+    /// Delete `key` from `scope`, `false` when it was not stored.
+    ///
+    /// # Errors
+    ///
+    /// When the store cannot be decrypted or written.
+    pub fn remove(&self, scope: &str, key: &str) -> Result<bool, Error> {
+        let mut secrets = self.load()?;
+        let removed = secrets
+            .get_mut(scope)
+            .and_then(|values| values.remove(key))
+            .is_some();
+        secrets.retain(|_, values| values.is_empty().eq(&false));
+        if removed {
+            self.save(&secrets)?;
+        }
+        Ok(removed)
+    }
+}
+
+/// The content of an env file exporting `values`, quoted for a posix shell.
+#[must_use]
+pub fn env_file(values: &BTreeMap<String, String>) -> String {
+    values
+        .iter()
+        .map(|(key, value)| format!("{key}='{}'\n", value.replace('\'', "'\\''")))
+        .collect()
+}
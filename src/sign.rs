@@ -0,0 +1,189 @@
+use crate::git;
+use crate::hub::{self, home};
+use std::env::var_os;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::path::{absolute, Path, PathBuf};
+use std::process::{Command, Stdio};
+use toml::Value;
+
+/// The `[hub.signing]` section: the cosign key pair signing published digests and the images
+/// whose signature deploy checks.
+#[derive(Clone, Debug)]
+pub struct Signing {
+    pub key: PathBuf,
+    pub public_key: PathBuf,
+    pub verify: Vec<String>,
+    pub tlog: bool,
+}
+
+/// The repository of an image reference, without its tag or digest.
+#[must_use]
+pub fn repository(reference: &str) -> &str {
+    let reference = reference.split('@').next().unwrap_or(reference);
+    match reference.rsplit_once(':') {
+        Some((repository, tag)) if tag.contains('/').eq(&false) => repository,
+        _ => reference,
+    }
+}
+
+fn cosign(args: &[&str], quiet: bool) -> Result<bool, Error> {
+    let mut command = Command::new("cosign");
+    command.args(args);
+    if quiet {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+    }
+    command
+        .status()
+        .map(|status| status.success())
+        .map_err(|_| Error::new(ErrorKind::NotFound, "cosign not found"))
+}
+
+impl Signing {
+    /// Read `[hub.signing]` from a config living in `root`, `None` when images are not signed.
+    ///
+    /// # Errors
+    ///
+    /// When the section has no key.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Self>, Error> {
+        let Some(signing) = config.get("hub").and_then(|hub| hub.get("signing")) else {
+            return Ok(None);
+        };
+        let root = absolute(root)?;
+        let key = signing
+            .get("key")
+            .and_then(Value::as_str)
+            .map(|key| home(key, root.as_path()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "hub.signing needs the key of a cosign key pair",
+                )
+            })?;
+        let public_key = signing
+            .get("public_key")
+            .and_then(Value::as_str)
+            .map_or_else(|| key.with_extension("pub"), |p| home(p, root.as_path()));
+        let mut verify = signing
+            .get("verify")
+            .and_then(Value::as_array)
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        if verify.is_empty() {
+            let registry = hub::registry(config);
+            let username = hub::username(config);
+            verify.push(if registry.is_empty() {
+                format!("{username}/*")
+            } else {
+                format!("{registry}/{username}/*")
+            });
+        }
+        Ok(Some(Self {
+            key,
+            public_key,
+            verify,
+            tlog: signing.get("tlog").and_then(Value::as_bool).unwrap_or(true),
+        }))
+    }
+
+    /// Whether deploy must check the signature of `image`.
+    #[must_use]
+    pub fn applies(&self, image: &str) -> bool {
+        let repository = repository(image);
+        self.verify
+            .iter()
+            .any(|pattern| git::matches(pattern, repository))
+    }
+
+    /// Create the key pair, cosign asks for the password protecting the private key.
+    ///
+    /// # Errors
+    ///
+    /// When a key already exists or cosign fails.
+    pub fn generate(&self) -> Result<(), Error> {
+        if self.key.exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", self.key.display()),
+            ));
+        }
+        let prefix = self.key.with_extension("");
+        if cosign(
+            &[
+                "generate-key-pair",
+                "--output-key-prefix",
+                prefix.to_string_lossy().as_ref(),
+            ],
+            false,
+        )? {
+            return Ok(());
+        }
+        Err(Error::other("cosign cannot generate the key pair"))
+    }
+
+    /// Push a cosign signature of `digest` for the repository of `reference`, the password of
+    /// the key is read from `COSIGN_PASSWORD` since cosign runs without a terminal.
+    ///
+    /// # Errors
+    ///
+    /// When the key is encrypted and `COSIGN_PASSWORD` is unset, cosign is missing or the
+    /// signature cannot be pushed.
+    pub fn sign(&self, reference: &str, digest: &str) -> Result<(), Error> {
+        if var_os("COSIGN_PASSWORD").is_none()
+            && read_to_string(&self.key).is_ok_and(|key| key.contains("ENCRYPTED"))
+        {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "{} is encrypted, set COSIGN_PASSWORD to sign {reference}",
+                    self.key.display()
+                ),
+            ));
+        }
+        let target = if digest.is_empty() {
+            reference.to_string()
+        } else {
+            format!("{}@{digest}", repository(reference))
+        };
+        let key = self.key.to_string_lossy().to_string();
+        let mut args = vec!["sign", "--yes", "--key", key.as_str()];
+        if self.tlog.eq(&false) {
+            args.push("--tlog-upload=false");
+        }
+        args.push(target.as_str());
+        if cosign(&args, true)? {
+            return Ok(());
+        }
+        Err(Error::other(format!("cosign cannot sign {target}")))
+    }
+
+    /// Check the signature of `image` in its registry against the public key.
+    ///
+    /// # Errors
+    ///
+    /// When cosign is missing or the image is unsigned or signed with another key.
+    pub fn verify(&self, image: &str) -> Result<(), Error> {
+        let key = self.public_key.to_string_lossy().to_string();
+        let mut args = vec!["verify", "--key", key.as_str()];
+        if self.tlog.eq(&false) {
+            args.push("--insecure-ignore-tlog=true");
+        }
+        args.push(image);
+        if cosign(&args, true)? {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{image} is not signed with {}", self.public_key.display()),
+        ))
+    }
+}
@@ -0,0 +1,690 @@
+use crate::hub::home;
+use crate::sbom::{Contents, Package};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_dir, read_to_string, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// The severities of findings, from the worst.
+pub const SEVERITIES: [&str; 5] = ["critical", "high", "medium", "low", "unknown"];
+
+/// Where the imported database lives unless `hub.vulnerabilities.database` says otherwise.
+pub const DATABASE: &str = "~/.docks/vulnerabilities.json";
+
+/// The most findings of each severity an image may have, severities absent are not limited.
+pub type Thresholds = BTreeMap<String, usize>;
+
+/// One vulnerable version range of a package.
+#[derive(Clone, Debug, Default)]
+pub struct Advisory {
+    pub id: String,
+    pub ecosystem: String,
+    pub package: String,
+    pub introduced: String,
+    pub fixed: String,
+    pub last_affected: String,
+    pub severity: String,
+    pub summary: String,
+}
+
+/// An advisory affecting a package found in an image.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub id: String,
+    pub package: String,
+    pub version: String,
+    pub fixed: String,
+    pub severity: String,
+    pub summary: String,
+}
+
+/// The advisories imported from OSV files, indexed by ecosystem and package.
+#[derive(Debug, Default)]
+pub struct Database {
+    advisories: HashMap<(String, String), Vec<Advisory>>,
+}
+
+/// The configured database path of a config living in `root`.
+#[must_use]
+pub fn database(config: &toml::Value, root: &Path) -> PathBuf {
+    home(
+        config
+            .get("hub")
+            .and_then(|hub| hub.get("vulnerabilities"))
+            .and_then(|v| v.get("database"))
+            .and_then(toml::Value::as_str)
+            .unwrap_or(DATABASE),
+        root,
+    )
+}
+
+fn severity(raw: &str) -> String {
+    let raw = raw.to_lowercase();
+    match raw.as_str() {
+        "critical" | "high" | "medium" | "low" => raw,
+        "moderate" => String::from("medium"),
+        "important" => String::from("high"),
+        "negligible" | "unimportant" => String::from("low"),
+        _ => String::from("unknown"),
+    }
+}
+
+/// The CVSS v3 base score of `vector`, like `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`,
+/// or of a plain number.
+fn cvss(vector: &str) -> Option<f64> {
+    if let Ok(score) = vector.parse::<f64>() {
+        return Some(score);
+    }
+    if vector.starts_with("CVSS:3").eq(&false) {
+        return None;
+    }
+    let metrics = vector
+        .split('/')
+        .skip(1)
+        .filter_map(|metric| metric.split_once(':'))
+        .collect::<HashMap<&str, &str>>();
+    let changed = metrics.get("S").copied().eq(&Some("C"));
+    let weight = |metric: &str| -> Option<f64> {
+        Some(match (metric, *metrics.get(metric)?) {
+            ("AV", "N") => 0.85,
+            ("AV", "A") => 0.62,
+            ("AV", "L") => 0.55,
+            ("AV", "P") => 0.2,
+            ("AC", "L") => 0.77,
+            ("AC", "H") => 0.44,
+            ("PR", "N") | ("UI", "N") => 0.85,
+            ("PR", "L") if changed => 0.68,
+            ("PR", "L") | ("UI", "R") => 0.62,
+            ("PR", "H") if changed => 0.5,
+            ("PR", "H") => 0.27,
+            ("C" | "I" | "A", "H") => 0.56,
+            ("C" | "I" | "A", "L") => 0.22,
+            ("C" | "I" | "A", "N") => 0.0,
+            _ => return None,
+        })
+    };
+    let iss = 1.0 - (1.0 - weight("C")?) * (1.0 - weight("I")?) * (1.0 - weight("A")?);
+    let impact = if changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02).powi(15)
+    } else {
+        6.42 * iss
+    };
+    if impact.le(&0.0) {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * weight("AV")? * weight("AC")? * weight("PR")? * weight("UI")?;
+    let total = if changed {
+        1.08 * (impact + exploitability)
+    } else {
+        impact + exploitability
+    };
+    let scaled = (total.min(10.0) * 100_000.0).round();
+    if (scaled % 10_000.0).eq(&0.0) {
+        return Some(scaled / 100_000.0);
+    }
+    Some(((scaled / 10_000.0).floor() + 1.0) / 10.0)
+}
+
+/// The severity of a CVSS base score.
+fn rating(score: f64) -> String {
+    String::from(match score {
+        s if s.ge(&9.0) => "critical",
+        s if s.ge(&7.0) => "high",
+        s if s.ge(&4.0) => "medium",
+        s if s.gt(&0.0) => "low",
+        _ => "unknown",
+    })
+}
+
+/// The severity of the first CVSS v3 score of an OSV `severity` list.
+fn scored(severities: Option<&Value>) -> Option<String> {
+    severities?
+        .as_array()?
+        .iter()
+        .filter(|s| field(s, "type").starts_with("CVSS_V3"))
+        .find_map(|s| cvss(field(s, "score").as_str()))
+        .map(rating)
+}
+
+fn ordering(c: Option<char>) -> i64 {
+    match c {
+        None => 0,
+        Some('~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => i64::from(u32::from(c)),
+        Some(c) => i64::from(u32::from(c)) + 256,
+    }
+}
+
+fn digit(c: Option<&char>) -> bool {
+    c.is_some_and(char::is_ascii_digit)
+}
+
+fn revision(a: &[char], b: &[char]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i.lt(&a.len()) || j.lt(&b.len()) {
+        while (i.lt(&a.len()) && digit(a.get(i)).eq(&false))
+            || (j.lt(&b.len()) && digit(b.get(j)).eq(&false))
+        {
+            let (x, y) = (ordering(a.get(i).copied()), ordering(b.get(j).copied()));
+            if x.ne(&y) {
+                return x.cmp(&y);
+            }
+            i += 1;
+            j += 1;
+        }
+        while a.get(i).is_some_and(|c| c.eq(&'0')) {
+            i += 1;
+        }
+        while b.get(j).is_some_and(|c| c.eq(&'0')) {
+            j += 1;
+        }
+        let mut first = Ordering::Equal;
+        while digit(a.get(i)) && digit(b.get(j)) {
+            if first.is_eq() {
+                first = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+        if digit(a.get(i)) {
+            return Ordering::Greater;
+        }
+        if digit(b.get(j)) {
+            return Ordering::Less;
+        }
+        if first.is_ne() {
+            return first;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compare two package versions the way dpkg does, the epoch, then the upstream version, then
+/// the revision after the last `-`, which also orders apk, cargo, npm and pypi versions
+/// without pre-releases.
+#[must_use]
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let epoch = |v: &str| -> (u64, String) {
+        match v.split_once(':') {
+            Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => {
+                (epoch.parse().unwrap_or_default(), rest.to_string())
+            }
+            _ => (0, v.to_string()),
+        }
+    };
+    let chars = |v: &str| v.chars().collect::<Vec<char>>();
+    let (ea, a) = epoch(a);
+    let (eb, b) = epoch(b);
+    let (ua, ra) = a.rsplit_once('-').unwrap_or((a.as_str(), ""));
+    let (ub, rb) = b.rsplit_once('-').unwrap_or((b.as_str(), ""));
+    ea.cmp(&eb)
+        .then_with(|| revision(chars(ua).as_slice(), chars(ub).as_slice()))
+        .then_with(|| revision(chars(ra).as_slice(), chars(rb).as_slice()))
+}
+
+impl Advisory {
+    #[must_use]
+    pub fn affects(&self, version: &str) -> bool {
+        let introduced = self.introduced.is_empty()
+            || self.introduced.eq("0")
+            || compare(version, self.introduced.as_str()).is_ge();
+        let fixed = self.fixed.is_empty() || compare(version, self.fixed.as_str()).is_lt();
+        let last =
+            self.last_affected.is_empty() || compare(version, self.last_affected.as_str()).is_le();
+        introduced && fixed && last
+    }
+}
+
+fn field(value: &Value, name: &str) -> String {
+    value
+        .get(name)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The advisories of one OSV document, one per affected range.
+#[must_use]
+pub fn osv(document: &Value) -> Vec<Advisory> {
+    let id = field(document, "id");
+    let summary = document
+        .get("summary")
+        .or_else(|| document.get("details"))
+        .and_then(Value::as_str)
+        .and_then(|s| s.lines().next())
+        .unwrap_or_default()
+        .to_string();
+    let mut advisories: Vec<Advisory> = Vec::new();
+    for affected in document
+        .get("affected")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let rated = affected
+            .pointer("/ecosystem_specific/severity")
+            .or_else(|| affected.pointer("/database_specific/severity"))
+            .or_else(|| document.pointer("/database_specific/severity"))
+            .and_then(Value::as_str)
+            .map(severity)
+            .filter(|rated| rated.ne("unknown"));
+        let rated = rated
+            .or_else(|| scored(affected.get("severity")))
+            .or_else(|| scored(document.get("severity")))
+            .unwrap_or_else(|| String::from("unknown"));
+        let base = Advisory {
+            id: id.to_string(),
+            ecosystem: affected
+                .pointer("/package/ecosystem")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            package: affected
+                .pointer("/package/name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            severity: rated,
+            summary: summary.to_string(),
+            ..Advisory::default()
+        };
+        let mut ranges = 0;
+        for range in affected
+            .get("ranges")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter(|range| field(range, "type").ne("GIT"))
+        {
+            let mut introduced: Option<String> = None;
+            for event in range
+                .get("events")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                if let Some(version) = event.get("introduced").and_then(Value::as_str) {
+                    introduced = Some(version.to_string());
+                } else if let Some(version) = event.get("fixed").and_then(Value::as_str) {
+                    advisories.push(Advisory {
+                        introduced: introduced.take().unwrap_or_default(),
+                        fixed: version.to_string(),
+                        ..base.clone()
+                    });
+                    ranges += 1;
+                } else if let Some(version) = event.get("last_affected").and_then(Value::as_str) {
+                    advisories.push(Advisory {
+                        introduced: introduced.take().unwrap_or_default(),
+                        last_affected: version.to_string(),
+                        ..base.clone()
+                    });
+                    ranges += 1;
+                }
+            }
+            if let Some(introduced) = introduced {
+                advisories.push(Advisory {
+                    introduced,
+                    ..base.clone()
+                });
+                ranges += 1;
+            }
+        }
+        if ranges.eq(&0) {
+            for version in affected
+                .get("versions")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                advisories.push(Advisory {
+                    introduced: version.to_string(),
+                    last_affected: version.to_string(),
+                    ..base.clone()
+                });
+            }
+        }
+    }
+    advisories
+}
+
+fn collect(path: &Path, advisories: &mut Vec<Advisory>) -> Result<(), Error> {
+    if path.is_dir() {
+        let mut entries = read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect::<Vec<PathBuf>>();
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || entry.extension().is_some_and(|e| e.eq("json")) {
+                collect(entry.as_path(), advisories)?;
+            }
+        }
+        return Ok(());
+    }
+    let content = read_to_string(path)?;
+    let value = serde_json::from_str::<Value>(content.as_str())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+    let documents = match value {
+        Value::Array(documents) => documents,
+        document => vec![document],
+    };
+    for document in &documents {
+        if document.get("affected").is_some() {
+            advisories.extend(osv(document));
+        } else if document.get("package").is_some() {
+            advisories.push(Advisory {
+                id: field(document, "id"),
+                ecosystem: field(document, "ecosystem"),
+                package: field(document, "package"),
+                introduced: field(document, "introduced"),
+                fixed: field(document, "fixed"),
+                last_affected: field(document, "last_affected"),
+                severity: severity(field(document, "severity").as_str()),
+                summary: field(document, "summary"),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The database key of an OSV ecosystem, `debian:12` for `Debian:12`, Ubuntu's `LTS` suffix
+/// left out.
+fn key(ecosystem: &str) -> String {
+    let ecosystem = ecosystem.to_lowercase();
+    ecosystem
+        .strip_suffix(":lts")
+        .unwrap_or(ecosystem.as_str())
+        .to_string()
+}
+
+/// The OSV ecosystems of a package, most precise first: for os packages the distribution
+/// with its release, `alpine:v3.19` for alpine 3.19.1, then the distribution alone.
+fn ecosystems(package: &Package, distro: &str, release: &str) -> Vec<String> {
+    match package.kind.as_str() {
+        "deb" | "apk" => {
+            let distro = distro.to_lowercase();
+            let release = if distro.eq("alpine") && release.is_empty().eq(&false) {
+                format!(
+                    "v{}",
+                    release.split('.').take(2).collect::<Vec<&str>>().join(".")
+                )
+            } else {
+                release.to_string()
+            };
+            if release.is_empty() {
+                vec![distro]
+            } else {
+                vec![format!("{distro}:{release}"), distro]
+            }
+        }
+        "cargo" => vec![String::from("crates.io")],
+        other => vec![other.to_lowercase()],
+    }
+}
+
+impl Database {
+    /// Load the database written by [`Database::import`], a missing file is an error since a
+    /// scan without advisories would always pass.
+    ///
+    /// # Errors
+    ///
+    /// When the file is missing or not valid.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if path.is_file().eq(&false) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{} not found, import a vulnerability database first",
+                    path.display()
+                ),
+            ));
+        }
+        let mut advisories: Vec<Advisory> = Vec::new();
+        collect(path, &mut advisories)?;
+        let mut database = Self::default();
+        for advisory in advisories {
+            let key = (
+                key(advisory.ecosystem.as_str()),
+                advisory.package.to_string(),
+            );
+            database.advisories.entry(key).or_default().push(advisory);
+        }
+        Ok(database)
+    }
+
+    /// Read OSV documents from `source`, a json file or a directory of them like an unzipped
+    /// osv.dev ecosystem export, and write them to the database at `path`.
+    ///
+    /// # Errors
+    ///
+    /// When `source` cannot be read or the database cannot be written.
+    pub fn import(source: &Path, path: &Path) -> Result<usize, Error> {
+        let mut advisories: Vec<Advisory> = Vec::new();
+        collect(source, &mut advisories)?;
+        let records = advisories
+            .iter()
+            .map(|a| {
+                json!({
+                    "id": a.id,
+                    "ecosystem": a.ecosystem,
+                    "package": a.package,
+                    "introduced": a.introduced,
+                    "fixed": a.fixed,
+                    "last_affected": a.last_affected,
+                    "severity": a.severity,
+                    "summary": a.summary,
+                })
+            })
+            .collect::<Vec<Value>>();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(
+            path,
+            serde_json::to_string(&records).map_err(|e| Error::other(e.to_string()))?,
+        )?;
+        Ok(records.len())
+    }
+
+    /// The advisories affecting the packages of `contents`, the worst first.
+    #[must_use]
+    pub fn scan(&self, contents: &Contents) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+        for package in &contents.packages {
+            let ecosystems =
+                ecosystems(package, contents.distro.as_str(), contents.release.as_str());
+            let mut names = vec![package.name.as_str()];
+            if package.source.is_empty().eq(&false) && package.source.ne(&package.name) {
+                names.push(package.source.as_str());
+            }
+            for (ecosystem, name) in ecosystems
+                .iter()
+                .flat_map(|ecosystem| names.iter().map(move |name| (ecosystem, name)))
+            {
+                let key = (ecosystem.to_string(), (*name).to_string());
+                for advisory in self.advisories.get(&key).into_iter().flatten() {
+                    if advisory.affects(package.version.as_str())
+                        && findings
+                            .iter()
+                            .any(|f| f.id.eq(&advisory.id) && f.package.eq(&package.name))
+                            .eq(&false)
+                    {
+                        findings.push(Finding {
+                            id: advisory.id.to_string(),
+                            package: package.name.to_string(),
+                            version: package.version.to_string(),
+                            fixed: advisory.fixed.to_string(),
+                            severity: advisory.severity.to_string(),
+                            summary: advisory.summary.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        findings.sort_by_key(|f| SEVERITIES.iter().position(|s| s.eq(&f.severity)));
+        findings
+    }
+}
+
+/// The number of findings of each severity.
+#[must_use]
+pub fn counts(findings: &[Finding]) -> BTreeMap<String, usize> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for finding in findings {
+        *counts.entry(finding.severity.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// The findings of `reference` grouped by severity, as one block to print.
+#[must_use]
+pub fn report(reference: &str, findings: &[Finding]) -> String {
+    let counts = counts(findings);
+    let mut report = format!("\n{reference}: {} vulnerabilities", findings.len());
+    for severity in SEVERITIES {
+        if let Some(count) = counts.get(severity) {
+            report.push_str(format!(", {count} {severity}").as_str());
+        }
+    }
+    report.push('\n');
+    for finding in findings {
+        let fixed = if finding.fixed.is_empty() {
+            String::from("no fix")
+        } else {
+            format!("fixed in {}", finding.fixed)
+        };
+        report.push_str(
+            format!(
+                "  {:8}  {:20}  {} {} ({fixed})  {}\n",
+                finding.severity, finding.id, finding.package, finding.version, finding.summary
+            )
+            .as_str(),
+        );
+    }
+    report
+}
+
+/// The severities whose findings exceed `thresholds`, as messages.
+#[must_use]
+pub fn exceeded(findings: &[Finding], thresholds: &Thresholds) -> Vec<String> {
+    let counts = counts(findings);
+    thresholds
+        .iter()
+        .filter_map(|(severity, max)| {
+            let count = counts.get(severity).copied().unwrap_or_default();
+            count
+                .gt(max)
+                .then(|| format!("{count} {severity} findings, at most {max} allowed"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, cvss, osv, Database};
+    use crate::sbom::{Contents, Package};
+    use serde_json::json;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn epochs_win_over_versions() {
+        assert_eq!(compare("1:1.0", "2.0"), Ordering::Greater);
+        assert_eq!(compare("0:2.0", "2.0"), Ordering::Equal);
+        assert_eq!(compare("1:2.0-1", "2:1.0-1"), Ordering::Less);
+    }
+
+    #[test]
+    fn tildes_sort_before_anything() {
+        assert_eq!(compare("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0~rc1", "1.0~rc2"), Ordering::Less);
+        assert_eq!(compare("1.0~~", "1.0~"), Ordering::Less);
+        assert_eq!(compare("1.0~", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0", "1.0a"), Ordering::Less);
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("1.01", "1.1"), Ordering::Equal);
+        assert_eq!(compare("3.0.11-1", "3.0.9-1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn revisions_compare_after_the_upstream_version() {
+        assert_eq!(compare("1.0-1", "1.0-2"), Ordering::Less);
+        assert_eq!(compare("1.0.1-1", "1.0-9"), Ordering::Greater);
+        assert_eq!(compare("2.30-1", "2.30+dfsg-1"), Ordering::Less);
+        assert_eq!(compare("1.2-3-1", "1.2-3-0"), Ordering::Greater);
+        assert_eq!(compare("1.0", "1.0-0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn cvss_base_scores() {
+        let score = |vector: &str| cvss(vector).expect("no score");
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H") - 9.8).abs() < 1e-9);
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H") - 10.0).abs() < 1e-9);
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N") - 6.1).abs() < 1e-9);
+        assert!((score("CVSS:3.0/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:N/A:N") - 5.5).abs() < 1e-9);
+        assert!(score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N").abs() < 1e-9);
+        assert_eq!(cvss("AV:N/AC:L/Au:N/C:P/I:P/A:P"), None);
+    }
+
+    #[test]
+    fn osv_keeps_the_release_and_scores_severities() {
+        let document = json!({
+            "id": "CVE-2024-0001",
+            "summary": "overflow",
+            "severity": [{ "type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H" }],
+            "affected": [{
+                "package": { "ecosystem": "Debian:12", "name": "openssl" },
+                "ranges": [{ "type": "ECOSYSTEM", "events": [{ "introduced": "0" }, { "fixed": "3.0.11-1" }] }]
+            }]
+        });
+        let advisories = osv(&document);
+        assert_eq!(advisories.len(), 1);
+        assert_eq!(advisories[0].ecosystem, "Debian:12");
+        assert_eq!(advisories[0].severity, "critical");
+        assert_eq!(advisories[0].fixed, "3.0.11-1");
+    }
+
+    #[test]
+    fn database_keys_keep_the_release() {
+        let dir = std::env::temp_dir().join(format!("docks-vuln-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let path = dir.join("db.json");
+        let records = json!([
+            { "id": "A", "ecosystem": "Debian:12", "package": "openssl", "fixed": "3.0.11-1", "severity": "high" },
+            { "id": "B", "ecosystem": "Ubuntu:22.04:LTS", "package": "openssl", "fixed": "3.0.2-0ubuntu1.10", "severity": "low" }
+        ]);
+        std::fs::write(&path, records.to_string()).expect("database");
+        let database = Database::load(path.as_path()).expect("load");
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+        let keys = database
+            .advisories
+            .keys()
+            .map(|(e, _)| e.as_str())
+            .collect::<Vec<&str>>();
+        assert!(keys.contains(&"debian:12"));
+        assert!(keys.contains(&"ubuntu:22.04"));
+        assert!(keys.contains(&"debian").eq(&false));
+        let contents = |release: &str| Contents {
+            distro: String::from("debian"),
+            release: release.to_string(),
+            packages: vec![Package {
+                kind: String::from("deb"),
+                name: String::from("openssl"),
+                version: String::from("3.0.9-1"),
+                ..Package::default()
+            }],
+            ..Contents::default()
+        };
+        let found = database.scan(&contents("12"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "A");
+        assert!(database.scan(&contents("11")).is_empty());
+    }
+}